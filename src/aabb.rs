@@ -0,0 +1,125 @@
+use crate::ray::*;
+use glam::*;
+
+/// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3A,
+    pub max: Vec3A,
+}
+
+impl Aabb {
+    /// A box containing nothing. Growing it by anything yields the bounds of that thing.
+    pub const EMPTY: Self = Self {
+        min: Vec3A::splat(f32::INFINITY),
+        max: Vec3A::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3A>) -> Self {
+        let mut aabb = Self::EMPTY;
+        for p in points {
+            aabb.grow(p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    /// Extend the box to contain a point.
+    pub fn grow(&mut self, p: Vec3A) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3A {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3A {
+        (self.max - self.min).max(Vec3A::ZERO)
+    }
+
+    /// Surface area of the box. Empty boxes have no area.
+    pub fn surface_area(&self) -> f32 {
+        let e = self.extent();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    /// Bounds of the box after transforming it, found by transforming all eight corners.
+    pub fn transform(&self, m: &Affine3A) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        Self::from_points((0..8).map(|i| {
+            let corner = vec3a(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            m.transform_point3a(corner)
+        }))
+    }
+
+    /// Slab test using the precomputed reciprocal of the ray direction.
+    /// Returns the distance at which the ray enters the box, or `None` if it misses the box
+    /// within [t_min, t_max].
+    pub fn intersect(&self, r: &Ray, inv_dir: Vec3A, t_min: f32, t_max: f32) -> Option<f32> {
+        let t0 = (self.min - r.origin()) * inv_dir;
+        let t1 = (self.max - r.origin()) * inv_dir;
+
+        let t_near = t0.min(t1).max_element().max(t_min);
+        let t_far = t0.max(t1).min_element().min(t_max);
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transform_contains_rotated_box() {
+        let aabb = Aabb::from_points([Vec3A::splat(-1.0), Vec3A::ONE]);
+        let m = Affine3A::from_rotation_y(std::f32::consts::FRAC_PI_4)
+            * Affine3A::from_translation(vec3(2.0, 0.0, 0.0));
+        let transformed = aabb.transform(&m);
+
+        // The rotated corners of the unit box reach sqrt(2) from its center.
+        let center = m.transform_point3a(Vec3A::ZERO);
+        let half_extent = vec3a(std::f32::consts::SQRT_2, 1.0, std::f32::consts::SQRT_2);
+        let error = (transformed.min - (center - half_extent)).abs()
+            + (transformed.max - (center + half_extent)).abs();
+        assert!(error.max_element() < 1e-5);
+        assert!(Aabb::EMPTY.transform(&m).is_empty());
+    }
+
+    #[test]
+    fn intersect_returns_entry_distance() {
+        let aabb = Aabb::from_points([Vec3A::splat(-1.0), Vec3A::ONE]);
+        let r = Ray::new(vec3a(0.0, 0.0, 5.0), vec3a(0.0, 0.0, -1.0));
+        let inv_dir = r.direction().recip();
+
+        assert_eq!(aabb.intersect(&r, inv_dir, 0.0, f32::INFINITY), Some(4.0));
+        assert_eq!(aabb.intersect(&r, inv_dir, 0.0, 3.0), None);
+        // Rays starting inside the box enter it at t_min.
+        assert_eq!(aabb.intersect(&r, inv_dir, 5.0, f32::INFINITY), Some(5.0));
+
+        let miss = Ray::new(vec3a(2.0, 0.0, 5.0), vec3a(0.0, 0.0, -1.0));
+        let inv_dir = miss.direction().recip();
+        assert_eq!(aabb.intersect(&miss, inv_dir, 0.0, f32::INFINITY), None);
+    }
+}
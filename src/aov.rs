@@ -0,0 +1,195 @@
+use crate::Color;
+use glam::*;
+use std::{fmt, str::FromStr};
+
+/// Auxiliary images which can be accumulated alongside the beauty image, for compositing
+/// and denoising. Everything but the lighting is recorded at the first hit of camera rays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Albedo,
+    /// World space shading normal, facing the camera.
+    Normal,
+    /// Distance to the camera, averaged over the camera rays which hit. Infinite where
+    /// every camera ray misses.
+    Depth,
+    /// World space position.
+    Position,
+    /// Index of the object in the scene, or -1 where camera rays miss.
+    ObjectId,
+    /// Light which reached the first hit directly from the emitters or the background.
+    Direct,
+    /// Light which bounced at least twice before reaching the camera.
+    Indirect,
+    /// Emitters and background seen directly by the camera.
+    Emission,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+    ];
+
+    /// Name used on the command line, in file names and as the layer name in EXR files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object-id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+        }
+    }
+
+    /// Names of the channels of the AOV's EXR layer. Single channel AOVs only use the first
+    /// component of their pixels.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo | Aov::Direct | Aov::Indirect | Aov::Emission => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId => &["id"],
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|a| a.name()).collect();
+                format!("Unknown AOV '{}', expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+/// Values of every AOV for a single sample, or their weighted sum over many samples.
+#[derive(Debug, Clone, Copy)]
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Vec3A,
+    /// Depth of the samples which hit. Misses add nothing, so use `mean_depth` to read it.
+    pub depth: f32,
+    /// Fraction of the samples whose camera ray hit something.
+    pub coverage: f32,
+    pub position: Vec3A,
+    /// Object ids can't be averaged, so sums keep the id of the sample nearest to the
+    /// pixel center, at the squared distance `object_distance`.
+    pub object_id: f32,
+    pub object_distance: f32,
+    pub direct: Color,
+    pub indirect: Color,
+    pub emission: Color,
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self {
+            albedo: Color::ZERO,
+            normal: Vec3A::ZERO,
+            depth: 0.0,
+            coverage: 0.0,
+            position: Vec3A::ZERO,
+            object_id: -1.0,
+            object_distance: f32::INFINITY,
+            direct: Color::ZERO,
+            indirect: Color::ZERO,
+            emission: Color::ZERO,
+        }
+    }
+}
+
+impl AovSample {
+    /// Add light which reached the camera after bouncing `bounces` times.
+    pub fn add_light(&mut self, bounces: u32, radiance: Color) {
+        match bounces {
+            0 => self.emission += radiance,
+            1 => self.direct += radiance,
+            _ => self.indirect += radiance,
+        }
+    }
+
+    /// Add the values of another sample to the sum, with a weight such as the pixel
+    /// filter's. The object id is kept.
+    pub fn accumulate(&mut self, sample: &AovSample, weight: f32) {
+        self.albedo += weight * sample.albedo;
+        self.normal += weight * sample.normal;
+        self.depth += weight * sample.depth;
+        self.coverage += weight * sample.coverage;
+        self.position += weight * sample.position;
+        self.direct += weight * sample.direct;
+        self.indirect += weight * sample.indirect;
+        self.emission += weight * sample.emission;
+    }
+
+    /// Keep the object id of a sample at a squared `distance` from the pixel center if it's
+    /// nearer than the one kept so far.
+    pub fn keep_nearest_object(&mut self, sample: &AovSample, distance: f32) {
+        if distance < self.object_distance {
+            self.object_id = sample.object_id;
+            self.object_distance = distance;
+        }
+    }
+
+    /// Average of a sum whose weights add up to `weight`. Sums without any weight average
+    /// to the values of a miss.
+    pub fn average(&self, weight: f32) -> AovSample {
+        if weight <= 0.0 {
+            return AovSample::default();
+        }
+        AovSample {
+            albedo: self.albedo / weight,
+            normal: self.normal / weight,
+            depth: self.depth / weight,
+            coverage: self.coverage / weight,
+            position: self.position / weight,
+            object_id: self.object_id,
+            object_distance: self.object_distance,
+            direct: self.direct / weight,
+            indirect: self.indirect / weight,
+            emission: self.emission / weight,
+        }
+    }
+
+    /// Average depth of the samples which hit, or infinity if none of them did.
+    pub fn mean_depth(&self) -> f32 {
+        if self.coverage > 0.0 {
+            self.depth / self.coverage
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    /// Value of an AOV as a color. Single channel AOVs are stored in every component.
+    pub fn get(&self, aov: Aov) -> Color {
+        match aov {
+            Aov::Albedo => self.albedo,
+            Aov::Normal => self.normal,
+            Aov::Depth => Vec3A::splat(self.mean_depth()),
+            Aov::Position => self.position,
+            Aov::ObjectId => Vec3A::splat(self.object_id),
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+            Aov::Emission => self.emission,
+        }
+    }
+}
@@ -1,5 +1,17 @@
 use crate::{
-    camera::*, hittable_list::HittableList, imgui_dock, input::*, mesh::Mesh, renderer::Renderer,
+    camera::*,
+    denoise::Denoiser,
+    display::ToneMapper,
+    environment::{Background, Environment},
+    filter::Filter,
+    hittable_list::HittableList,
+    imgui_dock,
+    input::*,
+    light::{Light, LightShape},
+    material::Lambertian,
+    renderer::{RenderMode, Renderer},
+    scene::Scene,
+    sky::Sky,
     Color,
 };
 use bytemuck::{Pod, Zeroable};
@@ -61,7 +73,7 @@ const TEX_WIDTH: usize = 800;
 const TEX_HEIGHT: usize = (TEX_WIDTH as f32 / ASPECT_RATIO) as usize;
 
 /// Returns a scene of 'n' random triangles.
-fn random_triangles(n: i32) -> Vec<crate::Triangle> {
+fn random_triangles(n: i32) -> Vec<crate::triangle::Triangle> {
     // let mut world = HittableList::new();
     let mut list = Vec::new();
     let mut rng = rand_xoshiro::Xoroshiro128PlusPlus::from_entropy();
@@ -92,7 +104,12 @@ fn random_triangles(n: i32) -> Vec<crate::Triangle> {
             rng.gen_range(0.0..1.0),
             rng.gen_range(0.0..1.0),
         );
-        list.push(crate::Triangle::new(v0, v1, v2, albedo, Color::ZERO));
+        list.push(crate::triangle::Triangle::new(
+            v0,
+            v1,
+            v2,
+            Arc::new(Lambertian::new(albedo)),
+        ));
     }
 
     list
@@ -119,9 +136,9 @@ pub struct Application {
 }
 
 impl Application {
-    /// Initializes the application.
+    /// Initializes the application and loads the scene description at `scene_path`.
     /// TODO: Refactor using the builder design patter.
-    pub fn init(title: &str, width: u32, height: u32) -> Self {
+    pub fn init(title: &str, width: u32, height: u32, scene_path: &str) -> Self {
         // Load the Vulkan library.
         let library = VulkanLibrary::new().unwrap();
 
@@ -421,7 +438,7 @@ impl Application {
         .expect("Failed to initialize renderer");
 
         // Initialize the renderer.
-        let renderer = Renderer::new(TEX_WIDTH, TEX_HEIGHT);
+        let mut renderer = Renderer::new(TEX_WIDTH, TEX_HEIGHT);
 
         // Create the initial texture.
         let mut builder = AutoCommandBufferBuilder::primary(
@@ -485,42 +502,13 @@ impl Application {
         let final_texture_id = textures.insert((texture_image_view, sampler));
 
         // Init the scene
-        let mut scene = HittableList::new();
-        // let mut random_triangles = random_triangles(100);
-        // for tri in random_triangles {
-        //     scene.add(tri);
-        // }
-        // scene.add(Mesh::from_triangles(random_triangles));
-        // let mut plane = Mesh::from_gltf("assets/plane.glb").unwrap();
-        // plane.transformation(Vec3A::ONE * 5.0, Quat::from_rotation_z(0.1), Vec3A::ZERO);
-        // scene.add(plane);
-        // let triangle = crate::Triangle::new(
-        //     vec3a(2.5, 0.0, 0.0),
-        //     vec3a(1.5, 2.1213203435596457, 0.0),
-        //     vec3a(-1.5, 1.5, 0.0),
-        // );
-        // scene.add(triangle);
-        // let mut icosphere = Mesh::from_gltf("assets/icosphere.glb").unwrap();
-        // icosphere.translation(vec3a(0.0, 1.0, 0.0));
-        // scene.add(icosphere);
-        // let mut cube = Mesh::from_gltf("assets/cube.glb").unwrap();
-        // cube.transformation(
-        //     Vec3A::ONE * 2.0,
-        //     Quat::from_rotation_y(1.0),
-        //     vec3a(-1.0, 1.0, -1.0),
-        // );
-        // scene.add(cube);
-        let cornell = Mesh::from_gltf("assets/cornell_light.glb").unwrap();
-        println!("cube tri count: {}", cornell.num_triangles());
-        scene.add(cornell);
-        // let mut bunny = Mesh::from_gltf("assets/bunny.glb").unwrap();
-        // bunny.transformation(
-        //     vec3a(0.25, 0.25, 0.25),
-        //     Quat::IDENTITY,
-        //     vec3a(0.3, 0.8, 0.5),
-        // );
-        // scene.add(bunny);
-        let camera = Camera::new(45.0, 0.1, 100.0, TEX_WIDTH as u32, TEX_HEIGHT as u32, false);
+        let Scene {
+            world: scene,
+            camera,
+            settings,
+        } = Scene::load(scene_path, TEX_WIDTH as u32, TEX_HEIGHT as u32)
+            .unwrap_or_else(|e| panic!("Failed to load scene: {}", e));
+        renderer.set_settings(settings);
 
         Application {
             event_loop,
@@ -545,6 +533,7 @@ impl Application {
 
     fn render_ui(
         renderer: &mut Renderer,
+        scene: &mut HittableList,
         ui: &imgui::Ui,
         texture_id: Option<imgui::TextureId>,
         since_last_redraw: Duration,
@@ -610,6 +599,19 @@ impl Application {
                                 .uv0([0.0, 1.0])
                                 .uv1([1.0, 0.0])
                                 .build(ui);
+
+                            // Clicking the image focuses on the surface under the cursor.
+                            if ui.is_item_clicked() {
+                                let [min_x, min_y] = ui.item_rect_min();
+                                let [mouse_x, mouse_y] = ui.io().mouse_pos;
+                                let x = ((mouse_x - min_x) as usize).min(TEX_WIDTH - 1);
+                                let row = ((mouse_y - min_y) as usize).min(TEX_HEIGHT - 1);
+                                // The renderer's first row is the bottom of the image.
+                                let y = TEX_HEIGHT - 1 - row;
+                                if camera.focus_on_pixel(&*scene, x, y) {
+                                    renderer.reset_accumulation_data();
+                                }
+                            }
                         }
                     });
                 ui.window("Scene")
@@ -626,16 +628,302 @@ impl Application {
                             // Since camera was moved we need reset the accumulation data.
                             renderer.reset_accumulation_data();
                         }
+
+                        // Depth of field. The f-stop is another way of setting the aperture.
+                        let mut radius = camera.get_aperture_radius();
+                        let mut changed = ui
+                            .slider_config("Aperture radius", 0.0, 1.0)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .build(&mut radius);
+                        if changed {
+                            camera.set_aperture_radius(radius);
+                        }
+                        let mut f_stop = camera.get_f_stop().min(64.0);
+                        if ui
+                            .slider_config("F-stop", 0.5, 64.0)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .build(&mut f_stop)
+                        {
+                            camera.set_f_stop(f_stop);
+                            changed = true;
+                        }
+                        let mut focus_distance = camera.get_focus_distance();
+                        if imgui::Drag::new("Focus distance")
+                            .speed(0.05)
+                            .range(0.01, 1000.0)
+                            .build(ui, &mut focus_distance)
+                        {
+                            camera.set_focus_distance(focus_distance);
+                            changed = true;
+                        }
+                        let mut blades = camera.get_aperture_blades();
+                        if ui.slider("Aperture blades", 0, 12, &mut blades) {
+                            camera.set_aperture_blades(blades);
+                            changed = true;
+                        }
+                        if changed {
+                            renderer.reset_accumulation_data();
+                        }
+                        ui.text_disabled("Click the viewport to focus on a surface.");
+
+                        // Lights are edited as copies, which replace the scene's lights
+                        // when they change.
+                        ui.separator();
+                        ui.text("Lights");
+                        let mut removed = None;
+                        for (i, light) in scene.scene_lights().to_vec().into_iter().enumerate() {
+                            let _id = ui.push_id_usize(i);
+                            let label = format!("{} light {}", light.shape.name(), i);
+                            if !ui.collapsing_header(label, imgui::TreeNodeFlags::empty()) {
+                                continue;
+                            }
+                            let mut light = light;
+                            if Self::light_ui(ui, &mut light) {
+                                scene.set_light(i, light);
+                                renderer.reset_accumulation_data();
+                            }
+                            if ui.button("Remove") {
+                                removed = Some(i);
+                            }
+                        }
+                        if let Some(i) = removed {
+                            scene.remove_light(i);
+                            renderer.reset_accumulation_data();
+                        }
+
+                        // New lights are placed in front of the camera.
+                        let names = ["Point", "Spot", "Directional", "Sphere", "Rect"];
+                        let mut added = None;
+                        ui.text("Add");
+                        for (index, name) in names.iter().enumerate() {
+                            ui.same_line();
+                            if ui.button(name) {
+                                added = Some(index);
+                            }
+                        }
+                        if let Some(index) = added {
+                            let forward = *camera.get_forward_direction();
+                            let position = *camera.get_position() + 2.0 * forward;
+                            let shape = match index {
+                                0 => LightShape::Point { position },
+                                1 => LightShape::Spot {
+                                    position,
+                                    direction: forward,
+                                    inner_angle: 20f32.to_radians(),
+                                    outer_angle: 30f32.to_radians(),
+                                },
+                                2 => LightShape::Directional { direction: forward },
+                                3 => LightShape::Sphere {
+                                    center: position,
+                                    radius: 0.1,
+                                },
+                                _ => LightShape::Rect {
+                                    corner: position - vec3a(0.25, 0.0, 0.25),
+                                    edge_u: vec3a(0.5, 0.0, 0.0),
+                                    edge_v: vec3a(0.0, 0.0, 0.5),
+                                },
+                            };
+                            let power = if index == 2 { 1.0 } else { 100.0 };
+                            scene.add_light(Light {
+                                shape,
+                                color: Color::ONE,
+                                power,
+                            });
+                            renderer.reset_accumulation_data();
+                        }
                     });
                 ui.window("Settings")
-                    .size([300.0, 110.0], imgui::Condition::FirstUseEver)
+                    .size([300.0, 240.0], imgui::Condition::FirstUseEver)
                     .build(|| {
                         ui.text(format!("Last render: {}ms", since_last_redraw.as_millis()));
                         ui.text(format!("Frame index: {}", renderer.get_frame_index()));
+
+                        let mut settings = *renderer.get_settings();
+                        let mut changed =
+                            ui.slider("Max bounces", 0, 64, &mut settings.max_bounces);
+                        // Lowering the maximum also lowers the Russian roulette depth.
+                        settings.min_bounces = settings.min_bounces.min(settings.max_bounces);
+                        changed |= ui.slider(
+                            "Russian roulette depth",
+                            0,
+                            settings.max_bounces,
+                            &mut settings.min_bounces,
+                        );
+                        changed |= ui.checkbox(
+                            "Next event estimation",
+                            &mut settings.next_event_estimation,
+                        );
+                        let names = RenderMode::ALL.map(|m| m.name());
+                        let mut index = RenderMode::ALL
+                            .iter()
+                            .position(|&m| m == settings.render_mode)
+                            .unwrap_or(0);
+                        if ui.combo_simple_string("Render mode", &mut index, &names) {
+                            settings.render_mode = RenderMode::ALL[index];
+                            changed = true;
+                        }
+                        let names = Filter::ALL.map(|f| f.name());
+                        let mut index = Filter::ALL
+                            .iter()
+                            .position(|&f| f == settings.filter)
+                            .unwrap_or(0);
+                        if ui.combo_simple_string("Pixel filter", &mut index, &names) {
+                            settings.filter = Filter::ALL[index];
+                            changed = true;
+                        }
+                        if settings.render_mode.is_heat_map() {
+                            changed |= ui
+                                .slider_config("Heat map max", 1.0, 1000.0)
+                                .flags(imgui::SliderFlags::LOGARITHMIC)
+                                .build(&mut settings.heat_map_max);
+                        }
+                        if changed {
+                            renderer.set_settings(settings);
+                        }
+
+                        // The sky is rebuilt whenever one of its parameters changes.
+                        let environment = scene.environment().clone();
+                        if let Background::Sky(sky) = &environment.background {
+                            ui.separator();
+                            let mut params = *sky.params();
+                            let mut changed =
+                                ui.slider("Sun elevation", 0.0, 90.0, &mut params.sun_elevation);
+                            changed |=
+                                ui.slider("Sun azimuth", -180.0, 180.0, &mut params.sun_azimuth);
+                            changed |= ui.slider("Turbidity", 2.0, 10.0, &mut params.turbidity);
+                            changed |= ui.slider("Sun size", 0.1, 10.0, &mut params.sun_size);
+                            if changed {
+                                scene.set_environment(Environment {
+                                    background: Background::Sky(Sky::new(params)),
+                                    ..environment.clone()
+                                });
+                                renderer.reset_accumulation_data();
+                            }
+                        }
+
+                        // Changing the display transform keeps the accumulated samples.
+                        ui.separator();
+                        let mut display = *renderer.get_display_transform();
+                        let mut changed =
+                            ui.slider("Exposure (EV)", -10.0, 10.0, &mut display.exposure);
+                        let names = ToneMapper::ALL.map(|t| t.name());
+                        let mut index = ToneMapper::ALL
+                            .iter()
+                            .position(|&t| t == display.tone_mapper)
+                            .unwrap_or(0);
+                        if ui.combo_simple_string("Tone mapping", &mut index, &names) {
+                            display.tone_mapper = ToneMapper::ALL[index];
+                            changed = true;
+                        }
+                        if display.tone_mapper == ToneMapper::ExtendedReinhard {
+                            changed |=
+                                ui.slider("White point", 0.1, 64.0, &mut display.white_point);
+                        }
+                        if changed {
+                            renderer.set_display_transform(display);
+                        }
+
+                        // The denoiser needs the AOVs, so turning it on for the first time
+                        // restarts the accumulation.
+                        let mut denoise = renderer.get_denoiser().is_some();
+                        if ui.checkbox("Denoise", &mut denoise) {
+                            renderer.set_denoiser(denoise.then(Denoiser::default));
+                        }
                     });
             });
     }
 
+    /// Widgets for editing a light. Returns whether the light changed.
+    fn light_ui(ui: &imgui::Ui, light: &mut Light) -> bool {
+        let mut changed = false;
+        match &mut light.shape {
+            LightShape::Point { position } => {
+                changed |= Self::drag_vector(ui, "Position", position);
+            }
+            LightShape::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+            } => {
+                changed |= Self::drag_vector(ui, "Position", position);
+                changed |= Self::drag_direction(ui, direction);
+                let mut inner = inner_angle.to_degrees();
+                if ui.slider("Inner angle", 0.0, 90.0, &mut inner) {
+                    *inner_angle = inner.to_radians().min(*outer_angle);
+                    changed = true;
+                }
+                let mut outer = outer_angle.to_degrees();
+                if ui.slider("Outer angle", 0.0, 90.0, &mut outer) {
+                    *outer_angle = outer.to_radians().max(*inner_angle);
+                    changed = true;
+                }
+            }
+            LightShape::Directional { direction } => {
+                changed |= Self::drag_direction(ui, direction);
+            }
+            LightShape::Sphere { center, radius } => {
+                changed |= Self::drag_vector(ui, "Center", center);
+                changed |= imgui::Drag::new("Radius")
+                    .speed(0.01)
+                    .range(0.001, 100.0)
+                    .build(ui, radius);
+            }
+            LightShape::Rect {
+                corner,
+                edge_u,
+                edge_v,
+            } => {
+                changed |= Self::drag_vector(ui, "Corner", corner);
+                // Edges which would make the rect degenerate are ignored.
+                let (mut u, mut v) = (*edge_u, *edge_v);
+                let dragged = Self::drag_vector(ui, "Edge u", &mut u)
+                    | Self::drag_vector(ui, "Edge v", &mut v);
+                if dragged && u.cross(v).length_squared() > 0.0 {
+                    *edge_u = u;
+                    *edge_v = v;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut color = light.color.to_array();
+        if ui.color_edit3("Color", &mut color) {
+            light.color = Color::from_array(color);
+            changed = true;
+        }
+        let label = match light.shape {
+            LightShape::Directional { .. } => "Irradiance (W/m^2)",
+            _ => "Power (W)",
+        };
+        changed |= ui
+            .slider_config(label, 0.0, 10000.0)
+            .flags(imgui::SliderFlags::LOGARITHMIC)
+            .build(&mut light.power);
+        changed
+    }
+
+    fn drag_vector(ui: &imgui::Ui, label: &str, vector: &mut Vec3A) -> bool {
+        let mut array = vector.to_array();
+        let changed = imgui::Drag::new(label)
+            .speed(0.05)
+            .build_array(ui, &mut array);
+        if changed {
+            *vector = Vec3A::from_array(array);
+        }
+        changed
+    }
+
+    /// Drag a unit direction, which stays unchanged while it would be zero.
+    fn drag_direction(ui: &imgui::Ui, direction: &mut Vec3A) -> bool {
+        let mut dragged = *direction;
+        if Self::drag_vector(ui, "Direction", &mut dragged) && dragged.length_squared() > 0.0 {
+            *direction = dragged.normalize();
+            return true;
+        }
+        false
+    }
+
     pub fn main_loop(self) {
         let Self {
             event_loop,
@@ -643,7 +931,7 @@ impl Application {
             queue,
             surface,
             memory_allocator,
-            scene,
+            mut scene,
             mut camera,
             mut renderer,
             mut swapchain,
@@ -766,6 +1054,7 @@ impl Application {
                     let ui = imgui.frame();
                     Application::render_ui(
                         &mut renderer,
+                        &mut scene,
                         &ui,
                         Some(final_texture_id),
                         since_last_redraw,
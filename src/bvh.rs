@@ -1,295 +1,668 @@
-use crate::hittable::*;
+use crate::aabb::Aabb;
 use crate::ray::*;
-use crate::triangle::*;
 use glam::*;
 
+/// Bounding volume hierarchy over a list of primitives.
+///
+/// The BVH doesn't own the primitives. It's built from their bounds and stores indices into
+/// the list, so the owner passes a function for intersecting a primitive when traversing.
 #[derive(Debug)]
-#[allow(dead_code)]
 pub struct Bvh {
     root_index: usize,
     nodes: Vec<BvhNode>,
     nodes_used: usize,
-    triangles: Vec<Triangle>, // TODO: Figure out how to have Bvh not own the triangle data itself.
-    triangle_indices: Vec<usize>,
-    num_triangles: usize,
+    prim_indices: Vec<usize>,
+    config: BvhConfig,
 }
 
-#[derive(Debug, Clone, Copy)]
-struct BvhNode {
-    aabb_min: Vec3A,
-    aabb_max: Vec3A,
-    left_child: usize, // Right child is always left_child + 1
-    first_prim: usize,
-    prim_count: usize, // If non-zero, is a leaf. Must zero for interior nodes.
-}
+/// Maximum depth of the tree, which bounds the size of the traversal stack.
+const MAX_DEPTH: usize = 64;
 
-impl BvhNode {
-    fn is_leaf(&self) -> bool {
-        self.prim_count > 0
-    }
+/// How the builder chooses where to split a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitMethod {
+    /// Split at the spatial midpoint of the node's longest axis.
+    Midpoint,
+    /// Split where the binned surface area heuristic estimates the cheapest traversal.
+    Sah,
 }
 
-fn intersect_aabb(r: &Ray, t_min: f32, t_max: f32, b_min: Vec3A, b_max: Vec3A) -> bool {
-    let mut t_min = t_min;
-    let mut t_max = t_max;
-    // Comptue t-intervals along the x-axis
-    let mut inv_d = 1.0 / r.direction().x;
-    let mut t0 = (b_min.x - r.origin().x) * inv_d;
-    let mut t1 = (b_max.x - r.origin().x) * inv_d;
-
-    if inv_d < 0.0 {
-        let tmp = t0;
-        t0 = t1;
-        t1 = tmp;
-    }
-
-    t_min = if t0 > t_min { t0 } else { t_min };
-    t_max = if t1 < t_max { t1 } else { t_max };
+/// Parameters for building a BVH.
+#[derive(Debug, Clone, Copy)]
+pub struct BvhConfig {
+    pub split_method: SplitMethod,
+    /// Nodes with this many primitives or fewer always become leaves.
+    pub leaf_size: usize,
+    /// The SAH builder splits nodes with more primitives than this, even if a leaf looks cheaper.
+    pub max_leaf_size: usize,
+    /// Number of bins per axis considered by the SAH builder.
+    pub num_bins: usize,
+    /// Cost of visiting an interior node, relative to `intersection_cost`.
+    pub traversal_cost: f32,
+    /// Cost of intersecting a single primitive.
+    pub intersection_cost: f32,
+}
 
-    if t_max <= t_min {
-        return false;
+impl Default for BvhConfig {
+    fn default() -> Self {
+        Self {
+            split_method: SplitMethod::Sah,
+            leaf_size: 2,
+            max_leaf_size: 16,
+            num_bins: 16,
+            traversal_cost: 1.0,
+            intersection_cost: 1.0,
+        }
     }
+}
 
-    // Comptue t-intervals along the y-axis
-    inv_d = 1.0 / r.direction().y;
-    t0 = (b_min.y - r.origin().y) * inv_d;
-    t1 = (b_max.y - r.origin().y) * inv_d;
+/// Measures of the quality of a BVH.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    /// Length of the longest path from the root to a leaf. A lone root has a depth of 0.
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    /// Expected cost of tracing a random ray which hits the root, according to the SAH.
+    pub sah_cost: f32,
+}
 
-    if inv_d < 0.0 {
-        let tmp = t0;
-        t0 = t1;
-        t1 = tmp;
+impl std::fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves, depth {}, largest leaf {}, SAH cost {:.2}",
+            self.node_count, self.leaf_count, self.max_depth, self.max_leaf_size, self.sah_cost
+        )
     }
+}
 
-    t_min = if t0 > t_min { t0 } else { t_min };
-    t_max = if t1 < t_max { t1 } else { t_max };
-
-    if t_max <= t_min {
-        return false;
-    }
+/// Bounds and primitive count of a bin used by the SAH builder.
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
 
-    // Comptue t-intervals along the z-axis
-    inv_d = 1.0 / r.direction().z;
-    t0 = (b_min.z - r.origin().z) * inv_d;
-    t1 = (b_max.z - r.origin().z) * inv_d;
+impl Bin {
+    const EMPTY: Self = Self {
+        bounds: Aabb::EMPTY,
+        count: 0,
+    };
 
-    if inv_d < 0.0 {
-        let tmp = t0;
-        t0 = t1;
-        t1 = tmp;
+    fn grow(&mut self, other: &Bin) {
+        self.bounds = self.bounds.union(&other.bounds);
+        self.count += other.count;
     }
+}
 
-    t_min = if t0 > t_min { t0 } else { t_min };
-    t_max = if t1 < t_max { t1 } else { t_max };
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    left_child: usize, // Right child is always left_child + 1
+    first_prim: usize,
+    prim_count: usize, // If non-zero, is a leaf. Must zero for interior nodes.
+}
 
-    if t_max <= t_min {
-        return false;
+impl BvhNode {
+    fn is_leaf(&self) -> bool {
+        self.prim_count > 0
     }
-
-    return true;
 }
 
 impl Bvh {
-    pub fn new(triangles: &Vec<Triangle>) -> Self {
-        let num_triangles = triangles.len();
-        // Populate the triangle index vector.
-        let mut triangle_indices = Vec::with_capacity(num_triangles);
-        let mut tris = Vec::with_capacity(num_triangles);
-        for i in 0..num_triangles {
-            triangle_indices.push(i);
-            // Copying triangle data.
-            let verts = &triangles[i].vertices();
-            tris.push(Triangle::new(
-                verts[0],
-                verts[1],
-                verts[2],
-                triangles[i].albedo(),
-                triangles[i].emissive(),
-            ));
-        }
+    /// Build a BVH over primitives with the given bounds, using the default configuration.
+    pub fn new(bounds: &[Aabb]) -> Self {
+        Self::with_config(bounds, BvhConfig::default())
+    }
+
+    pub fn with_config(bounds: &[Aabb], config: BvhConfig) -> Self {
+        let num_prims = bounds.len();
+        // Populate the primitive index vector.
+        let prim_indices = (0..num_prims).collect();
 
         // Initialize the BvhNode pool.
-        // Upper limit for a BVH with N triangles is 2N - 1
+        // Upper limit for a BVH with N primitives is 2N - 1
         let mut nodes = vec![
             BvhNode {
-                aabb_min: vec3a(f32::INFINITY, f32::INFINITY, f32::INFINITY),
-                aabb_max: vec3a(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+                bounds: Aabb::EMPTY,
                 left_child: 0,
                 first_prim: 0,
                 prim_count: 0,
             };
-            2 * num_triangles - 1
+            (2 * num_prims).max(2) - 1
         ];
 
         let root_index = 0;
         let nodes_used = 1;
-        nodes[root_index].prim_count = num_triangles; // Root contains all primites.
+        nodes[root_index].prim_count = num_prims; // Root contains all primites.
 
         let mut bvh = Self {
             root_index,
             nodes,
             nodes_used,
-            triangle_indices,
-            triangles: tris,
-            num_triangles,
+            prim_indices,
+            config,
         };
 
-        bvh.update_node_bounds(bvh.root_index);
+        bvh.update_node_bounds(bvh.root_index, bounds);
 
         // Recursively construct the bvh.
-        bvh.subdivide(bvh.root_index);
+        bvh.subdivide(bvh.root_index, 0, bounds, &config);
 
         bvh
     }
 
-    /// Recursively construct a BVH.
-    fn subdivide(&mut self, node_index: usize) {
-        let left_child;
-        let left_count;
-        let right_child;
-        let mut i;
-        let node_first_prim;
-        let node_prim_count;
-        // TODO: I don't need this block if I just access via node_index instead of getting a &mut to the node.
-        {
-            let node = &mut self.nodes[node_index];
-            if node.prim_count <= 2 {
-                // Reached parent of leaf nodes. Terminate recursion.
-                return;
-            }
+    /// Bounds of every primitive in the BVH.
+    pub fn bounds(&self) -> Aabb {
+        self.nodes[self.root_index].bounds
+    }
 
-            // Determine split axis and position.
-            let extent = node.aabb_max - node.aabb_min;
-            let mut axis = 0;
-            if extent.y > extent.x {
-                axis = 1;
-            }
-            if extent.z > extent[axis] {
-                axis = 2;
-            }
+    /// Recursively construct a BVH.
+    fn subdivide(&mut self, node_index: usize, depth: usize, bounds: &[Aabb], config: &BvhConfig) {
+        let node = self.nodes[node_index];
+        if node.prim_count <= config.leaf_size.max(1) || depth + 1 >= MAX_DEPTH {
+            // Reached a leaf. Terminate recursion.
+            return;
+        }
 
-            // Split position is the middle of the extent along the split axis.
-            let split_pos = node.aabb_min[axis] + extent[axis] * 0.5;
-
-            // In-place partition.
-            // Partition triangles based on the split.
-            i = node.first_prim;
-            let mut j = i + node.prim_count - 1;
-            while i <= j {
-                if self.triangles[self.triangle_indices[i]].centroid()[axis] < split_pos {
-                    i += 1;
-                } else {
-                    // Swap with triangle at end.
-                    self.triangle_indices.swap(i, j);
-                    j -= 1;
+        // Determine which side of the split each primitive's centroid falls on.
+        let goes_left: Box<dyn Fn(Vec3A) -> bool> = match config.split_method {
+            SplitMethod::Midpoint => {
+                // Determine split axis and position.
+                let extent = node.bounds.extent();
+                let mut axis = 0;
+                if extent.y > extent.x {
+                    axis = 1;
+                }
+                if extent.z > extent[axis] {
+                    axis = 2;
                 }
+
+                // Split position is the middle of the extent along the split axis.
+                let split_pos = node.bounds.min[axis] + extent[axis] * 0.5;
+                Box::new(move |centroid| centroid[axis] < split_pos)
             }
+            SplitMethod::Sah => match self.find_sah_split(&node, bounds, config) {
+                Some(goes_left) => goes_left,
+                None => return, // Splitting is more expensive than intersecting every primitive.
+            },
+        };
 
-            // Abort split if one of the sides is empty.
-            left_count = i - node.first_prim;
-            if left_count == 0 || left_count == node.prim_count {
-                return;
+        // In-place partition.
+        // Partition primitives based on the split.
+        let mut i = node.first_prim;
+        let mut j = i + node.prim_count - 1;
+        while i <= j {
+            if goes_left(bounds[self.prim_indices[i]].centroid()) {
+                i += 1;
+            } else {
+                // Swap with primitive at end.
+                self.prim_indices.swap(i, j);
+                if j == 0 {
+                    break;
+                }
+                j -= 1;
             }
+        }
 
-            // Create child nodes.
-            left_child = self.nodes_used;
-            self.nodes_used += 1;
-            right_child = self.nodes_used;
-            self.nodes_used += 1;
+        // Abort split if one of the sides is empty.
+        let left_count = i - node.first_prim;
+        if left_count == 0 || left_count == node.prim_count {
+            return;
+        }
 
-            node.left_child = left_child;
-            node_prim_count = node.prim_count;
-            node.prim_count = 0; // Set to 0 since it's not a leaf.
+        // Create child nodes.
+        let left_child = self.nodes_used;
+        self.nodes_used += 1;
+        let right_child = self.nodes_used;
+        self.nodes_used += 1;
 
-            node_first_prim = node.first_prim;
-        }
+        self.nodes[node_index].left_child = left_child;
+        self.nodes[node_index].prim_count = 0; // Set to 0 since it's not a leaf.
 
-        self.nodes[left_child].first_prim = node_first_prim;
+        self.nodes[left_child].first_prim = node.first_prim;
         self.nodes[left_child].prim_count = left_count;
 
         self.nodes[right_child].first_prim = i;
-        self.nodes[right_child].prim_count = node_prim_count - left_count;
+        self.nodes[right_child].prim_count = node.prim_count - left_count;
 
-        self.update_node_bounds(left_child);
-        self.update_node_bounds(right_child);
+        self.update_node_bounds(left_child, bounds);
+        self.update_node_bounds(right_child, bounds);
 
         // Recurse.
-        self.subdivide(left_child);
-        self.subdivide(right_child);
+        self.subdivide(left_child, depth + 1, bounds, config);
+        self.subdivide(right_child, depth + 1, bounds, config);
+    }
+
+    /// Find the cheapest split of a node according to the surface area heuristic.
+    /// Primitives are binned by centroid along each axis and only the planes between
+    /// bins are considered. Returns `None` if the node should be kept as a leaf.
+    fn find_sah_split(
+        &self,
+        node: &BvhNode,
+        bounds: &[Aabb],
+        config: &BvhConfig,
+    ) -> Option<Box<dyn Fn(Vec3A) -> bool>> {
+        let prims = &self.prim_indices[node.first_prim..node.first_prim + node.prim_count];
+        let num_bins = config.num_bins.max(2);
+
+        // Bins are distributed over the bounds of the centroids, not of the primitives.
+        let centroid_bounds = Aabb::from_points(prims.iter().map(|&prim| bounds[prim].centroid()));
+        let centroid_min = centroid_bounds.min;
+
+        let node_area = node.bounds.surface_area();
+        let leaf_cost = config.intersection_cost * node.prim_count as f32;
+        let mut best: Option<(usize, usize, f32)> = None; // Axis, split bin and cost.
+        for axis in 0..3 {
+            let extent = centroid_bounds.extent()[axis];
+            if extent <= 0.0 {
+                continue;
+            }
+            let scale = num_bins as f32 / extent;
+            let bin_index = |centroid: Vec3A| {
+                (((centroid[axis] - centroid_min[axis]) * scale) as usize).min(num_bins - 1)
+            };
+
+            // Populate the bins.
+            let mut bins = vec![Bin::EMPTY; num_bins];
+            for &prim in prims {
+                let bin = &mut bins[bin_index(bounds[prim].centroid())];
+                bin.bounds = bin.bounds.union(&bounds[prim]);
+                bin.count += 1;
+            }
+
+            // Sweep from the right to get the area and count to the right of every plane.
+            let mut right_costs = vec![0.0; num_bins];
+            let mut right = Bin::EMPTY;
+            for b in (1..num_bins).rev() {
+                right.grow(&bins[b]);
+                right_costs[b] = right.count as f32 * right.bounds.surface_area();
+            }
+
+            // Sweep from the left and evaluate the plane after each bin.
+            let mut left = Bin::EMPTY;
+            for b in 0..num_bins - 1 {
+                left.grow(&bins[b]);
+                let left_cost = left.count as f32 * left.bounds.surface_area();
+                let cost = config.traversal_cost
+                    + config.intersection_cost * (left_cost + right_costs[b + 1]) / node_area;
+                if cost < best.map_or(f32::INFINITY, |(_, _, best_cost)| best_cost) {
+                    best = Some((axis, b, cost));
+                }
+            }
+        }
+
+        // If every centroid is in the same place there is nothing to split.
+        let (axis, split_bin, cost) = best?;
+        if cost >= leaf_cost && node.prim_count <= config.max_leaf_size {
+            return None;
+        }
+
+        // Partition using the same binning as above, so rounding can't move primitives
+        // to the other side of the chosen plane.
+        let min = centroid_min[axis];
+        let scale = num_bins as f32 / centroid_bounds.extent()[axis];
+        Some(Box::new(move |centroid: Vec3A| {
+            (((centroid[axis] - min) * scale) as usize).min(num_bins - 1) <= split_bin
+        }))
+    }
+
+    /// Compute statistics describing the quality of the tree.
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats::default();
+        let root_area = self.bounds().surface_area();
+        if self.prim_indices.is_empty() || root_area <= 0.0 {
+            return stats;
+        }
+
+        // Costs are given by the probability of a ray through the root also passing
+        // through the node, which is proportional to the node's surface area.
+        let config = &self.config;
+        let mut stack = vec![(self.root_index, 0)];
+        while let Some((node_index, depth)) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let probability = node.bounds.surface_area() / root_area;
+            stats.node_count += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            if node.is_leaf() {
+                stats.leaf_count += 1;
+                stats.max_leaf_size = stats.max_leaf_size.max(node.prim_count);
+                stats.sah_cost += config.intersection_cost * node.prim_count as f32 * probability;
+            } else {
+                stats.sah_cost += config.traversal_cost * probability;
+                stack.push((node.left_child, depth + 1));
+                stack.push((node.left_child + 1, depth + 1));
+            }
+        }
+
+        stats
     }
 
-    /// Update the bounds for a given node.
-    fn update_node_bounds(&mut self, node_index: usize) {
+    /// Update the bounds for a given node to contain all of its primitives.
+    fn update_node_bounds(&mut self, node_index: usize, bounds: &[Aabb]) {
         let node = &mut self.nodes[node_index];
-        // Visit every vertex of each triangle to find the lowest and highest x, y, and z components,
-        // thus yielding an AABB for this node.
+        node.bounds = Aabb::EMPTY;
         for i in 0..node.prim_count {
-            let tri_idx = self.triangle_indices[node.first_prim + i];
-            let tri = self.triangles[tri_idx].vertices();
-            // Find min components for this tri.
-            node.aabb_min = node.aabb_min.min(tri[0]);
-            node.aabb_min = node.aabb_min.min(tri[1]);
-            node.aabb_min = node.aabb_min.min(tri[2] - 0.001); // BB must have non-zero width in each dimension.
-
-            // Find max components for this tri.
-            node.aabb_max = node.aabb_max.max(tri[0]);
-            node.aabb_max = node.aabb_max.max(tri[1]);
-            node.aabb_max = node.aabb_max.max(tri[2] + 0.001);
+            node.bounds = node
+                .bounds
+                .union(&bounds[self.prim_indices[node.first_prim + i]]);
         }
     }
 
-    fn intersect_bvh(
+    /// Find the closest intersection by walking the tree with an explicit stack.
+    /// The nearer child is visited first and `t_max` shrinks to the closest hit found so far,
+    /// so subtrees behind that hit are skipped.
+    ///
+    /// `hit_prim` intersects the primitive with the given index, ignoring hits further away
+    /// than the given distance, and returns the distance of the hit. `node_visits` is
+    /// incremented for every node whose contents are tested.
+    pub fn traverse(
         &self,
-        node_index: usize,
         r: &Ray,
         t_min: f32,
         t_max: f32,
-        rec: &mut HitPayload,
+        node_visits: &mut u32,
+        mut hit_prim: impl FnMut(usize, f32) -> Option<f32>,
     ) -> bool {
-        let node = &self.nodes[node_index];
-        if !intersect_aabb(r, t_min, t_max, node.aabb_min, node.aabb_max) {
+        if self.prim_indices.is_empty() {
             return false;
         }
+
+        let inv_dir = r.direction().recip();
+        let mut closest_so_far = t_max;
+        let mut hit_anything = false;
+
+        let root = &self.nodes[self.root_index];
+        if root.bounds.intersect(r, inv_dir, t_min, t_max).is_none() {
+            return false;
+        }
+
+        // Nodes still to be visited, along with the distance at which the ray enters them.
+        let mut stack = [(0, 0.0); MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut node = root;
+        loop {
+            *node_visits += 1;
+            if node.is_leaf() {
+                for i in 0..node.prim_count {
+                    let prim = self.prim_indices[node.first_prim + i];
+                    if let Some(t) = hit_prim(prim, closest_so_far) {
+                        hit_anything = true;
+                        closest_so_far = t;
+                    }
+                }
+            } else {
+                let left = &self.nodes[node.left_child];
+                let right = &self.nodes[node.left_child + 1];
+                let t_left = left.bounds.intersect(r, inv_dir, t_min, closest_so_far);
+                let t_right = right.bounds.intersect(r, inv_dir, t_min, closest_so_far);
+
+                // Visit the nearer child next and save the other one for later.
+                match (t_left, t_right) {
+                    (Some(t_left), Some(t_right)) => {
+                        let (near, far, t_far) = if t_left <= t_right {
+                            (left, node.left_child + 1, t_right)
+                        } else {
+                            (right, node.left_child, t_left)
+                        };
+                        stack[stack_size] = (far, t_far);
+                        stack_size += 1;
+                        node = near;
+                        continue;
+                    }
+                    (Some(_), None) => {
+                        node = left;
+                        continue;
+                    }
+                    (None, Some(_)) => {
+                        node = right;
+                        continue;
+                    }
+                    (None, None) => {}
+                }
+            }
+
+            // Pop the next node, skipping those which start behind the closest hit.
+            loop {
+                if stack_size == 0 {
+                    return hit_anything;
+                }
+                stack_size -= 1;
+                let (node_index, t_enter) = stack[stack_size];
+                if t_enter <= closest_so_far {
+                    node = &self.nodes[node_index];
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::*, material::Lambertian, mesh::Mesh, triangle::Triangle, Color};
+    use rand::{Rng, SeedableRng};
+    use std::{sync::Arc, time::Instant};
+
+    /// Small random triangles scattered in a box, like `Application`'s test scene.
+    fn random_triangles(n: usize) -> Vec<Triangle> {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(5);
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        (0..n)
+            .map(|_| {
+                let mut v = || vec3a(rng.gen(), rng.gen(), rng.gen()) * 9.0 - 5.0;
+                let v0 = v();
+                Triangle::new(v0, v0 + v() * 0.1, v0 + v() * 0.1, material.clone())
+            })
+            .collect()
+    }
+
+    fn bounds(triangles: &[Triangle]) -> Vec<Aabb> {
+        triangles.iter().map(|t| t.bounding_box()).collect()
+    }
+
+    fn closest_hit(hittable: &dyn Hittable, ray: &Ray) -> Option<f32> {
+        let mut rec = HitPayload::new();
+        rec.hit_distance = f32::INFINITY;
+        if hittable.hit(ray, 0.0, f32::INFINITY, &mut rec) {
+            Some(rec.hit_distance)
+        } else {
+            None
+        }
+    }
+
+    fn bvh_closest_hit(bvh: &Bvh, triangles: &[Triangle], ray: &Ray) -> Option<f32> {
+        let mut rec = HitPayload::new();
+        let hit = bvh.traverse(ray, 0.0, f32::INFINITY, &mut 0, |i, t_max| {
+            if triangles[i].hit(ray, 0.0, t_max, &mut rec) {
+                Some(rec.hit_distance)
+            } else {
+                None
+            }
+        });
+        hit.then_some(rec.hit_distance)
+    }
+
+    #[test]
+    fn builders_match_brute_force() {
+        let triangles = random_triangles(2000);
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(6);
+        for split_method in [SplitMethod::Midpoint, SplitMethod::Sah] {
+            let bvh = Bvh::with_config(
+                &bounds(&triangles),
+                BvhConfig {
+                    split_method,
+                    ..Default::default()
+                },
+            );
+            for _ in 0..1000 {
+                let origin = vec3a(rng.gen(), rng.gen(), rng.gen()) * 20.0 - 10.0;
+                let target = vec3a(rng.gen(), rng.gen(), rng.gen()) * 8.0 - 4.0;
+                let ray = Ray::new(origin, (target - origin).normalize());
+
+                let expected = triangles
+                    .iter()
+                    .filter_map(|t| closest_hit(t, &ray))
+                    .min_by(f32::total_cmp);
+                assert_eq!(
+                    bvh_closest_hit(&bvh, &triangles, &ray),
+                    expected,
+                    "{:?}",
+                    split_method
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sah_is_cheaper_than_midpoint() {
+        let triangles = random_triangles(5000);
+        let midpoint = Bvh::with_config(
+            &bounds(&triangles),
+            BvhConfig {
+                split_method: SplitMethod::Midpoint,
+                ..Default::default()
+            },
+        )
+        .stats();
+        let sah = Bvh::new(&bounds(&triangles)).stats();
+
+        assert!(
+            sah.sah_cost < midpoint.sah_cost,
+            "SAH: {}, midpoint: {}",
+            sah,
+            midpoint
+        );
+        assert!(sah.node_count < 2 * triangles.len());
+        assert!(sah.max_leaf_size <= BvhConfig::default().max_leaf_size);
+    }
+
+    #[test]
+    fn stats_of_single_triangle() {
+        let stats = Bvh::new(&bounds(&random_triangles(1))).stats();
+        assert_eq!(stats.node_count, 1);
+        assert_eq!(stats.leaf_count, 1);
+        assert_eq!(stats.max_depth, 0);
+        assert_eq!(stats.sah_cost, 1.0);
+    }
+
+    /// The original traversal, kept as a baseline for the benchmark below. It recurses into
+    /// both children and tests every box against the original `t_max`.
+    fn legacy_intersect<'a>(
+        bvh: &Bvh,
+        triangles: &'a [Triangle],
+        node_index: usize,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitPayload<'a>,
+    ) -> bool {
+        let node = &bvh.nodes[node_index];
+        let (mut t0, mut t1) = (t_min, t_max);
+        for axis in 0..3 {
+            let inv_d = 1.0 / r.direction()[axis];
+            let mut near = (node.bounds.min[axis] - r.origin()[axis]) * inv_d;
+            let mut far = (node.bounds.max[axis] - r.origin()[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t1 <= t0 {
+                return false;
+            }
+        }
+
         if node.is_leaf() {
             let mut ret = false;
             let mut temp_rec = HitPayload::new();
-            // let mut closest_so_far = t_max;
-            // If node is a leaf, intersect each of it's primitives and return the closest hit.
             for i in 0..node.prim_count {
-                ret |= if self.triangles[self.triangle_indices[node.first_prim + i]].hit(
-                    r,
-                    t_min,
-                    t_max,
-                    &mut temp_rec,
-                ) {
+                let triangle = &triangles[bvh.prim_indices[node.first_prim + i]];
+                if triangle.hit(r, t_min, t_max, &mut temp_rec) {
                     if temp_rec.hit_distance < rec.hit_distance {
-                        // Hit was closest recorded so far.
-                        rec.world_position = temp_rec.world_position;
-                        rec.world_normal = temp_rec.world_normal;
-                        rec.hit_distance = temp_rec.hit_distance;
-                        rec.front_face = temp_rec.front_face;
-                        rec.albedo = temp_rec.albedo;
-                        rec.emissive = temp_rec.emissive;
+                        *rec = temp_rec;
                     }
-
-                    true
-                } else {
-                    false
-                };
+                    ret = true;
+                }
             }
-            return ret;
+            ret
         } else {
-            // Node is an interior node. Recurse on each of it's children.
-            let left_hit = self.intersect_bvh(node.left_child, r, t_min, t_max, rec);
-            let right_hit = self.intersect_bvh(node.left_child + 1, r, t_min, t_max, rec);
-
+            let left_hit = legacy_intersect(bvh, triangles, node.left_child, r, t_min, t_max, rec);
+            let right_hit =
+                legacy_intersect(bvh, triangles, node.left_child + 1, r, t_min, t_max, rec);
             left_hit || right_hit
         }
     }
-}
 
-impl Hittable for Bvh {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload) -> bool {
-        self.intersect_bvh(self.root_index, r, t_min, t_max, rec)
+    /// Rays from random points around the triangles towards random points inside their bounds.
+    fn random_rays(triangles: &[Triangle], n: usize) -> Vec<Ray> {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(8);
+        let mut aabb_min = Vec3A::splat(f32::INFINITY);
+        let mut aabb_max = Vec3A::splat(f32::NEG_INFINITY);
+        for v in triangles.iter().flat_map(|t| t.vertices()) {
+            aabb_min = aabb_min.min(v);
+            aabb_max = aabb_max.max(v);
+        }
+        let center = (aabb_min + aabb_max) * 0.5;
+        let extent = aabb_max - aabb_min;
+
+        (0..n)
+            .map(|_| {
+                let mut v = || vec3a(rng.gen(), rng.gen(), rng.gen()) - 0.5;
+                let origin = center + v() * extent * 3.0;
+                let target = center + v() * extent;
+                Ray::new(origin, (target - origin).normalize())
+            })
+            .collect()
+    }
+
+    /// Compare the rays per second of the legacy and current traversals.
+    /// Run with `cargo test --release traversal_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn traversal_benchmark() {
+        let mut scenes = vec![("random triangles".to_string(), random_triangles(100_000))];
+        for path in [
+            "assets/cornell_light.glb",
+            "assets/icosphere.glb",
+            "assets/monkey.glb",
+        ] {
+            let mesh = Mesh::from_gltf(path, BvhConfig::default()).unwrap();
+            scenes.push((path.to_string(), mesh.triangles().to_vec()));
+        }
+
+        for (name, triangles) in scenes {
+            let bvh = Bvh::new(&bounds(&triangles));
+            let rays = random_rays(&triangles, 200_000);
+
+            let mut legacy_hits = Vec::with_capacity(rays.len());
+            let now = Instant::now();
+            for ray in &rays {
+                let mut rec = HitPayload::new();
+                rec.hit_distance = f32::INFINITY;
+                let root = bvh.root_index;
+                legacy_intersect(&bvh, &triangles, root, ray, 0.0, f32::INFINITY, &mut rec);
+                legacy_hits.push(rec.hit_distance);
+            }
+            let legacy_time = now.elapsed().as_secs_f64();
+
+            let mut hits = Vec::with_capacity(rays.len());
+            let now = Instant::now();
+            for ray in &rays {
+                hits.push(bvh_closest_hit(&bvh, &triangles, ray).unwrap_or(f32::INFINITY));
+            }
+            let time = now.elapsed().as_secs_f64();
+
+            assert_eq!(hits, legacy_hits, "{}", name);
+            println!("{} ({} triangles)", name, triangles.len());
+            println!(
+                "    Legacy:  {:.2} Mrays/s",
+                rays.len() as f64 / legacy_time / 1e6
+            );
+            println!("    Current: {:.2} Mrays/s", rays.len() as f64 / time / 1e6);
+        }
     }
 }
@@ -1,9 +1,17 @@
-use crate::input::*;
+use crate::{
+    hittable::{HitPayload, Hittable},
+    input::*,
+    ray::Ray,
+    util,
+};
 use glam::*;
-use imgui::Ui;
-use rand::{Rng, SeedableRng};
+use rand::Rng;
+use std::f32::consts::PI;
 use winit::event::{MouseButton, VirtualKeyCode};
 
+// Height of a full frame sensor in meters, which relates the f-stop to the aperture radius.
+const SENSOR_HEIGHT: f32 = 0.024;
+
 pub struct Camera {
     projection: Mat4,
     view: Mat4,
@@ -17,8 +25,10 @@ pub struct Camera {
     position: Vec3A,
     forward_direction: Vec3A,
 
-    // Cached ray directions
-    ray_directions: Vec<Vec3A>,
+    // Thin lens. An aperture radius of zero is a pinhole, which keeps everything in focus.
+    aperture_radius: f32,
+    aperture_blades: u32, // Sides of a polygonal aperture, or fewer than 3 for a round one.
+    focus_distance: f32,  // Distance to the plane in focus, along the forward direction.
 
     viewport_width: u32,
     viewport_height: u32,
@@ -26,8 +36,6 @@ pub struct Camera {
     movement_speed: f32,
     rotation_speed: f32,
     last_mouse_pos: (f32, f32),
-
-    enable_aa: bool, // Flag to enable anti-aliasing.
 }
 
 impl Camera {
@@ -37,7 +45,6 @@ impl Camera {
         far_clip: f32,
         viewport_width: u32,
         viewport_height: u32,
-        enable_aa: bool,
     ) -> Self {
         // TODO: These defaults shouldn't be hard-coded like this.
         let forward_direction = vec3a(0.0, 0.0, -1.0);
@@ -57,31 +64,6 @@ impl Camera {
         );
         let inverse_projection = projection.inverse();
 
-        // Initialize the ray directions
-        let mut rng = rand_xoshiro::Xoroshiro128PlusPlus::from_entropy();
-        let mut ray_directions = Vec::with_capacity((viewport_width * viewport_height) as usize);
-        for y in 0..viewport_height {
-            for x in 0..viewport_width {
-                let (jitter_x, jitter_y) = if enable_aa {
-                    (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0))
-                } else {
-                    (0.0, 0.0)
-                };
-                let mut coord = vec2(
-                    (x as f32 + jitter_x) / viewport_width as f32,
-                    (y as f32 + jitter_y) / viewport_height as f32,
-                );
-                coord = coord * 2.0 - 1.0; // -1 -> 1
-
-                let target = inverse_projection * vec4(coord.x, coord.y, 1.0, 1.0);
-                let ray_direction = Vec3A::from(
-                    (inverse_view * ((target.truncate() / target.w).normalize()).extend(0.0))
-                        .truncate(),
-                ); // World space
-                ray_directions.push(ray_direction);
-            }
-        }
-
         Self {
             view,
             inverse_view,
@@ -95,7 +77,10 @@ impl Camera {
             position,
             forward_direction,
 
-            ray_directions,
+            aperture_radius: 0.0,
+            aperture_blades: 0,
+            // Focused on the plane through the origin.
+            focus_distance: -position.dot(forward_direction),
 
             viewport_width,
             viewport_height,
@@ -104,7 +89,6 @@ impl Camera {
             rotation_speed: 0.3,
 
             last_mouse_pos: (0.0, 0.0),
-            enable_aa,
         }
     }
 
@@ -173,19 +157,14 @@ impl Camera {
             moved = true;
         }
 
-        // If the camera moved we need to recompute the view matrix and ray directions.
+        // If the camera moved we need to recompute the view matrix.
         if moved {
             self.recalculate_view();
-            self.recalculate_view_directions();
         }
 
         moved
     }
 
-    pub fn get_ray_directions(&self) -> &Vec<Vec3A> {
-        &self.ray_directions
-    }
-
     pub fn get_position(&self) -> &Vec3A {
         &self.position
     }
@@ -193,41 +172,125 @@ impl Camera {
     pub fn set_position(&mut self, position: Vec3A) {
         self.position = position;
         self.recalculate_view();
-        self.recalculate_view_directions();
     }
 
-    /// Recompute the view directions.
-    fn recalculate_view_directions(&mut self) {
-        self.ray_directions.resize(
-            (self.viewport_width * self.viewport_height) as usize,
-            Vec3A::ZERO,
-        );
+    pub fn get_forward_direction(&self) -> &Vec3A {
+        &self.forward_direction
+    }
+
+    pub fn set_forward_direction(&mut self, forward_direction: Vec3A) {
+        self.forward_direction = forward_direction.normalize();
+        self.recalculate_view();
+    }
+
+    pub fn get_aperture_radius(&self) -> f32 {
+        self.aperture_radius
+    }
+
+    pub fn set_aperture_radius(&mut self, aperture_radius: f32) {
+        self.aperture_radius = aperture_radius.max(0.0);
+    }
+
+    pub fn get_aperture_blades(&self) -> u32 {
+        self.aperture_blades
+    }
+
+    /// Make the aperture a regular polygon, which shapes the bokeh of out of focus
+    /// highlights. Fewer than 3 blades make a round aperture.
+    pub fn set_aperture_blades(&mut self, aperture_blades: u32) {
+        self.aperture_blades = aperture_blades;
+    }
+
+    pub fn get_focus_distance(&self) -> f32 {
+        self.focus_distance
+    }
+
+    pub fn set_focus_distance(&mut self, focus_distance: f32) {
+        self.focus_distance = focus_distance;
+    }
+
+    /// Focal length of a lens with the camera's field of view on a full frame sensor,
+    /// in meters if the scene is.
+    pub fn get_focal_length(&self) -> f32 {
+        0.5 * SENSOR_HEIGHT / (0.5 * self.vertical_fov.to_radians()).tan()
+    }
+
+    /// Ratio of the focal length to the aperture diameter, infinite for a pinhole.
+    pub fn get_f_stop(&self) -> f32 {
+        self.get_focal_length() / (2.0 * self.aperture_radius)
+    }
+
+    pub fn set_f_stop(&mut self, f_stop: f32) {
+        self.set_aperture_radius(self.get_focal_length() / (2.0 * f_stop));
+    }
+
+    /// Direction of the ray from the center of the lens through a point of the image, given
+    /// in pixels from the bottom left corner.
+    pub fn get_direction(&self, film: Vec2) -> Vec3A {
+        let viewport = vec2(self.viewport_width as f32, self.viewport_height as f32);
+        let coord = film / viewport * 2.0 - 1.0; // -1 -> 1
+
+        let target = self.inverse_projection * vec4(coord.x, coord.y, 1.0, 1.0);
+        Vec3A::from(
+            (self.inverse_view * ((target.truncate() / target.w).normalize()).extend(0.0))
+                .truncate(),
+        ) // World space
+    }
 
-        let mut rng = rand_xoshiro::Xoroshiro128PlusPlus::from_entropy();
-        for y in 0..self.viewport_height {
-            for x in 0..self.viewport_width {
-                let (jitter_x, jitter_y) = if self.enable_aa {
-                    (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0))
-                } else {
-                    (0.0, 0.0)
-                };
-                // TODO: The jittering should take place per-frame.
-                let mut coord = vec2(
-                    (x as f32 + jitter_x) / self.viewport_width as f32,
-                    (y as f32 + jitter_y) / self.viewport_height as f32,
-                );
-                coord = coord * 2.0 - 1.0; // -1 -> 1
-
-                let target = self.inverse_projection * vec4(coord.x, coord.y, 1.0, 1.0);
-                let ray_direction = Vec3A::from(
-                    (self.inverse_view * ((target.truncate() / target.w).normalize()).extend(0.0))
-                        .truncate(),
-                ); // World space
-                self.ray_directions[(x + y * self.viewport_width) as usize] = ray_direction;
+    /// Ray through a point of the image, given in pixels from the bottom left corner.
+    /// With an aperture, the ray starts at a point of the lens chosen with `rng`, so that
+    /// only the plane at the focus distance is sharp.
+    pub fn get_ray(&self, film: Vec2, rng: &mut impl Rng) -> Ray {
+        let direction = self.get_direction(film);
+        if self.aperture_radius <= 0.0 {
+            return Ray::new(self.position, direction);
+        }
+
+        // Rays through the same point of the image meet on the plane in focus.
+        let focus_point = self.position
+            + direction * (self.focus_distance / direction.dot(self.forward_direction));
+        let lens = self.aperture_radius
+            * sample_aperture(vec2(rng.gen(), rng.gen()), self.aperture_blades);
+        let right = Vec3A::from(self.inverse_view.x_axis.truncate());
+        let up = Vec3A::from(self.inverse_view.y_axis.truncate());
+        let origin = self.position + lens.x * right + lens.y * up;
+        Ray::new(origin, (focus_point - origin).normalize())
+    }
+
+    /// Focus on the surface seen through the center of the lens at the center of a pixel.
+    /// Returns false and keeps the focus distance if the pixel sees the background.
+    pub fn focus_on_pixel(&mut self, scene: &impl Hittable, x: usize, y: usize) -> bool {
+        let direction = self.get_direction(vec2(x as f32 + 0.5, y as f32 + 0.5));
+        self.focus_along(scene, direction)
+    }
+
+    /// Focus on the surface at the center of the image, or on the plane through the origin
+    /// if the center sees the background. Meant for cameras which are placed in a scene
+    /// without a focus distance.
+    pub fn focus_on_scene(&mut self, scene: &impl Hittable) {
+        if !self.focus_along(scene, self.forward_direction) {
+            let distance = -self.position.dot(self.forward_direction);
+            if distance > 0.0 {
+                self.focus_distance = distance;
             }
         }
     }
 
+    fn focus_along(&mut self, scene: &impl Hittable, direction: Vec3A) -> bool {
+        let mut hit = HitPayload::new();
+        hit.hit_distance = f32::INFINITY;
+        if !scene.hit(
+            &Ray::new(self.position, direction),
+            0.0,
+            f32::INFINITY,
+            &mut hit,
+        ) {
+            return false;
+        }
+        self.focus_distance = hit.hit_distance * direction.dot(self.forward_direction);
+        true
+    }
+
     /// Recompute the view and inverse view matrices.
     /// This function should be called whenver the camera's position or
     /// orientation is modified.
@@ -243,6 +306,7 @@ impl Camera {
     /// Recompute the projection and inverse projection matrices.
     /// This function should be called if the viewport dimensions, vertical fov,
     /// or near/far clip planes ever change.
+    #[allow(dead_code)]
     fn recalculate_projection(&mut self) {
         self.projection = Mat4::perspective_rh(
             self.vertical_fov.to_radians(),
@@ -253,3 +317,118 @@ impl Camera {
         self.inverse_projection = self.projection.inverse();
     }
 }
+
+/// Map a point of the unit square to a uniformly distributed point of the aperture: the unit
+/// disk, or a regular polygon with `blades` sides inscribed in it.
+fn sample_aperture(u: Vec2, blades: u32) -> Vec2 {
+    if blades < 3 {
+        return util::concentric_disk_sample(u);
+    }
+
+    // Pick one of the triangles between the center and an edge, and reuse the rest of u.x.
+    let scaled = u.x * blades as f32;
+    let i = (scaled as u32).min(blades - 1);
+    let u = vec2(scaled - i as f32, u.y);
+    let vertex = |k: u32| {
+        let angle = 2.0 * PI * k as f32 / blades as f32;
+        vec2(angle.cos(), angle.sin())
+    };
+    u.x.sqrt() * ((1.0 - u.y) * vertex(i) + u.y * vertex(i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, plane::Plane, Color};
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    /// A camera at the origin looking down -z.
+    fn camera() -> Camera {
+        let mut camera = Camera::new(45.0, 0.1, 100.0, 8, 6);
+        camera.set_position(Vec3A::ZERO);
+        camera.set_forward_direction(vec3a(0.0, 0.0, -1.0));
+        camera
+    }
+
+    #[test]
+    fn film_positions_map_to_directions() {
+        let camera = camera();
+        let center = camera.get_direction(vec2(4.0, 3.0));
+        assert!((center - vec3a(0.0, 0.0, -1.0)).length() < 1e-6);
+
+        // The top edge of the image is half of the field of view above the center.
+        let top = camera.get_direction(vec2(4.0, 6.0));
+        assert!((top.y.atan2(-top.z) - 22.5f32.to_radians()).abs() < 1e-5);
+        let left = camera.get_direction(vec2(0.0, 3.0));
+        assert!(left.x < 0.0 && left.y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn lens_rays_meet_on_the_focal_plane() {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(3);
+        let mut camera = camera();
+        let pinhole = camera.get_ray(vec2(1.25, 2.5), &mut rng);
+        assert_eq!(pinhole.origin(), Vec3A::ZERO);
+
+        camera.set_aperture_radius(0.1);
+        camera.set_focus_distance(2.0);
+        let t = 2.0 / -pinhole.direction().z;
+        let focus_point = pinhole.direction() * t;
+        for _ in 0..100 {
+            let ray = camera.get_ray(vec2(1.25, 2.5), &mut rng);
+            assert!(ray.origin().length() <= 0.1 + 1e-6);
+            assert!(ray.origin().z.abs() < 1e-6);
+            let t = 2.0 / -ray.direction().z;
+            let point = ray.origin() + ray.direction() * t;
+            assert!((point - focus_point).length() < 1e-5);
+        }
+
+        // An aperture of f/2 is a quarter of the focal length wide.
+        camera.set_f_stop(2.0);
+        assert!((camera.get_aperture_radius() - camera.get_focal_length() / 4.0).abs() < 1e-6);
+        assert!((camera.get_f_stop() - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn polygonal_aperture_is_sampled_uniformly() {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(4);
+        let blades = 6;
+        let n = 60_000;
+        let mut inner = 0;
+        let mut mean = Vec2::ZERO;
+        for _ in 0..n {
+            let p = sample_aperture(vec2(rng.gen(), rng.gen()), blades);
+            // Inside the hexagon, whose edges are at a distance of cos(30°) from the center.
+            for k in 0..blades {
+                let angle = (2.0 * k as f32 + 1.0) * PI / blades as f32;
+                let normal = vec2(angle.cos(), angle.sin());
+                assert!(p.dot(normal) <= (PI / blades as f32).cos() + 1e-5);
+            }
+            if p.length() < 0.5 {
+                inner += 1;
+            }
+            mean += p;
+        }
+        // The disk of half the radius lies inside the hexagon and gets its share of samples.
+        let area = 0.5 * blades as f32 * (2.0 * PI / blades as f32).sin();
+        let fraction = inner as f32 / n as f32;
+        assert!((fraction - 0.25 * PI / area).abs() < 0.01, "{}", fraction);
+        assert!((mean / n as f32).length() < 0.01);
+    }
+
+    #[test]
+    fn focus_on_picked_surface() {
+        let mut camera = camera();
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        let wall = Plane::new(vec3a(0.0, 0.0, -3.0), vec3a(0.0, 0.0, 1.0), material);
+
+        // The focus distance is measured along the view direction, even at the corners.
+        assert!(camera.focus_on_pixel(&wall, 0, 0));
+        assert!((camera.get_focus_distance() - 3.0).abs() < 1e-4);
+
+        camera.set_forward_direction(vec3a(0.0, 0.0, 1.0));
+        assert!(!camera.focus_on_pixel(&wall, 4, 3));
+        assert!((camera.get_focus_distance() - 3.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{aabb::Aabb, hittable::*, light::AreaLight, material::Material, quad::Quad, ray::*};
+use glam::*;
+use std::sync::Arc;
+
+/// Axis-aligned box made of six quads facing outwards. Each face is textured upright, as
+/// seen from outside with y pointing up, and the top and bottom are aligned with x.
+#[derive(Debug, Clone)]
+pub struct Cuboid {
+    faces: [Quad; 6],
+    bounds: Aabb,
+}
+
+impl Cuboid {
+    /// Create the box between two opposite corners.
+    pub fn new(a: Vec3A, b: Vec3A, material: Arc<dyn Material>) -> Self {
+        let (min, max) = (a.min(b), a.max(b));
+        let d = max - min;
+        let (dx, dy, dz) = (
+            vec3a(d.x, 0.0, 0.0),
+            vec3a(0.0, d.y, 0.0),
+            vec3a(0.0, 0.0, d.z),
+        );
+        let face = |corner, edge_u, edge_v| Quad::new(corner, edge_u, edge_v, material.clone());
+        let faces = [
+            face(vec3a(max.x, min.y, max.z), -dz, dy), // +x
+            face(min, dz, dy),                         // -x
+            face(vec3a(min.x, max.y, max.z), dx, -dz), // +y
+            face(min, dx, dz),                         // -y
+            face(vec3a(min.x, min.y, max.z), dx, dy),  // +z
+            face(vec3a(max.x, min.y, min.z), -dx, dy), // -z
+        ];
+        Self {
+            faces,
+            bounds: Aabb { min, max },
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+        for (i, face) in self.faces.iter().enumerate() {
+            if face.hit(r, t_min, closest_so_far, rec) {
+                rec.primitive_index = i;
+                hit_anything = true;
+                closest_so_far = rec.hit_distance;
+            }
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        self.faces
+            .iter()
+            .enumerate()
+            .flat_map(|(i, face)| {
+                face.emitters()
+                    .into_iter()
+                    .map(move |(_, light)| (i, light))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, Color};
+
+    #[test]
+    fn faces_point_outwards() {
+        let material = Arc::new(Lambertian {
+            albedo: Color::ONE,
+            emission: Color::ONE,
+        });
+        let cuboid = Cuboid::new(Vec3A::ONE, vec3a(-1.0, -2.0, -3.0), material);
+        assert_eq!(
+            cuboid.bounding_box(),
+            Aabb::from_points([vec3a(-1.0, -2.0, -3.0), Vec3A::ONE])
+        );
+
+        // Rays from outside along each axis hit the front of the face they point at.
+        for axis in [
+            Vec3A::X,
+            Vec3A::Y,
+            Vec3A::Z,
+            -Vec3A::X,
+            -Vec3A::Y,
+            -Vec3A::Z,
+        ] {
+            let center = vec3a(0.0, -0.5, -1.0);
+            let r = Ray::new(center + axis * 10.0, -axis);
+            let mut rec = HitPayload::new();
+            assert!(cuboid.hit(&r, 0.0, f32::INFINITY, &mut rec));
+            assert!(rec.front_face);
+            assert_eq!(rec.world_normal, axis);
+            // Hits record the face, which is also the index of its emitter.
+            let (_, face) = &cuboid.emitters()[rec.primitive_index];
+            assert_eq!(face.sample_point(Vec2::splat(0.5)).normal, axis);
+            assert!((rec.uv - Vec2::splat(0.5)).length() < 1e-6);
+        }
+
+        // Every face is an emitter, and together they cover the surface of the box.
+        let emitters = cuboid.emitters();
+        let faces: Vec<_> = emitters.iter().map(|(face, _)| *face).collect();
+        assert_eq!(faces, [0, 1, 2, 3, 4, 5]);
+        let area: f32 = emitters.iter().map(|(_, e)| e.area()).sum();
+        assert_eq!(area, 2.0 * (2.0 * 3.0 + 3.0 * 4.0 + 4.0 * 2.0));
+    }
+}
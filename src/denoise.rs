@@ -0,0 +1,211 @@
+use crate::{util, Color};
+use glam::*;
+use rayon::prelude::*;
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010). The noisy image is blurred
+/// with increasingly sparse 5x5 kernels, while feature buffers keep the blur from crossing
+/// the edges of objects and textures.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    /// Number of filter passes. Each pass doubles the size of the kernel, so 5 passes cover
+    /// 61x61 pixels.
+    pub iterations: u32,
+    /// Tolerance of the edge-stopping functions. Smaller values preserve more detail, and
+    /// larger values remove more noise.
+    pub color_sigma: f32,
+    pub normal_sigma: f32,
+    pub albedo_sigma: f32,
+    /// Tolerated depth difference, relative to the depth of the pixel.
+    pub depth_sigma: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            color_sigma: 1.0,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+            depth_sigma: 0.05,
+        }
+    }
+}
+
+/// Noise-free buffers describing the surface seen by each pixel, which guide the filter.
+pub struct Features<'a> {
+    pub albedo: &'a [Color],
+    pub normal: &'a [Vec3A],
+    /// Distance to the camera, infinite for the background.
+    pub depth: &'a [f32],
+}
+
+/// Weights of the B3 spline kernel along each axis.
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Smallest albedo the color is divided by, so that black surfaces don't blow up.
+const MIN_ALBEDO: f32 = 1e-3;
+
+impl Denoiser {
+    /// Denoise an image whose rows are `width` pixels long.
+    ///
+    /// The color is divided by the albedo before filtering, so that the filter only smooths
+    /// the lighting and texture detail survives.
+    pub fn denoise(&self, width: usize, color: &[Color], features: &Features) -> Vec<Color> {
+        assert_eq!(features.albedo.len(), color.len());
+        assert_eq!(features.normal.len(), color.len());
+        assert_eq!(features.depth.len(), color.len());
+        if width == 0 || color.is_empty() {
+            return color.to_vec();
+        }
+
+        let albedo: Vec<Color> = features
+            .albedo
+            .iter()
+            .map(|a| a.max(Vec3A::splat(MIN_ALBEDO)))
+            .collect();
+        let mut current: Vec<Color> = color.iter().zip(&albedo).map(|(c, a)| *c / *a).collect();
+        let mut next = vec![Color::ZERO; color.len()];
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            // Later passes average over more pixels, whose variance is lower.
+            let color_sigma = self.color_sigma / (1 << iteration) as f32;
+            next.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    *out = self.filter_pixel(&current, features, width, x, y, step, color_sigma);
+                }
+            });
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        current.iter().zip(&albedo).map(|(c, a)| *c * *a).collect()
+    }
+
+    /// Weighted average of the pixels `step` apart around a pixel.
+    #[allow(clippy::too_many_arguments)]
+    fn filter_pixel(
+        &self,
+        color: &[Color],
+        features: &Features,
+        width: usize,
+        x: usize,
+        y: usize,
+        step: usize,
+        color_sigma: f32,
+    ) -> Color {
+        let height = color.len() / width;
+        let p = x + y * width;
+        let (c_p, n_p, a_p, d_p) = (
+            compress(color[p]),
+            features.normal[p],
+            features.albedo[p],
+            features.depth[p],
+        );
+
+        let mut sum = Color::ZERO;
+        let mut weight_sum = 0.0;
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let qy = y as isize + (j as isize - 2) * step as isize;
+            if qy < 0 || qy >= height as isize {
+                continue;
+            }
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let qx = x as isize + (i as isize - 2) * step as isize;
+                if qx < 0 || qx >= width as isize {
+                    continue;
+                }
+                let q = qx as usize + qy as usize * width;
+
+                let color_distance = (compress(color[q]) - c_p).length_squared();
+                let normal_distance = (features.normal[q] - n_p).length_squared();
+                let albedo_distance = (features.albedo[q] - a_p).length_squared();
+                let weight = kx
+                    * ky
+                    * (-color_distance / (color_sigma * color_sigma)
+                        - normal_distance / (self.normal_sigma * self.normal_sigma)
+                        - albedo_distance / (self.albedo_sigma * self.albedo_sigma))
+                        .exp()
+                    * self.depth_weight(d_p, features.depth[q]);
+
+                sum += weight * color[q];
+                weight_sum += weight;
+            }
+        }
+
+        // The center pixel always has a positive weight, unless its own depth is invalid.
+        if weight_sum > 0.0 {
+            sum / weight_sum
+        } else {
+            color[p]
+        }
+    }
+
+    fn depth_weight(&self, d_p: f32, d_q: f32) -> f32 {
+        if d_p.is_infinite() || d_q.is_infinite() {
+            // The background only blends with the background.
+            return if d_p == d_q { 1.0 } else { 0.0 };
+        }
+        let relative = (d_p - d_q).abs() / (self.depth_sigma * d_p.max(1e-4));
+        (-relative).exp()
+    }
+}
+
+/// Compress the range of a color, so that color differences are compared relative to the
+/// brightness of the pixel rather than in absolute terms.
+fn compress(c: Color) -> Color {
+    let c = c.max(Color::ZERO);
+    c / (1.0 + util::luminance(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn smooths_noise_but_keeps_edges() {
+        // Two walls with different normals meet in the middle of the image. Both are evenly
+        // lit, but the lighting is noisy.
+        let (width, height) = (32, 16);
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(11);
+        let left = |x: usize| x < width / 2;
+        let mut color = Vec::new();
+        let mut normal = Vec::new();
+        for _ in 0..height {
+            for x in 0..width {
+                let mean = if left(x) { 0.2 } else { 0.8 };
+                color.push(Color::splat(mean * rng.gen_range(0.0..2.0)));
+                normal.push(if left(x) { Vec3A::X } else { Vec3A::Z });
+            }
+        }
+        let albedo = vec![Color::ONE; color.len()];
+        let depth = vec![1.0; color.len()];
+        let features = Features {
+            albedo: &albedo,
+            normal: &normal,
+            depth: &depth,
+        };
+
+        let denoised = Denoiser::default().denoise(width, &color, &features);
+        let error = |image: &[Color]| {
+            let mut sum = 0.0;
+            for (i, c) in image.iter().enumerate() {
+                let mean = if left(i % width) { 0.2 } else { 0.8 };
+                sum += (c.x - mean) * (c.x - mean);
+            }
+            sum / image.len() as f32
+        };
+        assert!(
+            error(&denoised) < 0.1 * error(&color),
+            "{} vs. {}",
+            error(&denoised),
+            error(&color)
+        );
+
+        // The walls don't bleed into each other.
+        for y in 0..height {
+            assert!(denoised[y * width + width / 2 - 1].x < 0.3);
+            assert!(denoised[y * width + width / 2].x > 0.6);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{
+    aabb::Aabb,
+    hittable::*,
+    light::{AreaLight, SurfacePoint},
+    material::Material,
+    onb::Onb,
+    ray::*,
+    util, Color,
+};
+use glam::*;
+use std::{f32::consts::PI, sync::Arc};
+
+/// Flat disk, such as a round area light. The front face is on the side of `normal`.
+#[derive(Debug, Clone)]
+pub struct Disk {
+    center: Vec3A,
+    normal: Vec3A,
+    radius: f32,
+    // Directions in the plane of the disk along which the texture coordinates increase.
+    tangent: Vec3A,
+    bitangent: Vec3A,
+    material: Arc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Vec3A, normal: Vec3A, radius: f32, material: Arc<dyn Material>) -> Self {
+        let onb = Onb::from_w(normal);
+        Self {
+            center,
+            normal: normal.normalize(),
+            radius,
+            tangent: onb.local(Vec3A::X),
+            bitangent: onb.local(-Vec3A::Y),
+            material,
+        }
+    }
+
+    /// Point at an offset from the center in units of the radius.
+    fn point_at(&self, offset: Vec2) -> SurfacePoint {
+        let position = self.center + self.radius * self.local(offset);
+        SurfacePoint::new(
+            position,
+            self.normal,
+            tex_coords(offset),
+            self.material.as_ref(),
+        )
+    }
+
+    fn local(&self, offset: Vec2) -> Vec3A {
+        offset.x * self.tangent + offset.y * self.bitangent
+    }
+}
+
+/// Textures are mapped onto the disk from the square around it.
+fn tex_coords(offset: Vec2) -> Vec2 {
+    offset * 0.5 + 0.5
+}
+
+impl AreaLight for Disk {
+    fn area(&self) -> f32 {
+        PI * self.radius * self.radius
+    }
+
+    /// Radiance emitted at the center of the disk.
+    fn emission(&self) -> Color {
+        self.point_at(Vec2::ZERO).emission
+    }
+
+    fn sample_point(&self, u: Vec2) -> SurfacePoint {
+        self.point_at(util::concentric_disk_sample(u))
+    }
+}
+
+impl Hittable for Disk {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        let denom = self.normal.dot(r.direction());
+        if denom.abs() < 1e-8 {
+            return false; // Ray is parallel to the disk.
+        }
+        let t = self.normal.dot(self.center - r.origin()) / denom;
+        if !(t_min..=t_max).contains(&t) {
+            return false;
+        }
+
+        let position = r.origin() + t * r.direction();
+        let offset = (position - self.center) / self.radius;
+        if offset.length_squared() > 1.0 {
+            return false;
+        }
+
+        rec.hit_distance = t;
+        rec.world_position = position;
+        rec.set_face_normal(r, self.normal);
+        rec.uv = tex_coords(vec2(offset.dot(self.tangent), offset.dot(self.bitangent)));
+        rec.tangent = 2.0 * self.radius * self.tangent;
+        rec.bitangent = 2.0 * self.radius * self.bitangent;
+        rec.material = Some(self.material.as_ref());
+        rec.barycentrics = None;
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // The disk reaches furthest along the axes most perpendicular to its normal.
+        let n = self.normal;
+        let extent = self.radius
+            * vec3a(
+                (1.0 - n.x * n.x).max(0.0).sqrt(),
+                (1.0 - n.y * n.y).max(0.0).sqrt(),
+                (1.0 - n.z * n.z).max(0.0).sqrt(),
+            );
+        Aabb::from_points([self.center - extent, self.center + extent])
+    }
+
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        if self.emission() == Color::ZERO {
+            return Vec::new();
+        }
+        vec![(0, Arc::new(self.clone()))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn hit_within_radius() {
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        let disk = Disk::new(vec3a(0.0, 1.0, 0.0), vec3a(0.0, -1.0, 0.0), 0.5, material);
+        let mut rec = HitPayload::new();
+        let r = Ray::new(vec3a(0.3, 0.0, 0.3), vec3a(0.0, 1.0, 0.0));
+        assert!(disk.hit(&r, 0.0, f32::INFINITY, &mut rec));
+        assert_eq!(rec.hit_distance, 1.0);
+        assert!(rec.front_face);
+        assert_eq!(rec.world_normal, vec3a(0.0, -1.0, 0.0));
+
+        // Inside the square around the disk, but outside of the disk.
+        let miss = Ray::new(vec3a(0.4, 0.0, 0.4), vec3a(0.0, 1.0, 0.0));
+        assert!(!disk.hit(&miss, 0.0, f32::INFINITY, &mut rec));
+
+        let aabb = disk.bounding_box();
+        assert!((aabb.min - vec3a(-0.5, 1.0, -0.5)).length() < 1e-6);
+        assert!((aabb.max - vec3a(0.5, 1.0, 0.5)).length() < 1e-6);
+    }
+
+    #[test]
+    fn samples_lie_on_disk() {
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        let normal = vec3a(1.0, 1.0, 0.0).normalize();
+        let disk = Disk::new(Vec3A::ONE, normal, 2.0, material);
+        for u in [vec2(0.0, 0.0), vec2(0.9, 0.1), vec2(0.3, 0.99)] {
+            let point = disk.sample_point(u);
+            let offset = point.position - Vec3A::ONE;
+            assert!(offset.dot(normal).abs() < 1e-5);
+            assert!(offset.length() <= 2.0 + 1e-5);
+        }
+    }
+}
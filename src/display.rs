@@ -0,0 +1,229 @@
+use crate::{util, Color};
+use glam::*;
+use std::{fmt, str::FromStr};
+
+/// Operator compressing radiance into the range a display can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// Clip each channel at 1.
+    Clamp,
+    /// `L / (1 + L)` on the luminance, which never reaches white.
+    Reinhard,
+    /// Reinhard with the luminance reaching white at the white point.
+    ExtendedReinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+    AcesFilmic,
+    /// Troy Sobotka's AgX, which desaturates bright colors smoothly towards white.
+    AgX,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 5] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::ExtendedReinhard,
+        ToneMapper::AcesFilmic,
+        ToneMapper::AgX,
+    ];
+
+    /// Name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMapper::Clamp => "clamp",
+            ToneMapper::Reinhard => "reinhard",
+            ToneMapper::ExtendedReinhard => "extended-reinhard",
+            ToneMapper::AcesFilmic => "aces",
+            ToneMapper::AgX => "agx",
+        }
+    }
+
+    /// Map linear radiance to linear display values in [0, 1].
+    pub fn apply(self, c: Color, white_point: f32) -> Color {
+        let c = c.max(Color::ZERO);
+        let mapped = match self {
+            ToneMapper::Clamp => c,
+            ToneMapper::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMapper::ExtendedReinhard => {
+                let white_squared = white_point * white_point;
+                scale_luminance(c, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            }
+            ToneMapper::AcesFilmic => aces_filmic(c),
+            ToneMapper::AgX => agx(c),
+        };
+        mapped.clamp(Color::ZERO, Color::ONE)
+    }
+}
+
+impl fmt::Display for ToneMapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ToneMapper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|tone_mapper| tone_mapper.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|t| t.name()).collect();
+                format!(
+                    "Unknown tone mapper '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Turns accumulated radiance into 8-bit sRGB pixels. Unlike the render settings, changing
+/// it doesn't invalidate the samples accumulated so far.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops. Radiance is scaled by `2^exposure`.
+    pub exposure: f32,
+    pub tone_mapper: ToneMapper,
+    /// Exposed luminance which the extended Reinhard operator maps to white.
+    pub white_point: f32,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+            white_point: 4.0,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// Expose, tone map and sRGB encode radiance, then quantize it to 8 bits per channel.
+    pub fn apply(&self, radiance: Color) -> [u8; 3] {
+        let exposed = radiance * self.exposure.exp2();
+        let display = self.tone_mapper.apply(exposed, self.white_point);
+        display
+            .to_array()
+            .map(|c| (util::linear_to_srgb(c) * 255.0).round() as u8)
+    }
+}
+
+/// Scale a color so that its luminance is mapped by `f`, keeping its hue.
+fn scale_luminance(c: Color, f: impl Fn(f32) -> f32) -> Color {
+    let l = util::luminance(c);
+    if l <= 0.0 {
+        return Color::ZERO;
+    }
+    c * (f(l) / l)
+}
+
+fn aces_filmic(c: Color) -> Color {
+    // sRGB to the RRT's input space, and the ODT's output space back to sRGB.
+    let input = Mat3A::from_cols_array(&[
+        0.59719, 0.07600, 0.02840, //
+        0.35458, 0.90834, 0.13383, //
+        0.04823, 0.01566, 0.83777,
+    ]);
+    let output = Mat3A::from_cols_array(&[
+        1.60475, -0.10208, -0.00327, //
+        -0.53108, 1.10813, -0.07276, //
+        -0.07367, -0.00605, 1.07602,
+    ]);
+
+    let v = input * c;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+    output * (a / b)
+}
+
+fn agx(c: Color) -> Color {
+    // Inset of the primaries, which bleeds bright saturated colors into the other channels.
+    let inset = Mat3A::from_cols(
+        vec3a(0.84247905, 0.042328242, 0.042375654),
+        vec3a(0.0784336, 0.87846863, 0.0784336),
+        vec3a(0.079223745, 0.07916613, 0.879143),
+    );
+    let outset = Mat3A::from_cols(
+        vec3a(1.196879, -0.052896854, -0.052971635),
+        vec3a(-0.09802088, 1.1519032, -0.09804345),
+        vec3a(-0.09902974, -0.098961174, 1.1510737),
+    );
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    // Encode in log2 between the minimum and maximum exposures.
+    let v = inset * c;
+    let v = Vec3A::from(v.to_array().map(|x| x.max(1e-10).log2()));
+    let x = ((v - MIN_EV) / (MAX_EV - MIN_EV)).clamp(Vec3A::ZERO, Vec3A::ONE);
+
+    // Polynomial approximation of the sigmoid contrast curve.
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+
+    // The curve's output is encoded with a gamma of 2.2.
+    let v = (outset * curve).max(Vec3A::ZERO);
+    Vec3A::from(v.to_array().map(|x| x.powf(2.2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_encoding() {
+        let transform = DisplayTransform::default();
+        assert_eq!(transform.apply(Color::ZERO), [0, 0, 0]);
+        assert_eq!(transform.apply(Color::ONE), [255, 255, 255]);
+        // Middle grey is encoded brighter than half.
+        assert_eq!(transform.apply(Color::splat(0.18)), [118, 118, 118]);
+        // Values above 1 are clipped instead of wrapping around.
+        assert_eq!(transform.apply(Color::splat(3.0)), [255, 255, 255]);
+        for c in [0.0, 0.002, 0.1, 0.5, 1.0] {
+            assert!((util::srgb_to_linear(util::linear_to_srgb(c)) - c).abs() < 1e-5);
+        }
+
+        // One stop of exposure doubles the radiance.
+        let exposed = DisplayTransform {
+            exposure: 1.0,
+            ..transform
+        };
+        assert_eq!(
+            exposed.apply(Color::splat(0.25)),
+            transform.apply(Color::splat(0.5))
+        );
+    }
+
+    #[test]
+    fn tone_mappers_are_monotonic() {
+        for tone_mapper in ToneMapper::ALL {
+            let mut previous = -1.0;
+            for i in 0..=64 {
+                let radiance = 2f32.powf(i as f32 / 4.0 - 8.0);
+                let mapped = tone_mapper.apply(Color::splat(radiance), 4.0);
+                let l = util::luminance(mapped);
+                assert!(l >= previous, "{} decreases at {}", tone_mapper, radiance);
+                assert!(mapped.cmple(Color::ONE).all() && mapped.cmpge(Color::ZERO).all());
+                previous = l;
+            }
+            // Black stays (nearly) black.
+            assert!(tone_mapper.apply(Color::ZERO, 4.0).max_element() < 1e-3);
+        }
+
+        // The extended operator reaches white at the white point.
+        let white = ToneMapper::ExtendedReinhard.apply(Color::splat(4.0), 4.0);
+        assert!((white - Color::ONE).abs().max_element() < 1e-5);
+    }
+
+    #[test]
+    fn parse_names() {
+        for tone_mapper in ToneMapper::ALL {
+            assert_eq!(tone_mapper.name().parse(), Ok(tone_mapper));
+        }
+        assert!("filmic".parse::<ToneMapper>().is_err());
+    }
+}
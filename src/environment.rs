@@ -0,0 +1,391 @@
+use crate::{hdr_image::HdrImage, sky::Sky, util, Color};
+use glam::*;
+use rand::Rng;
+use std::{error::Error, f32::consts::PI, path::Path, sync::Arc};
+
+/// Radiance arriving from infinitely far away, seen by rays which escape the scene.
+#[derive(Debug, Clone)]
+pub enum Background {
+    Constant(Color),
+    /// Sky blending from `bottom` straight down to `top` straight up.
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    /// Equirectangular image covering every direction.
+    Map(Arc<EnvironmentMap>),
+    /// Procedural daylight sky with a sun.
+    Sky(Sky),
+}
+
+/// The background of a scene, which also lights it.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub background: Background,
+    /// Scale applied to the radiance of the background.
+    pub intensity: f32,
+    /// Rotation of the background about the y axis, in radians.
+    pub rotation: f32,
+    /// Whether camera rays see the background. A hidden background still lights the scene
+    /// and shows up in reflections.
+    pub visible: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            background: Background::Constant(Color::ZERO),
+            intensity: 1.0,
+            rotation: 0.0,
+            visible: true,
+        }
+    }
+}
+
+/// A direction sampled towards the environment.
+pub struct EnvironmentSample {
+    pub direction: Vec3A,
+    pub radiance: Color,
+    /// Density of the direction with respect to solid angle.
+    pub pdf: f32,
+}
+
+impl Environment {
+    /// Whether the environment doesn't emit any light, so it's not worth sampling.
+    pub fn is_black(&self) -> bool {
+        self.intensity <= 0.0
+            || match &self.background {
+                Background::Constant(color) => *color == Color::ZERO,
+                Background::Gradient { bottom, top } => {
+                    *bottom == Color::ZERO && *top == Color::ZERO
+                }
+                Background::Map(map) => map.is_black(),
+                Background::Sky(_) => false,
+            }
+    }
+
+    /// Radiance arriving from the given direction, which doesn't need to be normalized.
+    pub fn radiance(&self, direction: Vec3A) -> Color {
+        let local = self.to_local(direction.normalize());
+        let radiance = match &self.background {
+            Background::Constant(color) => *color,
+            Background::Gradient { bottom, top } => bottom.lerp(*top, 0.5 * (local.y + 1.0)),
+            Background::Map(map) => map.lookup(direction_to_uv(local)),
+            Background::Sky(sky) => sky.radiance(local),
+        };
+        radiance * self.intensity
+    }
+
+    /// Sample a direction towards the environment. Maps are sampled proportionally to the
+    /// luminance of their texels, skies by picking either the sun or the sky, and uniform
+    /// backgrounds uniformly over the sphere.
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<EnvironmentSample> {
+        if self.is_black() {
+            return None;
+        }
+
+        let u = vec2(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        let (local, pdf) = match &self.background {
+            Background::Map(map) => {
+                let (uv, pdf_uv) = map.sample(u);
+                let local = uv_to_direction(uv);
+                (local, uv_pdf_to_solid_angle(pdf_uv, local))
+            }
+            Background::Sky(sky) => sky.sample(u),
+            _ => {
+                let y = 1.0 - 2.0 * u.x;
+                let r = (1.0 - y * y).max(0.0).sqrt();
+                let phi = 2.0 * PI * u.y;
+                (vec3a(r * phi.cos(), y, r * phi.sin()), 1.0 / (4.0 * PI))
+            }
+        };
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let direction = self.to_world(local);
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.radiance(direction),
+            pdf,
+        })
+    }
+
+    /// Solid angle density with which `sample` generates the given direction.
+    pub fn pdf(&self, direction: Vec3A) -> f32 {
+        if self.is_black() {
+            return 0.0;
+        }
+        match &self.background {
+            Background::Map(map) => {
+                let local = self.to_local(direction.normalize());
+                uv_pdf_to_solid_angle(map.pdf(direction_to_uv(local)), local)
+            }
+            Background::Sky(sky) => sky.pdf(self.to_local(direction.normalize())),
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+
+    fn to_local(&self, direction: Vec3A) -> Vec3A {
+        Mat3A::from_rotation_y(-self.rotation) * direction
+    }
+
+    fn to_world(&self, direction: Vec3A) -> Vec3A {
+        Mat3A::from_rotation_y(self.rotation) * direction
+    }
+}
+
+/// An equirectangular environment image with the distributions for importance sampling it.
+/// The center of the image lies in the -z direction, and the top row straight up.
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    image: HdrImage,
+    /// Distribution of the columns within each row.
+    rows: Vec<Distribution1D>,
+    /// Distribution of the rows.
+    marginal: Distribution1D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage) -> Self {
+        let (width, height) = (image.width(), image.height());
+        assert!(width > 0 && height > 0, "Environment map must not be empty");
+
+        // Texels near the poles cover less solid angle, so they're picked less often.
+        let rows: Vec<Distribution1D> = image
+            .pixels()
+            .chunks(width)
+            .enumerate()
+            .map(|(y, row)| {
+                let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+                let weights = row
+                    .iter()
+                    .map(|c| util::luminance(*c).max(0.0) * sin_theta)
+                    .collect();
+                Distribution1D::new(weights)
+            })
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+
+        Self {
+            image,
+            rows,
+            marginal,
+        }
+    }
+
+    /// Load an equirectangular `.hdr` or `.exr` image.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let image = HdrImage::load(path)?;
+        if image.width() == 0 || image.height() == 0 {
+            return Err("Environment map is empty".into());
+        }
+        Ok(Self::new(image))
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.marginal.integral <= 0.0
+    }
+
+    fn texel(&self, uv: Vec2) -> (usize, usize) {
+        let x = (uv.x * self.image.width() as f32) as usize;
+        let y = (uv.y * self.image.height() as f32) as usize;
+        (
+            x.min(self.image.width() - 1),
+            y.min(self.image.height() - 1),
+        )
+    }
+
+    /// Radiance of the texel containing the given texture coordinates.
+    fn lookup(&self, uv: Vec2) -> Color {
+        let (x, y) = self.texel(uv);
+        self.image.pixels()[x + y * self.image.width()]
+    }
+
+    /// Sample texture coordinates proportionally to the luminance of the texels, weighted by
+    /// their solid angle. Returns the coordinates and their density.
+    fn sample(&self, u: Vec2) -> (Vec2, f32) {
+        let (v, pdf_v, y) = self.marginal.sample(u.y);
+        let (u, pdf_u, _) = self.rows[y].sample(u.x);
+        (vec2(u, v), pdf_u * pdf_v)
+    }
+
+    /// Density with which `sample` generates the given texture coordinates.
+    fn pdf(&self, uv: Vec2) -> f32 {
+        let (x, y) = self.texel(uv);
+        self.marginal.pdf(y) * self.rows[y].pdf(x)
+    }
+}
+
+/// Piecewise constant distribution over [0, 1), with one bucket per weight.
+#[derive(Debug)]
+struct Distribution1D {
+    weights: Vec<f32>,
+    /// Normalized cumulative weights, starting at 0 and ending at 1.
+    cdf: Vec<f32>,
+    /// Average of the weights.
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(weights: Vec<f32>) -> Self {
+        let n = weights.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        let mut sum = 0.0f64;
+        for weight in &weights {
+            sum += *weight as f64;
+            cdf.push(sum as f32);
+        }
+        let integral = (sum / n as f64) as f32;
+        if sum > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= sum as f32);
+        } else {
+            // Without any weight, fall back to a uniform distribution.
+            cdf.iter_mut()
+                .enumerate()
+                .for_each(|(i, c)| *c = i as f32 / n as f32);
+        }
+        Self {
+            weights,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Map a uniform number to a point in [0, 1). Returns the point, its density and the
+    /// bucket it's in.
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.weights.len();
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            ((u - self.cdf[i]) / width).clamp(0.0, 1.0)
+        } else {
+            0.5
+        };
+        let x = ((i as f32 + offset) / n as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf(i), i)
+    }
+
+    fn pdf(&self, i: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.weights[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Equirectangular texture coordinates of a normalized direction.
+fn direction_to_uv(direction: Vec3A) -> Vec2 {
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+    vec2(u.rem_euclid(1.0), v)
+}
+
+fn uv_to_direction(uv: Vec2) -> Vec3A {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    vec3a(sin_theta * phi.sin(), cos_theta, -sin_theta * phi.cos())
+}
+
+/// Convert a density over the texture coordinates to one over solid angle.
+fn uv_pdf_to_solid_angle(pdf_uv: f32, direction: Vec3A) -> f32 {
+    let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    pdf_uv / (2.0 * PI * PI * sin_theta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    /// A dim map with a small bright spot.
+    fn spot_map() -> Environment {
+        let (width, height) = (32, 16);
+        let mut pixels = vec![Color::splat(0.1); width * height];
+        pixels[5 + 4 * width] = Color::splat(500.0);
+        Environment {
+            background: Background::Map(Arc::new(EnvironmentMap::new(HdrImage::new(
+                width, height, pixels,
+            )))),
+            rotation: 1.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mapping_round_trips() {
+        for uv in [vec2(0.5, 0.5), vec2(0.1, 0.3), vec2(0.9, 0.8)] {
+            assert!((direction_to_uv(uv_to_direction(uv)) - uv).length() < 1e-5);
+        }
+        // The center of the map is in front of the default camera.
+        assert!((uv_to_direction(vec2(0.5, 0.5)) - vec3a(0.0, 0.0, -1.0)).length() < 1e-6);
+        assert!(uv_to_direction(vec2(0.5, 0.0)).y > 0.999);
+    }
+
+    #[test]
+    fn sampling_integrates_the_radiance() {
+        // Integral of the spot map's radiance over the sphere, summed up texel by texel.
+        let (width, height) = (32, 16);
+        let mut spot_integral = 0.0;
+        for y in 0..height {
+            let theta = |y: usize| PI * y as f32 / height as f32;
+            let solid_angle = 2.0 * PI / width as f32 * (theta(y).cos() - theta(y + 1).cos());
+            spot_integral += solid_angle * 0.1 * width as f32;
+        }
+        let spot_solid_angle = 2.0 * PI / width as f32
+            * ((PI * 4.0 / height as f32).cos() - (PI * 5.0 / height as f32).cos());
+        spot_integral += spot_solid_angle * (500.0 - 0.1);
+
+        let sky = Environment {
+            background: Background::Gradient {
+                bottom: Color::ONE,
+                top: vec3a(0.5, 0.7, 1.0),
+            },
+            ..Default::default()
+        };
+
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(5);
+        for (environment, integral) in [
+            (spot_map(), Color::splat(spot_integral)),
+            (sky, 2.0 * PI * (Color::ONE + vec3a(0.5, 0.7, 1.0))),
+        ] {
+            let n = 100_000;
+            let mut estimate = Color::ZERO;
+            for _ in 0..n {
+                // Dividing by `pdf` checks that it agrees with the density of the samples.
+                let sample = environment.sample(&mut rng).unwrap();
+                estimate += sample.radiance / environment.pdf(sample.direction);
+            }
+            estimate /= n as f32;
+            assert!(
+                (estimate - integral).abs().max_element() < 0.01 * integral.max_element(),
+                "{} vs. {}",
+                estimate,
+                integral
+            );
+        }
+    }
+
+    #[test]
+    fn bright_texels_are_sampled_more_often() {
+        let environment = spot_map();
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(9);
+        let bright = (0..1000)
+            .filter(|_| environment.sample(&mut rng).unwrap().radiance.x > 100.0)
+            .count();
+        // The spot has most of the power, despite covering 1/512 of the texels.
+        assert!(bright > 500, "{}", bright);
+
+        // Black environments aren't sampled.
+        let black = Environment::default();
+        assert!(black.is_black());
+        assert!(black.sample(&mut rng).is_none());
+    }
+}
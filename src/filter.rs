@@ -0,0 +1,160 @@
+use glam::Vec2;
+use std::{f32::consts::PI, fmt, str::FromStr};
+
+/// Pixel reconstruction filter, which weights the samples around a pixel by their offset
+/// from its center. Every filter is separable, the product of a 1D filter along each axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Averages the samples inside the pixel. Sharp, but prone to aliasing.
+    Box,
+    /// Falls off linearly over one pixel.
+    Tent,
+    /// Truncated Gaussian with a standard deviation of half a pixel.
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3. Its negative lobes sharpen edges slightly.
+    Mitchell,
+    /// Four-term Blackman-Harris window, which is smooth with little ringing.
+    BlackmanHarris,
+}
+
+const GAUSSIAN_SIGMA: f32 = 0.5;
+const MITCHELL_B: f32 = 1.0 / 3.0;
+const MITCHELL_C: f32 = 1.0 / 3.0;
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::Box,
+        Filter::Tent,
+        Filter::Gaussian,
+        Filter::Mitchell,
+        Filter::BlackmanHarris,
+    ];
+
+    /// Name used on the command line and in scene files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell",
+            Filter::BlackmanHarris => "blackman-harris",
+        }
+    }
+
+    /// Offset in pixels along each axis beyond which samples have no weight.
+    pub fn radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell | Filter::BlackmanHarris => 2.0,
+        }
+    }
+
+    /// Weight of a sample at an offset in pixels from the center of a pixel. Weights are
+    /// relative, since the weighted sum of the samples is divided by the sum of the weights.
+    pub fn evaluate(self, offset: Vec2) -> f32 {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    fn evaluate_1d(self, x: f32) -> f32 {
+        let radius = self.radius();
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x / radius,
+            Filter::Gaussian => {
+                // Shifted down to reach zero at the radius.
+                let gaussian = |x: f32| (-x * x / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell => {
+                let (b, c) = (MITCHELL_B, MITCHELL_C);
+                let (x2, x3) = (x * x, x * x * x);
+                let weight = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                };
+                weight / 6.0
+            }
+            Filter::BlackmanHarris => {
+                // The window spans the diameter of the filter, and peaks in its middle.
+                let t = 2.0 * PI * (0.5 + 0.5 * x / radius);
+                0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|filter| filter.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "Unknown filter '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::vec2;
+
+    #[test]
+    fn filters_peak_at_the_center_and_vanish_at_the_radius() {
+        for filter in Filter::ALL {
+            let center = filter.evaluate(Vec2::ZERO);
+            assert!(center > 0.0, "{}", filter);
+            let radius = filter.radius();
+            for x in [0.25, 0.5, 1.0, 1.5] {
+                if x < radius {
+                    assert!(filter.evaluate(vec2(x, 0.0)) <= center, "{}", filter);
+                }
+            }
+            assert!(filter.evaluate(vec2(radius + 0.01, 0.0)).abs() < 1e-6);
+            assert!(filter.evaluate(vec2(0.0, -radius - 0.01)).abs() < 1e-6);
+            if filter != Filter::Box {
+                assert!(
+                    filter.evaluate(vec2(radius, 0.0)).abs() < 1e-3,
+                    "{}",
+                    filter
+                );
+            }
+            assert_eq!(filter.name().parse::<Filter>(), Ok(filter));
+        }
+        assert!("lanczos".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn mitchell_has_negative_lobes() {
+        let filter = Filter::Mitchell;
+        assert!((filter.evaluate_1d(0.0) - 8.0 / 9.0).abs() < 1e-6);
+        assert!(filter.evaluate_1d(1.5) < 0.0);
+        // The two pieces meet at one pixel.
+        assert!((filter.evaluate_1d(0.9999) - filter.evaluate_1d(1.0)).abs() < 1e-3);
+    }
+}
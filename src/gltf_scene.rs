@@ -0,0 +1,195 @@
+use crate::{
+    camera::Camera,
+    hittable_list::HittableList,
+    light::{Light, LightShape},
+    mesh::{load_gltf, GltfConverter, Mesh, MeshGeometry},
+};
+use easy_gltf::Projection;
+use glam::*;
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+/// The contents of a glTF file, imported as separate objects.
+pub struct GltfScene {
+    pub world: HittableList,
+    /// The first perspective camera in the file.
+    pub camera: Option<Camera>,
+}
+
+impl GltfScene {
+    /// Import every scene of a glTF file, including `KHR_lights_punctual` lights.
+    ///
+    /// Each model becomes a mesh of its own, placed with the transform of its node. Nodes
+    /// which use the same glTF mesh share its geometry.
+    pub fn load(
+        path: &str,
+        viewport_width: u32,
+        viewport_height: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (scenes, primitives) = load_gltf(path)?;
+
+        let mut world = HittableList::new();
+        let mut converter = GltfConverter::default();
+        let mut geometries: HashMap<(usize, usize), Arc<MeshGeometry>> = HashMap::new();
+        let models = scenes.iter().flat_map(|scene| &scene.models);
+        for (model, primitive) in models.zip(&primitives) {
+            // The loader has moved the vertices into the scene, so they're moved back to
+            // model space to be shared. Singular transforms can't be undone, and such
+            // models keep their own flattened geometry.
+            let transform = primitive.transform;
+            if !transform.matrix3.determinant().is_normal() {
+                world.add(Mesh::from_triangles(
+                    converter.triangles(model, &primitive.samplers)?,
+                ));
+                continue;
+            }
+
+            let key = (primitive.mesh, primitive.primitive);
+            let geometry = match geometries.get(&key) {
+                Some(geometry) => geometry.clone(),
+                None => {
+                    let to_model = transform.inverse();
+                    // The inverse transpose of the inverse transform.
+                    let normal_to_model = transform.matrix3.transpose();
+                    let triangles = converter
+                        .triangles(model, &primitive.samplers)?
+                        .iter()
+                        .map(|triangle| triangle.transformed(&to_model, &normal_to_model))
+                        .collect();
+                    let geometry = Arc::new(MeshGeometry::new(triangles));
+                    geometries.insert(key, geometry.clone());
+                    geometry
+                }
+            };
+            let mut mesh = Mesh::from_geometry(geometry);
+            mesh.set_transform(transform);
+            world.add(mesh);
+        }
+        for light in scenes.iter().flat_map(|scene| &scene.lights) {
+            world.add_light(convert_light(light));
+        }
+        world.build_bvh();
+
+        // Orthographic cameras aren't supported.
+        let camera = scenes
+            .iter()
+            .flat_map(|scene| &scene.cameras)
+            .find_map(|camera| match camera.projection {
+                Projection::Perspective { yfov, .. } => {
+                    // A missing far plane is infinite, which the projection matrix can't represent.
+                    let far = if camera.zfar.is_finite() {
+                        camera.zfar
+                    } else {
+                        camera.znear * 1e6
+                    };
+                    let mut converted = Camera::new(
+                        yfov.0.to_degrees(),
+                        camera.znear,
+                        far,
+                        viewport_width,
+                        viewport_height,
+                    );
+                    converted.set_position(vector(camera.position()));
+                    converted.set_forward_direction(vector(camera.forward()));
+                    Some(converted)
+                }
+                Projection::Orthographic { .. } => None,
+            });
+
+        Ok(Self { world, camera })
+    }
+}
+
+/// Convert a glTF light. The photometric intensities are used as radiometric ones.
+fn convert_light(light: &easy_gltf::Light) -> Light {
+    use easy_gltf::Light as GltfLight;
+    match light {
+        GltfLight::Point {
+            position,
+            color,
+            intensity,
+        } => Light::from_intensity(
+            LightShape::Point {
+                position: vector(*position),
+            },
+            vector(*color),
+            *intensity,
+        ),
+        GltfLight::Spot {
+            position,
+            direction,
+            color,
+            intensity,
+            inner_cone_angle,
+            outer_cone_angle,
+        } => Light::from_intensity(
+            LightShape::Spot {
+                position: vector(*position),
+                direction: vector(*direction).normalize(),
+                inner_angle: *inner_cone_angle,
+                outer_angle: *outer_cone_angle,
+            },
+            vector(*color),
+            *intensity,
+        ),
+        GltfLight::Directional {
+            direction,
+            color,
+            intensity,
+        } => Light::from_intensity(
+            LightShape::Directional {
+                direction: vector(*direction).normalize(),
+            },
+            vector(*color),
+            *intensity,
+        ),
+    }
+}
+
+/// Convert one of the loader's vectors.
+fn vector(v: impl Into<[f32; 3]>) -> Vec3A {
+    Vec3A::from(v.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::*, ray::Ray};
+
+    fn hit_along_z(world: &HittableList, x: f32, y: f32) -> Option<Vec3A> {
+        let mut rec = HitPayload::new();
+        rec.hit_distance = f32::INFINITY;
+        let ray = Ray::new(vec3a(x, y, 10.0), -Vec3A::Z);
+        world
+            .hit(&ray, 0.0, f32::INFINITY, &mut rec)
+            .then_some(rec.world_position)
+    }
+
+    #[test]
+    fn import_instances_and_camera() {
+        let scene = GltfScene::load("assets/fixtures/instanced_triangles.gltf", 16, 16).unwrap();
+        assert_eq!(scene.world.len(), 2);
+
+        // The first node moves the triangle left, the second one moves it right and its
+        // child scales it up.
+        let left = hit_along_z(&scene.world, -2.0, 0.0).unwrap();
+        assert!(left.abs_diff_eq(vec3a(-2.0, 0.0, 0.0), 1e-5));
+        let right = hit_along_z(&scene.world, 2.0, 0.75).unwrap();
+        assert!(right.abs_diff_eq(vec3a(2.0, 0.75, 0.0), 1e-5));
+        assert!(hit_along_z(&scene.world, -2.0, 0.75).is_none());
+        assert!(hit_along_z(&scene.world, 0.0, 0.0).is_none());
+
+        let camera = scene.camera.unwrap();
+        assert!(camera
+            .get_position()
+            .abs_diff_eq(vec3a(0.0, 0.0, 5.0), 1e-5));
+        assert!(camera.get_forward_direction().abs_diff_eq(-Vec3A::Z, 1e-5));
+    }
+
+    #[test]
+    fn reject_point_primitives() {
+        let err = GltfScene::load("assets/fixtures/points.gltf", 16, 16)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Unsupported primitive mode"));
+    }
+}
@@ -0,0 +1,473 @@
+use crate::Color;
+use glam::vec3a;
+use image::{
+    codecs::hdr::{HdrDecoder, HdrEncoder},
+    Rgb,
+};
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// Precision of the channels of an OpenEXR file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPrecision {
+    /// 16-bit floats, which are enough for most images and half the size.
+    Half,
+    Float,
+}
+
+/// File formats which keep linear radiance, including values above 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrFormat {
+    Exr(ExrPrecision),
+    /// Radiance RGBE, with an 8-bit mantissa per channel and a shared exponent.
+    Hdr,
+    /// Portable float map, with uncompressed 32-bit floats.
+    Pfm,
+}
+
+impl HdrFormat {
+    /// Format of a path with an `.exr`, `.hdr` or `.pfm` extension. EXR files use floats.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "exr" => Some(HdrFormat::Exr(ExrPrecision::Float)),
+            "hdr" => Some(HdrFormat::Hdr),
+            "pfm" => Some(HdrFormat::Pfm),
+            _ => None,
+        }
+    }
+    /// File extension of the format, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            HdrFormat::Exr(_) => "exr",
+            HdrFormat::Hdr => "hdr",
+            HdrFormat::Pfm => "pfm",
+        }
+    }
+}
+
+/// An image of linear RGB radiance. The first row is the top of the image.
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+#[allow(dead_code)]
+impl HdrImage {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// Load a Radiance `.hdr` or OpenEXR image, keeping the values above 1.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let (width, height, pixels) = match HdrFormat::from_path(path) {
+            Some(HdrFormat::Hdr) => {
+                // The generic decoder tone maps to 8 bits, so read the floats directly.
+                let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+                let metadata = decoder.metadata();
+                let pixels = decoder.read_image_hdr()?;
+                (metadata.width, metadata.height, pixels)
+            }
+            Some(HdrFormat::Exr(_)) => {
+                let image = image::open(path)?.into_rgb32f();
+                (
+                    image.width(),
+                    image.height(),
+                    image.pixels().copied().collect(),
+                )
+            }
+            _ => return Err(format!("'{}' is not an .hdr or .exr file", path.display()).into()),
+        };
+        let pixels = pixels.iter().map(|p| vec3a(p[0], p[1], p[2])).collect();
+        Ok(Self::new(width as usize, height as usize, pixels))
+    }
+
+    pub fn save(&self, path: &Path, format: HdrFormat) -> Result<(), Box<dyn Error>> {
+        let mut writer = create(path)?;
+        match format {
+            HdrFormat::Exr(precision) => self.write_exr(&mut writer, precision)?,
+            HdrFormat::Hdr => self.write_hdr(&mut writer)?,
+            HdrFormat::Pfm => self.write_pfm(&mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write an uncompressed scanline OpenEXR file with R, G and B channels.
+    pub fn write_exr(&self, w: &mut impl Write, precision: ExrPrecision) -> std::io::Result<()> {
+        let layer = ExrLayer {
+            name: "",
+            channels: &["R", "G", "B"],
+            image: self,
+        };
+        write_exr_layers(w, &[layer], precision)
+    }
+
+    /// Write a Radiance RGBE file. Negative values can't be represented and become zero.
+    pub fn write_hdr(&self, w: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let pixels: Vec<Rgb<f32>> = self
+            .pixels
+            .iter()
+            .map(|c| Rgb(c.max(Color::ZERO).to_array()))
+            .collect();
+        HdrEncoder::new(w).encode(&pixels, self.width, self.height)?;
+        Ok(())
+    }
+
+    /// Write a little-endian color PFM file, whose rows go from the bottom to the top.
+    pub fn write_pfm(&self, w: &mut impl Write) -> std::io::Result<()> {
+        // A negative scale means little-endian.
+        write!(w, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.pixels.chunks(self.width).rev() {
+            for pixel in row {
+                for value in pixel.to_array() {
+                    w.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An image stored in an OpenEXR file, whose channels are named `<name>.<channel>`. The
+/// channels of a layer without a name, such as the beauty image, are named `<channel>`.
+pub struct ExrLayer<'a> {
+    pub name: &'a str,
+    /// Names of the channels, taken from the components of the pixels in order.
+    pub channels: &'a [&'a str],
+    pub image: &'a HdrImage,
+}
+
+/// Write several images of the same size to a single OpenEXR file.
+pub fn save_exr_layers(
+    path: &Path,
+    layers: &[ExrLayer],
+    precision: ExrPrecision,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = create(path)?;
+    write_exr_layers(&mut writer, layers, precision)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write an uncompressed scanline OpenEXR file with the channels of every layer.
+pub fn write_exr_layers(
+    w: &mut impl Write,
+    layers: &[ExrLayer],
+    precision: ExrPrecision,
+) -> std::io::Result<()> {
+    let (width, height) = match layers.first() {
+        Some(layer) => (layer.image.width, layer.image.height),
+        None => (0, 0),
+    };
+    // Channels are stored in alphabetical order.
+    let mut channels = Vec::new();
+    for layer in layers {
+        assert_eq!((layer.image.width, layer.image.height), (width, height));
+        for (component, channel) in layer.channels.iter().enumerate() {
+            let name = if layer.name.is_empty() {
+                channel.to_string()
+            } else {
+                format!("{}.{}", layer.name, channel)
+            };
+            channels.push((name, layer.image, component));
+        }
+    }
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+    let (pixel_type, value_size) = match precision {
+        ExrPrecision::Half => (1u32, 2),
+        ExrPrecision::Float => (2u32, 4),
+    };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]); // Magic number.
+    header.extend_from_slice(&2u32.to_le_bytes()); // Version 2, single part scanlines.
+
+    let mut chlist = Vec::new();
+    for (name, _, _) in &channels {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&pixel_type.to_le_bytes());
+        // Perceptually linear flag and reserved bytes, then the x and y sampling.
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1u32.to_le_bytes());
+        chlist.extend_from_slice(&1u32.to_le_bytes());
+    }
+    chlist.push(0);
+    exr_attribute(&mut header, "channels", "chlist", &chlist);
+
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]); // Increasing y.
+    exr_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+    w.write_all(&header)?;
+
+    // Offsets of every scanline from the start of the file, which follow the header.
+    let line_size = width * channels.len() * value_size;
+    let chunk_size = 8 + line_size;
+    let first_chunk = header.len() + 8 * height;
+    for y in 0..height {
+        w.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height {
+        line.clear();
+        for (_, image, component) in &channels {
+            for pixel in &image.pixels[y * width..(y + 1) * width] {
+                let value = pixel[*component];
+                match precision {
+                    ExrPrecision::Half => line.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+                    ExrPrecision::Float => line.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as u32).to_le_bytes())?;
+        w.write_all(&line)?;
+    }
+    Ok(())
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, String> {
+    let file = File::create(path)
+        .map_err(|e| format!("{}: Failed to create image: {}", path.display(), e))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Append an attribute to an EXR header.
+fn exr_attribute(header: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(ty.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Convert to a 16-bit float, rounding to the nearest value. Values too large for half
+/// precision become infinite.
+fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinite, and NaN stays NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    // Rounds to the nearest value, and to an even mantissa on ties.
+    let round = |value: u32, shift: u32| {
+        let truncated = value >> shift;
+        let remainder = value & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if remainder > halfway || (remainder == halfway && truncated & 1 == 1) {
+            truncated + 1
+        } else {
+            truncated
+        }
+    };
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        sign | 0x7c00
+    } else if half_exponent > 0 {
+        // Rounding may carry into the exponent, which is still the correct result.
+        let bits = ((half_exponent as u32) << 10) + round(mantissa, 13);
+        sign | bits.min(0x7c00) as u16
+    } else {
+        // Subnormal half, in multiples of 2^-24.
+        let shift = (14 - half_exponent) as u32;
+        if shift > 24 {
+            return sign;
+        }
+        sign | round(mantissa | 0x80_0000, shift) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{vec3a, Vec3A};
+
+    /// A 2x2 image with values above 1 and a distinct value in each channel.
+    fn image() -> HdrImage {
+        HdrImage::new(
+            2,
+            2,
+            vec![
+                vec3a(1.0, 2.0, 3.0),
+                vec3a(0.5, 0.25, 100.0),
+                vec3a(0.0, 1e-3, 4096.0),
+                vec3a(7.0, 8.0, 9.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7e00, 0x7e00);
+        // The smallest subnormal, and ties rounding to even.
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let original = image();
+        for precision in [ExrPrecision::Half, ExrPrecision::Float] {
+            let mut bytes = Vec::new();
+            original.write_exr(&mut bytes, precision).unwrap();
+
+            let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::OpenExr)
+                .unwrap()
+                .into_rgb32f();
+            assert_eq!((decoded.width(), decoded.height()), (2, 2));
+            for (pixel, expected) in decoded.pixels().zip(original.pixels()) {
+                let error = (vec3a(pixel[0], pixel[1], pixel[2]) - *expected).abs();
+                // Half floats have 11 significant bits.
+                assert!(error.cmple(expected.abs() * 1e-3).all());
+            }
+        }
+    }
+
+    #[test]
+    fn exr_layers() {
+        let beauty = image();
+        let depth = HdrImage::new(2, 2, vec![Vec3A::splat(4.0); 4]);
+        let layers = [
+            ExrLayer {
+                name: "depth",
+                channels: &["Z"],
+                image: &depth,
+            },
+            ExrLayer {
+                name: "",
+                channels: &["R", "G", "B"],
+                image: &beauty,
+            },
+        ];
+        let mut bytes = Vec::new();
+        write_exr_layers(&mut bytes, &layers, ExrPrecision::Float).unwrap();
+
+        // Channels are sorted by name, with the layer name as prefix.
+        let find = |name: &[u8]| bytes.windows(name.len()).position(|w| w == name).unwrap();
+        assert!(find(b"B\0") < find(b"R\0"));
+        assert!(find(b"R\0") < find(b"depth.Z\0"));
+
+        // Readers without layer support still find the beauty image.
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::OpenExr)
+            .unwrap()
+            .into_rgb32f();
+        for (pixel, expected) in decoded.pixels().zip(beauty.pixels()) {
+            assert_eq!(vec3a(pixel[0], pixel[1], pixel[2]), *expected);
+        }
+    }
+
+    #[test]
+    fn load_saved_images() {
+        let original = image();
+        for format in [HdrFormat::Exr(ExrPrecision::Float), HdrFormat::Hdr] {
+            // The process id keeps concurrent test runs from sharing the file.
+            let name = format!(
+                "leia_load_test_{}.{}",
+                std::process::id(),
+                format.extension()
+            );
+            let path = std::env::temp_dir().join(name);
+            original.save(&path, format).unwrap();
+            let loaded = HdrImage::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!((loaded.width(), loaded.height()), (2, 2));
+            for (pixel, expected) in loaded.pixels().iter().zip(original.pixels()) {
+                // RGBE keeps 8 bits of precision relative to the largest channel.
+                let error = (*pixel - *expected).abs();
+                assert!(error.max_element() <= expected.max_element() / 128.0);
+            }
+        }
+        assert!(HdrImage::load(Path::new("image.png")).is_err());
+    }
+
+    #[test]
+    fn pfm_rows_start_at_the_bottom() {
+        let mut bytes = Vec::new();
+        image().write_pfm(&mut bytes).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 2 * 2 * 3 * 4);
+        // The first pixel is the bottom left one.
+        let first = f32::from_le_bytes(bytes[header.len()..header.len() + 4].try_into().unwrap());
+        assert_eq!(first, 0.0);
+        let last = f32::from_le_bytes(bytes[bytes.len() - 4..].try_into().unwrap());
+        assert_eq!(last, 100.0);
+    }
+
+    #[test]
+    fn hdr_keeps_values_above_one() {
+        let mut bytes = Vec::new();
+        image().write_hdr(&mut bytes).unwrap();
+        // The generic decoder tone maps to 8 bits, so read the floats directly.
+        let decoded = image::codecs::hdr::HdrDecoder::new(bytes.as_slice())
+            .unwrap()
+            .read_image_hdr()
+            .unwrap();
+        let pixel = decoded[3];
+        // RGBE keeps 8 bits of precision relative to the largest channel.
+        assert!((pixel[2] - 9.0).abs() < 9.0 / 128.0);
+        assert!(pixel[0] > 1.0);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::{
+    aov::Aov,
+    denoise::Denoiser,
+    display::{DisplayTransform, ToneMapper},
+    filter::Filter,
+    hdr_image::{self, ExrLayer, ExrPrecision, HdrFormat},
+    renderer::{RenderMode, Renderer},
+    scene::Scene,
+    IMG_HEIGHT, IMG_WIDTH,
+};
+use image::RgbaImage;
+use rand::SeedableRng;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
+};
+
+const USAGE: &str = "\
+Usage: leia render [OPTIONS]
+
+Options:
+    --scene <path>      Scene file or glTF file to render (default: scenes/cornell.toml)
+    -o, --output <path> Image to write the result to (default: out.png). Linear radiance
+                        is written to .exr, .hdr and .pfm files, without clamping
+    --half              Write .exr files with 16-bit instead of 32-bit floats
+    --aovs <names>      Comma separated AOVs to write, or 'all': albedo, normal, depth,
+                        position, object-id, direct, indirect and emission. They're
+                        written as layers of .exr outputs, and to <output>.<aov>.exr
+                        or the output's HDR format otherwise
+    --separate-aovs     Write AOVs to separate files even if the output is an .exr file
+    --denoise           Denoise the result, guided by the albedo and normals
+    --exposure <stops>  Exposure adjustment of 8-bit images (default: 0)
+    --tonemap <name>    Tone mapper of 8-bit images: clamp, reinhard, extended-reinhard,
+                        aces or agx (default: clamp)
+    --white-point <l>   Luminance mapped to white by extended-reinhard (default: 4)
+    --mode <name>       What to render: path-traced, shading-normals, geometric-normals,
+                        depth, albedo, object-index, barycentrics, bvh-heatmap,
+                        triangle-tests or time (default: path-traced)
+    --heat-map-max <v>  Depth, count or microseconds shown as red in heat maps (default: 64)
+    --filter <name>     Pixel filter: box, tent, gaussian, mitchell or blackman-harris
+                        (default: the scene's, or box)
+    --width <pixels>    Width of the rendered image
+    --height <pixels>   Height of the rendered image
+    -n, --frames <n>    Number of frames to accumulate (default: 64)
+    --seed <seed>       Seed for the master RNG, for reproducible renders
+    -h, --help          Print this message instead of rendering";
+
+/// Options for an offline render.
+pub struct HeadlessOptions {
+    pub scene_path: String,
+    pub output_path: String,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+    pub seed: Option<u64>,
+    pub half: bool,
+    pub aovs: Vec<Aov>,
+    pub separate_aovs: bool,
+    pub denoise: bool,
+    pub display_transform: DisplayTransform,
+    /// Overrides of the scene's render settings.
+    pub render_mode: Option<RenderMode>,
+    pub heat_map_max: Option<f32>,
+    pub filter: Option<Filter>,
+    /// Print the usage instead of rendering.
+    pub help: bool,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            scene_path: String::from("scenes/cornell.toml"),
+            output_path: String::from("out.png"),
+            width: IMG_WIDTH,
+            height: IMG_HEIGHT,
+            frames: 64,
+            seed: None,
+            half: false,
+            aovs: Vec::new(),
+            separate_aovs: false,
+            denoise: false,
+            display_transform: DisplayTransform::default(),
+            render_mode: None,
+            heat_map_max: None,
+            filter: None,
+            help: false,
+        }
+    }
+}
+
+impl HeadlessOptions {
+    /// Parse options from the command line arguments following the `render` subcommand.
+    pub fn from_args(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut options = Self::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--half" => {
+                    options.half = true;
+                    continue;
+                }
+                "--separate-aovs" => {
+                    options.separate_aovs = true;
+                    continue;
+                }
+                "--denoise" => {
+                    options.denoise = true;
+                    continue;
+                }
+                "-h" | "--help" => {
+                    options.help = true;
+                    continue;
+                }
+                _ => {}
+            }
+
+            // Every other option takes exactly one value.
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for '{}'\n\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--scene" => options.scene_path = value()?.clone(),
+                "-o" | "--output" => options.output_path = value()?.clone(),
+                "--width" => options.width = parse_value(arg, value()?)?,
+                "--height" => options.height = parse_value(arg, value()?)?,
+                "-n" | "--frames" => options.frames = parse_value(arg, value()?)?,
+                "--seed" => options.seed = Some(parse_value(arg, value()?)?),
+                "--exposure" => options.display_transform.exposure = parse_value(arg, value()?)?,
+                "--tonemap" => {
+                    options.display_transform.tone_mapper = value()?.parse::<ToneMapper>()?
+                }
+                "--white-point" => {
+                    options.display_transform.white_point = parse_value(arg, value()?)?
+                }
+                "--aovs" => options.aovs = parse_aovs(value()?)?,
+                "--mode" => options.render_mode = Some(value()?.parse::<RenderMode>()?),
+                "--heat-map-max" => options.heat_map_max = Some(parse_value(arg, value()?)?),
+                "--filter" => options.filter = Some(value()?.parse::<Filter>()?),
+                _ => return Err(format!("Unknown option '{}'\n\n{}", arg, USAGE).into()),
+            }
+        }
+
+        if options.width == 0 || options.height == 0 {
+            return Err("Image dimensions must be non-zero".into());
+        }
+        if options.frames == 0 {
+            return Err("At least one frame must be rendered".into());
+        }
+        let display = &options.display_transform;
+        if !display.exposure.is_finite() {
+            return Err("Exposure must be finite".into());
+        }
+        if display.white_point <= 0.0 || !display.white_point.is_finite() {
+            return Err("White point must be positive".into());
+        }
+        if let Some(max) = options.heat_map_max {
+            if max <= 0.0 || !max.is_finite() {
+                return Err("Heat map maximum must be positive".into());
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Parse a comma separated list of AOVs.
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, String> {
+    if value == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    value.split(',').map(|name| name.trim().parse()).collect()
+}
+
+/// Path of a separate AOV image, next to the output.
+fn aov_path(output_path: &Path, aov: Aov, extension: &str) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    output_path.with_file_name(format!("{}.{}.{}", stem, aov.name(), extension))
+}
+
+/// Parse the value given to a command line option.
+fn parse_value<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for '{}'", value, option))
+}
+
+/// Render a scene without a window and write the accumulated image to disk.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = HeadlessOptions::from_args(args)?;
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    // Init the scene.
+    let Scene {
+        world: scene,
+        camera,
+        mut settings,
+    } = Scene::load(&options.scene_path, options.width, options.height)?;
+    eprintln!(
+        "Loaded '{}' ({} area lights, {} punctual lights)",
+        options.scene_path,
+        scene.lights().len(),
+        scene.lights().punctual().len()
+    );
+
+    if let Some(mode) = options.render_mode {
+        settings.render_mode = mode;
+    }
+    if let Some(max) = options.heat_map_max {
+        settings.heat_map_max = max;
+    }
+    if let Some(filter) = options.filter {
+        settings.filter = filter;
+    }
+
+    let mut renderer = Renderer::new(options.width as usize, options.height as usize);
+    renderer.set_settings(settings);
+    renderer.set_display_transform(options.display_transform);
+    renderer.set_aovs_enabled(!options.aovs.is_empty());
+    if options.denoise {
+        renderer.set_denoiser(Some(Denoiser::default()));
+    }
+
+    // Master RNG for seeding the per-pixel RNGs.
+    let mut master_rng = match options.seed {
+        Some(seed) => rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(seed),
+        None => rand_xoshiro::Xoshiro256PlusPlus::from_entropy(),
+    };
+
+    let now = Instant::now();
+    for frame in 0..options.frames {
+        renderer.render(&scene, &camera, &mut master_rng);
+        eprint!("\rFrames rendered: {}/{}", frame + 1, options.frames);
+    }
+    let elapsed = now.elapsed();
+    eprintln!("\nDone!");
+    eprintln!("Time taken: {}ms", elapsed.as_millis());
+
+    let output_path = Path::new(&options.output_path);
+    let precision = if options.half {
+        ExrPrecision::Half
+    } else {
+        ExrPrecision::Float
+    };
+    let format = HdrFormat::from_path(output_path).map(|format| match format {
+        HdrFormat::Exr(_) => HdrFormat::Exr(precision),
+        format => format,
+    });
+    // The 8-bit final image is already denoised, but the linear one never is.
+    let beauty = renderer
+        .get_denoised_image()
+        .unwrap_or_else(|| renderer.get_linear_image());
+    let aov_images = options
+        .aovs
+        .iter()
+        .filter_map(|&aov| Some((aov, renderer.get_aov_image(aov)?)))
+        .collect::<Vec<_>>();
+
+    match format {
+        Some(HdrFormat::Exr(precision)) if !options.separate_aovs => {
+            // Store the AOVs as layers next to the beauty image.
+            let mut layers = vec![ExrLayer {
+                name: "",
+                channels: &["R", "G", "B"],
+                image: &beauty,
+            }];
+            layers.extend(aov_images.iter().map(|(aov, image)| ExrLayer {
+                name: aov.name(),
+                channels: aov.channels(),
+                image,
+            }));
+            hdr_image::save_exr_layers(output_path, &layers, precision)?;
+            eprintln!("Saved '{}'", options.output_path);
+            return Ok(());
+        }
+        Some(format) => beauty.save(output_path, format)?,
+        None => {
+            // The renderer's first row is the bottom of the image, so flip it before saving.
+            let image = RgbaImage::from_raw(
+                options.width,
+                options.height,
+                renderer.get_final_image().clone(),
+            )
+            .ok_or("Final image does not match the requested dimensions")?;
+            image::imageops::flip_vertical(&image).save(output_path)?;
+        }
+    }
+    eprintln!("Saved '{}'", options.output_path);
+
+    // 8-bit images can't hold AOVs such as depth, so those are written to EXR files.
+    let aov_format = format.unwrap_or(HdrFormat::Exr(precision));
+    for (aov, image) in &aov_images {
+        let path = aov_path(output_path, *aov, aov_format.extension());
+        match aov_format {
+            HdrFormat::Exr(precision) => {
+                // Single channel AOVs keep their channel name, e.g. `Z` for depth.
+                let layer = ExrLayer {
+                    name: "",
+                    channels: aov.channels(),
+                    image,
+                };
+                hdr_image::save_exr_layers(&path, &[layer], precision)?;
+            }
+            format => image.save(&path, format)?,
+        }
+        eprintln!("Saved '{}'", path.display());
+    }
+
+    Ok(())
+}
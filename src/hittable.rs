@@ -1,32 +1,71 @@
+use crate::aabb::Aabb;
+use crate::light::AreaLight;
+use crate::material::Material;
 use crate::ray::*;
-use crate::Color;
 use glam::*;
+use std::sync::Arc;
 
-// TODO: Should this store material information?
-pub struct HitPayload {
+#[derive(Clone, Copy)]
+pub struct HitPayload<'a> {
     pub world_position: Vec3A,
-    pub world_normal: Vec3A,
+    pub world_normal: Vec3A,   // Geometric normal of the surface.
+    pub shading_normal: Vec3A, // Normal used by materials, e.g. interpolated from vertex normals.
+    pub uv: Vec2,              // Texture coordinates of the hit.
+    // Directions in which the texture coordinates increase, for normal mapping.
+    // Zero when the surface has no texture coordinates.
+    pub tangent: Vec3A,
+    pub bitangent: Vec3A,
     pub hit_distance: f32,
     pub front_face: bool, // Whether the hit was on the "front face" of the object.
     pub object_index: usize, // Index of the hittable object which was hit.
-    pub albedo: Color,
-    pub emissive: Color,
+    // Index of the primitive within the object, e.g. the triangle of a mesh. Together with
+    // the object index it identifies the emitter which was hit.
+    pub primitive_index: usize,
+    pub material: Option<&'a dyn Material>, // Material of the surface which was hit.
+    // Barycentric coordinates of the second and third vertex, if a triangle was hit.
+    pub barycentrics: Option<Vec2>,
+    // Work done to find the hit, summed over every object tested.
+    pub stats: TraversalStats,
 }
 
-impl HitPayload {
+/// Counters of the work done while tracing a ray, for debug visualizations.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraversalStats {
+    pub bvh_node_visits: u32,
+    pub triangle_tests: u32,
+}
+
+impl<'a> HitPayload<'a> {
     /// Create a new hit record.
     pub fn new() -> Self {
         Self {
             world_position: Vec3A::ZERO,
             world_normal: Vec3A::ZERO,
+            shading_normal: Vec3A::ZERO,
+            uv: Vec2::ZERO,
+            tangent: Vec3A::ZERO,
+            bitangent: Vec3A::ZERO,
             hit_distance: -1.0,
             front_face: false,
             object_index: usize::MAX, // This represents an invalid index.
-            albedo: Color::new(0.0, 1.0, 0.0), // TODO: Should the default be something else?
-            emissive: Color::ZERO,
+            primitive_index: 0,
+            material: None,
+            barycentrics: None,
+            stats: TraversalStats::default(),
         }
     }
 
+    /// Create a ray leaving the hit point in the given direction.
+    /// The origin is nudged off the surface so the ray doesn't hit it again.
+    pub fn spawn_ray(&self, direction: Vec3A) -> Ray {
+        let offset = if direction.dot(self.world_normal) > 0.0 {
+            self.world_normal
+        } else {
+            -self.world_normal
+        };
+        Ray::new(self.world_position + offset * 1e-4, direction)
+    }
+
     /// We want normals recorded in hits to always be opposite of incident rays.
     /// This function will determine whether a hit occurred on the front or back face
     /// of an object, and will ensure that the recorded normal of the hit is opposite
@@ -42,9 +81,44 @@ impl HitPayload {
             -object_normal
             // vec3(1.0, 0.0, 0.0)
         };
+        self.shading_normal = self.world_normal;
+        // Objects made of several primitives set the index after the primitive was hit.
+        self.primitive_index = 0;
+    }
+
+    /// Set a shading normal differing from the geometric normal. Must be called after
+    /// `set_face_normal`. The normal is flipped to the side of the geometric normal, and
+    /// ignored if it would put the viewer below the shading surface.
+    pub fn set_shading_normal(&mut self, r: &Ray, normal: Vec3A) {
+        let normal = if normal.dot(self.world_normal) < 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        self.shading_normal = if normal.dot(r.direction()) < 0.0 {
+            normal
+        } else {
+            self.world_normal
+        };
+    }
+
+    /// Whether a direction leaving the surface is on the same side of both the geometric and
+    /// the shading surface. Light arriving from other directions would leak through the
+    /// surface, since shading normals don't match the actual geometry.
+    pub fn is_consistent(&self, direction: Vec3A) -> bool {
+        self.world_normal.dot(direction) * self.shading_normal.dot(direction) > 0.0
     }
 }
 
 pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload) -> bool;
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool;
+
+    /// World space bounds of the object, used for building acceleration structures.
+    fn bounding_box(&self) -> Aabb;
+
+    /// World space surfaces of the object which emit light, used for sampling direct lighting.
+    /// Each is paired with the index of its primitive, as recorded by hits on it.
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        Vec::new()
+    }
 }
@@ -1,33 +1,194 @@
-use std::sync::Arc;
-
+use crate::aabb::Aabb;
+use crate::bvh::*;
+use crate::environment::Environment;
 use crate::hittable::*;
+use crate::light::{AreaLight, Light, LightList};
 use crate::ray::*;
+use std::sync::Arc;
 
 pub struct HittableList {
     objects: Vec<Box<dyn Hittable + Send + Sync>>,
+    lights: LightList, // Emitters of every object and light, in world space.
+    bvh: Option<Bvh>,  // Top-level BVH over the objects' bounds. Objects are looped over if unset.
+    // Index of the object of each BVH primitive.
+    bvh_objects: Vec<usize>,
+    // Objects with infinite bounds, like planes, which are tested outside of the BVH.
+    unbounded: Vec<usize>,
+    // Emitters of each object, collected once when it's added so lights can be edited
+    // without collecting them again.
+    object_emitters: Vec<Vec<(usize, Arc<dyn AreaLight>)>>,
+    // Lights placed in the scene, and the surfaces of the area lights among them. There are
+    // few of them and they may be edited, so their surfaces are tested outside of the BVH.
+    scene_lights: Vec<Light>,
+    light_surfaces: Vec<Box<dyn Hittable + Send + Sync>>,
+    // Primitive indices of the emitters of each light surface, to renumber them.
+    surface_primitives: Vec<Vec<usize>>,
+    // Background, which lights the scene from outside.
+    environment: Environment,
 }
 
 #[allow(dead_code)]
 impl HittableList {
     pub fn new() -> Self {
         let objects = Vec::new();
-        Self { objects }
+        Self {
+            objects,
+            lights: LightList::new(),
+            bvh: None,
+            bvh_objects: Vec::new(),
+            unbounded: Vec::new(),
+            object_emitters: Vec::new(),
+            scene_lights: Vec::new(),
+            light_surfaces: Vec::new(),
+            surface_primitives: Vec::new(),
+            environment: Environment::default(),
+        }
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.lights.clear();
+        self.object_emitters.clear();
+        self.scene_lights.clear();
+        self.light_surfaces.clear();
+        self.surface_primitives.clear();
+        self.environment = Environment::default();
+        self.bvh = None;
     }
 
+    /// Add an object to the list. The object's emitters are collected at this point,
+    /// so it must already be in its final position.
+    /// Adding an object discards the top-level BVH until `build_bvh` is called again.
     // TODO: Why do I need a static lifetime bound?
     pub fn add<H: Hittable + Send + Sync + 'static>(&mut self, object: H) {
+        let index = self.objects.len();
+        // The light surfaces are numbered after the objects, so each of them moves up by one.
+        // The last one moves first so that their indices stay apart.
+        for (i, primitives) in self.surface_primitives.iter().enumerate().rev() {
+            self.lights
+                .renumber(index + i, index + i + 1, primitives.iter().copied());
+        }
+        let emitters = object.emitters();
+        self.lights.extend(index, emitters.iter().cloned());
+        self.object_emitters.push(emitters);
         self.objects.push(Box::new(object));
+        self.bvh = None;
     }
-}
 
-impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload) -> bool {
+    /// Build a top-level BVH over the world space bounds of the objects. Each object is
+    /// responsible for accelerating its own intersections, e.g. with a mesh's BVH.
+    /// Objects with infinite bounds can't be partitioned and are always tested.
+    pub fn build_bvh(&mut self) {
+        let mut bounds = Vec::new();
+        self.bvh_objects.clear();
+        self.unbounded.clear();
+        for (i, obj) in self.objects.iter().enumerate() {
+            let aabb = obj.bounding_box();
+            if aabb.min.is_finite() && aabb.max.is_finite() {
+                bounds.push(aabb);
+                self.bvh_objects.push(i);
+            } else if !aabb.is_empty() {
+                self.unbounded.push(i);
+            }
+        }
+        self.bvh = Some(Bvh::new(&bounds));
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn lights(&self) -> &LightList {
+        &self.lights
+    }
+
+    /// Lights placed in the scene, in the order they were added.
+    pub fn scene_lights(&self) -> &[Light] {
+        &self.scene_lights
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        if let Some(surface) = light.surface() {
+            let index = self.objects.len() + self.light_surfaces.len();
+            let emitters = surface.emitters();
+            self.surface_primitives
+                .push(emitters.iter().map(|&(primitive, _)| primitive).collect());
+            self.lights.extend(index, emitters);
+            self.light_surfaces.push(surface);
+        } else if let Some(punctual) = light.punctual() {
+            self.lights.add_punctual(punctual);
+        }
+        self.scene_lights.push(light);
+    }
+
+    /// Replace one of the scene's lights, e.g. after moving it.
+    pub fn set_light(&mut self, index: usize, light: Light) {
+        self.scene_lights[index] = light;
+        self.rebuild_lights();
+    }
+
+    pub fn remove_light(&mut self, index: usize) {
+        self.scene_lights.remove(index);
+        self.rebuild_lights();
+    }
+
+    /// Rebuild the light list from the objects' cached emitters and the scene's lights.
+    fn rebuild_lights(&mut self) {
+        self.lights.clear();
+        self.light_surfaces.clear();
+        self.surface_primitives.clear();
+        for (i, emitters) in self.object_emitters.iter().enumerate() {
+            self.lights.extend(i, emitters.iter().cloned());
+        }
+        for light in std::mem::take(&mut self.scene_lights) {
+            self.add_light(light);
+        }
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
+    }
+
+    /// Find the closest hit with the objects, ignoring the lights.
+    fn hit_objects<'a>(
+        &'a self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rec: &mut HitPayload<'a>,
+    ) -> bool {
+        if let Some(bvh) = &self.bvh {
+            // Objects only record hits closer than the distance they're given.
+            let mut hit_anything = false;
+            let mut closest_so_far = t_max;
+            for &i in &self.unbounded {
+                if self.objects[i].hit(r, t_min, closest_so_far, rec) {
+                    rec.object_index = i;
+                    hit_anything = true;
+                    closest_so_far = rec.hit_distance;
+                }
+            }
+            let mut visits = 0;
+            let hit_bounded = bvh.traverse(r, t_min, closest_so_far, &mut visits, |prim, t_max| {
+                let i = self.bvh_objects[prim];
+                if self.objects[i].hit(r, t_min, t_max, rec) {
+                    rec.object_index = i;
+                    Some(rec.hit_distance)
+                } else {
+                    None
+                }
+            });
+            rec.stats.bvh_node_visits += visits;
+            return hit_anything || hit_bounded;
+        }
+
         let mut temp_rec = HitPayload::new();
         temp_rec.hit_distance = t_max;
+        temp_rec.stats = rec.stats;
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
 
@@ -39,17 +200,208 @@ impl Hittable for HittableList {
                     // Record the closest hit.
                     closest_so_far = temp_rec.hit_distance;
 
-                    rec.world_position = temp_rec.world_position;
-                    rec.world_normal = temp_rec.world_normal;
-                    rec.hit_distance = temp_rec.hit_distance;
-                    rec.front_face = temp_rec.front_face;
+                    *rec = temp_rec;
                     rec.object_index = i;
-                    rec.albedo = temp_rec.albedo;
-                    rec.emissive = temp_rec.emissive;
                 }
             }
         }
+        // Objects tested after the closest hit also count.
+        rec.stats = temp_rec.stats;
+
+        hit_anything
+    }
+}
 
+impl Hittable for HittableList {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        let mut hit_anything = self.hit_objects(r, t_min, t_max, rec);
+        let mut closest_so_far = if hit_anything {
+            rec.hit_distance
+        } else {
+            t_max
+        };
+        // Light surfaces are numbered after the objects.
+        for (i, surface) in self.light_surfaces.iter().enumerate() {
+            if surface.hit(r, t_min, closest_so_far, rec) {
+                rec.object_index = self.objects.len() + i;
+                hit_anything = true;
+                closest_so_far = rec.hit_distance;
+            }
+        }
         hit_anything
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects
+            .iter()
+            .chain(&self.light_surfaces)
+            .fold(Aabb::EMPTY, |aabb, obj| aabb.union(&obj.bounding_box()))
+    }
+
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        self.objects
+            .iter()
+            .chain(&self.light_surfaces)
+            .flat_map(|obj| obj.emitters())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{light::LightShape, material::Lambertian, mesh::Mesh, triangle::Triangle, Color};
+    use glam::*;
+    use rand::{Rng, SeedableRng};
+    use std::time::Instant;
+
+    /// A grid of `n * n` small meshes of random triangles.
+    /// Also returns the same triangles in world space, for comparing with a single mesh.
+    fn mesh_grid(n: usize) -> (HittableList, Vec<Triangle>) {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(9);
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        let mut list = HittableList::new();
+        let mut world_triangles = Vec::new();
+        for i in 0..n {
+            for j in 0..n {
+                let triangles = (0..32)
+                    .map(|_| {
+                        let mut v = || vec3a(rng.gen(), rng.gen(), rng.gen()) - 0.5;
+                        let v0 = v();
+                        Triangle::new(v0, v0 + v() * 0.5, v0 + v() * 0.5, material.clone())
+                    })
+                    .collect::<Vec<_>>();
+                let rotation = Quat::from_rotation_y(i as f32);
+                let translation = vec3a(i as f32 * 2.0, 0.0, j as f32 * 2.0);
+
+                let m = Affine3A::from_rotation_translation(rotation, translation.into());
+                world_triangles.extend(triangles.iter().map(|t| {
+                    let [v0, v1, v2] = t.vertices().map(|v| m.transform_point3a(v));
+                    Triangle::new(v0, v1, v2, material.clone())
+                }));
+
+                let mut mesh = Mesh::from_triangles(triangles);
+                mesh.transformation(Vec3A::ONE, rotation, translation);
+                list.add(mesh);
+            }
+        }
+        (list, world_triangles)
+    }
+
+    /// Rays from above the grid towards random points on it.
+    fn random_rays(n: usize, size: f32) -> Vec<Ray> {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(10);
+        (0..n)
+            .map(|_| {
+                let origin = vec3a(rng.gen(), 1.0, rng.gen()) * vec3a(size, 10.0, size);
+                let target = vec3a(rng.gen(), 0.0, rng.gen()) * size;
+                Ray::new(origin, (target - origin).normalize())
+            })
+            .collect()
+    }
+
+    fn closest_hit(list: &HittableList, ray: &Ray) -> Option<(f32, usize)> {
+        let mut rec = HitPayload::new();
+        rec.hit_distance = f32::INFINITY;
+        if list.hit(ray, 0.0, f32::INFINITY, &mut rec) {
+            Some((rec.hit_distance, rec.object_index))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn bvh_matches_linear_search() {
+        let (mut list, _) = mesh_grid(8);
+        let rays = random_rays(2000, 16.0);
+        let linear: Vec<_> = rays.iter().map(|ray| closest_hit(&list, ray)).collect();
+
+        list.build_bvh();
+        let bvh: Vec<_> = rays.iter().map(|ray| closest_hit(&list, ray)).collect();
+        assert_eq!(bvh, linear);
+        assert!(linear.iter().any(|hit| hit.is_some()));
+
+        // Adding an object falls back to the linear search until the BVH is rebuilt.
+        list.add(Mesh::from_triangles(Vec::new()));
+        assert!(list.bvh.is_none());
+    }
+
+    #[test]
+    fn editing_lights_keeps_object_emitters() {
+        let mut list = HittableList::new();
+        let material = Arc::new(Lambertian {
+            albedo: Color::ZERO,
+            emission: Color::ONE,
+        });
+        list.add(Triangle::new(
+            vec3a(0.0, 0.0, 0.0),
+            vec3a(1.0, 0.0, 0.0),
+            vec3a(0.0, 1.0, 0.0),
+            material,
+        ));
+        let rect = |x| {
+            let shape = LightShape::Rect {
+                corner: vec3a(x, 2.0, 0.0),
+                edge_u: Vec3A::X,
+                edge_v: Vec3A::Z,
+            };
+            Light::from_intensity(shape, Color::ONE, 1.0)
+        };
+        list.add_light(rect(0.0));
+
+        list.set_light(0, rect(1.0));
+        assert_eq!(list.lights().len(), 2);
+        assert!(list.lights().pdf_area(0, 0) > 0.0);
+        assert!(list.lights().pdf_area(1, 0) > 0.0);
+
+        // Objects added after a light are numbered before its surface.
+        list.add(Triangle::new(
+            vec3a(0.0, 0.0, 1.0),
+            vec3a(1.0, 0.0, 1.0),
+            vec3a(0.0, 1.0, 1.0),
+            Arc::new(Lambertian::new(Color::ONE)),
+        ));
+        assert_eq!(list.lights().len(), 2);
+        assert_eq!(list.lights().pdf_area(1, 0), 0.0);
+        assert!(list.lights().pdf_area(2, 0) > 0.0);
+        let ray = Ray::new(vec3a(1.5, 3.0, 0.5), vec3a(0.0, -1.0, 0.0));
+        assert_eq!(closest_hit(&list, &ray).map(|hit| hit.1), Some(2));
+
+        list.remove_light(0);
+        assert_eq!(list.lights().len(), 1);
+        assert_eq!(list.lights().pdf_area(2, 0), 0.0);
+    }
+
+    /// Compare tracing against a grid of meshes with and without the top-level BVH, and
+    /// against a single mesh containing all of the triangles.
+    /// Run with `cargo test --release tlas_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn tlas_benchmark() {
+        let rays_per_second = |list: &HittableList, rays: &[Ray]| {
+            let now = Instant::now();
+            for ray in rays {
+                closest_hit(list, ray);
+            }
+            rays.len() as f64 / now.elapsed().as_secs_f64() / 1e6
+        };
+
+        for n in [1, 8, 64] {
+            let (mut list, world_triangles) = mesh_grid(n);
+            let rays = random_rays(100_000, n as f32 * 2.0);
+
+            let linear = rays_per_second(&list, &rays);
+            list.build_bvh();
+            let bvh = rays_per_second(&list, &rays);
+
+            let mut single_mesh = HittableList::new();
+            single_mesh.add(Mesh::from_triangles(world_triangles));
+            let single = rays_per_second(&single_mesh, &rays);
+
+            println!("{} meshes", list.len());
+            println!("    Linear:      {:.2} Mrays/s", linear);
+            println!("    BVH:         {:.2} Mrays/s", bvh);
+            println!("    Single mesh: {:.2} Mrays/s", single);
+        }
+    }
 }
@@ -63,6 +63,7 @@ impl InputState {
 
     /// Get the change in mouse coordinates.
     /// TODO: This doesn't seem to be working?
+    #[allow(dead_code)]
     pub fn get_mouse_delta(&self) -> (f32, f32) {
         match self.last_mouse_position {
             Some(last_mouse_pos) => (
@@ -0,0 +1,488 @@
+use crate::{
+    hittable::{HitPayload, Hittable},
+    material::{Lambertian, Material},
+    quad::Quad,
+    sphere::Sphere,
+    util, Color,
+};
+use glam::*;
+use rand::Rng;
+use std::{collections::HashMap, f32::consts::PI, fmt::Debug, sync::Arc};
+
+/// A point sampled on one of the scene's emitters.
+pub struct LightSample {
+    pub position: Vec3A,
+    pub normal: Vec3A,
+    /// Radiance leaving the light towards the point being shaded.
+    pub emission: Color,
+    /// Density of the sample with respect to the area of the lights.
+    pub pdf_area: f32,
+}
+
+/// A point on the surface of an area light.
+pub struct SurfacePoint {
+    pub position: Vec3A,
+    pub normal: Vec3A,
+    /// Radiance emitted from the point, the same on both sides of the surface.
+    pub emission: Color,
+}
+
+impl SurfacePoint {
+    /// Evaluate the emission of a material at a point with the given texture coordinates.
+    pub fn new(position: Vec3A, normal: Vec3A, uv: Vec2, material: &dyn Material) -> Self {
+        let mut hit = HitPayload::new();
+        hit.world_position = position;
+        hit.world_normal = normal;
+        hit.shading_normal = normal;
+        hit.uv = uv;
+        hit.front_face = true;
+        Self {
+            position,
+            normal,
+            emission: material.emitted(&hit),
+        }
+    }
+}
+
+/// A world space surface which emits light and can be sampled uniformly by area.
+pub trait AreaLight: Debug + Send + Sync {
+    fn area(&self) -> f32;
+
+    /// Radiance used for choosing between lights, e.g. at the center of a textured light.
+    fn emission(&self) -> Color;
+
+    /// Map a uniformly distributed point of the unit square to a uniformly distributed
+    /// point on the surface.
+    fn sample_point(&self, u: Vec2) -> SurfacePoint;
+}
+
+/// A light without area, such as those of glTF's `KHR_lights_punctual` extension.
+/// Punctual lights can't be hit by rays, so they're only found by sampling them directly.
+#[derive(Debug, Clone)]
+pub enum PunctualLight {
+    /// Emits `intensity` (radiant intensity) equally in all directions.
+    Point { position: Vec3A, intensity: Color },
+    /// A point light restricted to a cone around `direction`. The intensity falls off
+    /// between the inner and outer cone angles, given as cosines.
+    Spot {
+        position: Vec3A,
+        direction: Vec3A,
+        intensity: Color,
+        cos_inner: f32,
+        cos_outer: f32,
+    },
+    /// Light arriving from infinitely far away, travelling along `direction`.
+    Directional { direction: Vec3A, irradiance: Color },
+}
+
+/// Light arriving at a point from a punctual light.
+pub struct PunctualSample {
+    /// Direction towards the light.
+    pub direction: Vec3A,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f32,
+    /// Irradiance at the point from a surface facing the light.
+    pub irradiance: Color,
+}
+
+impl PunctualLight {
+    pub fn illuminate(&self, point: Vec3A) -> Option<PunctualSample> {
+        match *self {
+            PunctualLight::Point {
+                position,
+                intensity,
+            } => Self::inverse_square(point, position, intensity),
+            PunctualLight::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let sample = Self::inverse_square(point, position, intensity)?;
+                // Falloff from the glTF specification.
+                let cos_theta = direction.dot(-sample.direction);
+                let t =
+                    ((cos_theta - cos_outer) / (cos_inner - cos_outer).max(1e-3)).clamp(0.0, 1.0);
+                if t <= 0.0 {
+                    return None;
+                }
+                Some(PunctualSample {
+                    irradiance: sample.irradiance * t * t,
+                    ..sample
+                })
+            }
+            PunctualLight::Directional {
+                direction,
+                irradiance,
+            } => Some(PunctualSample {
+                direction: -direction,
+                distance: f32::INFINITY,
+                irradiance,
+            }),
+        }
+    }
+
+    fn inverse_square(point: Vec3A, position: Vec3A, intensity: Color) -> Option<PunctualSample> {
+        let to_light = position - point;
+        let distance_squared = to_light.length_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        Some(PunctualSample {
+            direction: to_light / distance,
+            distance,
+            irradiance: intensity / distance_squared,
+        })
+    }
+}
+
+/// A light placed in the scene by itself, rather than an emissive object.
+/// Its brightness is given in physical units, independently of its size or cone angle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub shape: LightShape,
+    /// Linear color which scales the power of each channel.
+    pub color: Color,
+    /// Power emitted by the light in watts. Directional lights would have infinite power,
+    /// so this is their irradiance in W/m² instead.
+    pub power: f32,
+}
+
+/// Where a light is and how it emits. Angles are in radians.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightShape {
+    Point {
+        position: Vec3A,
+    },
+    /// Falls off between the inner and outer angle from `direction`.
+    Spot {
+        position: Vec3A,
+        direction: Vec3A,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+    /// Travels along `direction`.
+    Directional {
+        direction: Vec3A,
+    },
+    /// Diffuse sphere, which is visible to rays.
+    Sphere {
+        center: Vec3A,
+        radius: f32,
+    },
+    /// Diffuse parallelogram emitting from both sides, which is visible to rays.
+    Rect {
+        corner: Vec3A,
+        edge_u: Vec3A,
+        edge_v: Vec3A,
+    },
+}
+
+impl LightShape {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightShape::Point { .. } => "point",
+            LightShape::Spot { .. } => "spot",
+            LightShape::Directional { .. } => "directional",
+            LightShape::Sphere { .. } => "sphere",
+            LightShape::Rect { .. } => "rect",
+        }
+    }
+}
+
+impl Light {
+    /// Create a light from the radiant intensity of a point or spot light, the irradiance
+    /// of a directional light or the radiance of an area light.
+    pub fn from_intensity(shape: LightShape, color: Color, intensity: f32) -> Self {
+        let mut light = Self {
+            shape,
+            color,
+            power: 0.0,
+        };
+        light.power = intensity * light.power_per_intensity();
+        light
+    }
+
+    /// The light's intensity, irradiance or radiance, depending on its shape.
+    pub fn intensity(&self) -> Color {
+        let scale = self.power_per_intensity();
+        if scale <= 0.0 {
+            return Color::ZERO;
+        }
+        self.color * self.power / scale
+    }
+
+    /// Power emitted with unit intensity. This is the solid angle covered by punctual lights
+    /// and π times the emitting area of diffuse area lights.
+    fn power_per_intensity(&self) -> f32 {
+        match self.shape {
+            LightShape::Point { .. } => 4.0 * PI,
+            LightShape::Spot {
+                inner_angle,
+                outer_angle,
+                ..
+            } => {
+                // The falloff is quadratic in the cosine, so it covers a third of the
+                // solid angle between the cones.
+                let (cos_inner, cos_outer) = (inner_angle.cos(), outer_angle.cos());
+                2.0 * PI * ((1.0 - cos_inner) + (cos_inner - cos_outer) / 3.0)
+            }
+            LightShape::Directional { .. } => 1.0,
+            LightShape::Sphere { radius, .. } => PI * 4.0 * PI * radius * radius,
+            LightShape::Rect { edge_u, edge_v, .. } => PI * 2.0 * edge_u.cross(edge_v).length(),
+        }
+    }
+
+    /// The light as a punctual light, unless it has an area.
+    pub fn punctual(&self) -> Option<PunctualLight> {
+        let intensity = self.intensity();
+        match self.shape {
+            LightShape::Point { position } => Some(PunctualLight::Point {
+                position,
+                intensity,
+            }),
+            LightShape::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+            } => Some(PunctualLight::Spot {
+                position,
+                direction: direction.normalize(),
+                intensity,
+                cos_inner: inner_angle.cos(),
+                cos_outer: outer_angle.cos(),
+            }),
+            LightShape::Directional { direction } => Some(PunctualLight::Directional {
+                direction: direction.normalize(),
+                irradiance: intensity,
+            }),
+            LightShape::Sphere { .. } | LightShape::Rect { .. } => None,
+        }
+    }
+
+    /// The emissive surface of an area light, which doesn't reflect any light.
+    pub fn surface(&self) -> Option<Box<dyn Hittable + Send + Sync>> {
+        let material = Arc::new(Lambertian {
+            albedo: Color::ZERO,
+            emission: self.intensity(),
+        });
+        match self.shape {
+            LightShape::Sphere { center, radius } => {
+                Some(Box::new(Sphere::new(center, radius, material)))
+            }
+            LightShape::Rect {
+                corner,
+                edge_u,
+                edge_v,
+            } => Some(Box::new(Quad::new(corner, edge_u, edge_v, material))),
+            _ => None,
+        }
+    }
+}
+
+/// Lights in world space, used for sampling direct lighting.
+/// Area lights are chosen proportionally to their power and then sampled uniformly by area.
+/// Punctual lights are kept separately, since they need to be sampled every time.
+#[derive(Debug, Default)]
+pub struct LightList {
+    area_lights: Vec<Arc<dyn AreaLight>>,
+    cdf: Vec<f32>, // Unnormalized cumulative power of the area lights.
+    total_power: f32,
+    // Index of the area light of each emitting primitive, keyed by the object and primitive
+    // indices recorded by hits on it.
+    indices: HashMap<(usize, usize), usize>,
+    punctual: Vec<PunctualLight>,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether there are no area lights.
+    pub fn is_empty(&self) -> bool {
+        self.area_lights.is_empty()
+    }
+
+    /// Number of area lights, such as emissive triangles.
+    pub fn len(&self) -> usize {
+        self.area_lights.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.area_lights.clear();
+        self.cdf.clear();
+        self.total_power = 0.0;
+        self.indices.clear();
+        self.punctual.clear();
+    }
+
+    pub fn add_punctual(&mut self, light: PunctualLight) {
+        self.punctual.push(light);
+    }
+
+    pub fn punctual(&self) -> &[PunctualLight] {
+        &self.punctual
+    }
+
+    /// Add the emitters of an object, paired with their primitive indices.
+    /// Lights which don't emit any light are skipped.
+    pub fn extend(
+        &mut self,
+        object_index: usize,
+        lights: impl IntoIterator<Item = (usize, Arc<dyn AreaLight>)>,
+    ) {
+        for (primitive_index, light) in lights {
+            let power = util::luminance(light.emission()) * light.area();
+            if power <= 0.0 {
+                continue;
+            }
+            self.total_power += power;
+            self.cdf.push(self.total_power);
+            self.indices
+                .insert((object_index, primitive_index), self.area_lights.len());
+            self.area_lights.push(light);
+        }
+    }
+
+    /// Move the emitters of an object to another object index, e.g. when objects are added
+    /// before it. The primitive indices are the ones its emitters were added with.
+    pub fn renumber(
+        &mut self,
+        object_index: usize,
+        new_index: usize,
+        primitive_indices: impl IntoIterator<Item = usize>,
+    ) {
+        for primitive_index in primitive_indices {
+            if let Some(index) = self.indices.remove(&(object_index, primitive_index)) {
+                self.indices.insert((new_index, primitive_index), index);
+            }
+        }
+    }
+
+    /// Pick a light proportionally to its power and sample a point on it uniformly.
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<LightSample> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let u = rng.gen_range(0.0..self.total_power);
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.area_lights.len() - 1);
+        let light = &self.area_lights[index];
+
+        let point = light.sample_point(vec2(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)));
+        Some(LightSample {
+            position: point.position,
+            normal: point.normal,
+            emission: point.emission,
+            pdf_area: self.light_pdf_area(index),
+        })
+    }
+
+    /// Area density with which `sample` generates a point on the emitter which a hit recorded
+    /// with the given object and primitive indices is on. Zero if the emitter isn't sampled.
+    pub fn pdf_area(&self, object_index: usize, primitive_index: usize) -> f32 {
+        match self.indices.get(&(object_index, primitive_index)) {
+            Some(&index) => self.light_pdf_area(index),
+            None => 0.0,
+        }
+    }
+
+    /// Since lights are picked by power and sampled by area, the density of a light is its
+    /// radiance over the total power. Textured lights are picked by their emission at the
+    /// center, so it's the same everywhere on them.
+    fn light_pdf_area(&self, index: usize) -> f32 {
+        util::luminance(self.area_lights[index].emission()) / self.total_power
+    }
+
+    /// Convert an area density at `light_position` to a solid angle density as seen from `origin`.
+    pub fn to_solid_angle(
+        pdf_area: f32,
+        origin: Vec3A,
+        light_position: Vec3A,
+        light_normal: Vec3A,
+    ) -> f32 {
+        let to_light = light_position - origin;
+        let distance_squared = to_light.length_squared();
+        let cos_light = light_normal.dot(to_light.normalize()).abs();
+        if cos_light <= 0.0 {
+            return 0.0;
+        }
+        pdf_area * distance_squared / cos_light
+    }
+}
+
+/// Power heuristic with an exponent of 2 for multiple importance sampling.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intensity_round_trips_through_power() {
+        let shapes = [
+            LightShape::Point {
+                position: Vec3A::ZERO,
+            },
+            LightShape::Spot {
+                position: Vec3A::ZERO,
+                direction: Vec3A::NEG_Y,
+                inner_angle: 0.2,
+                outer_angle: 0.6,
+            },
+            LightShape::Directional {
+                direction: Vec3A::NEG_Y,
+            },
+            LightShape::Sphere {
+                center: Vec3A::ZERO,
+                radius: 0.5,
+            },
+            LightShape::Rect {
+                corner: Vec3A::ZERO,
+                edge_u: Vec3A::X,
+                edge_v: 2.0 * Vec3A::Z,
+            },
+        ];
+        for shape in &shapes {
+            let light = Light::from_intensity(shape.clone(), vec3a(1.0, 0.5, 0.0), 3.0);
+            assert!(
+                (light.intensity() - vec3a(3.0, 1.5, 0.0))
+                    .abs()
+                    .max_element()
+                    < 1e-5
+            );
+        }
+
+        // A spot light without falloff covers the solid angle of its cone.
+        let spot = Light {
+            shape: LightShape::Spot {
+                position: Vec3A::ZERO,
+                direction: Vec3A::NEG_Y,
+                inner_angle: 0.5,
+                outer_angle: 0.5,
+            },
+            color: Color::ONE,
+            power: 1.0,
+        };
+        let solid_angle = 2.0 * PI * (1.0 - 0.5f32.cos());
+        assert!((spot.intensity().x - 1.0 / solid_angle).abs() < 1e-5);
+
+        // Both sides of a 1x2 rect emit.
+        let rect = Light::from_intensity(shapes[4].clone(), Color::ONE, 1.0);
+        assert!((rect.power - 4.0 * PI).abs() < 1e-5);
+    }
+}
@@ -1,33 +1,43 @@
+mod aabb;
+mod aov;
 mod application;
 mod bvh;
 mod camera;
+mod cuboid;
+mod denoise;
+mod disk;
+mod display;
+mod environment;
+mod filter;
+mod gltf_scene;
+mod hdr_image;
+mod headless;
 mod hittable;
 mod hittable_list;
 mod imgui_dock;
 mod input;
+mod light;
+mod material;
 mod mesh;
+mod obj;
 mod onb;
+mod plane;
+mod ply;
+mod quad;
 mod ray;
 mod renderer;
 mod rng;
+mod scene;
+mod sky;
+mod sphere;
+mod texture;
 mod triangle;
 mod util;
 
 use application::Application;
 use camera::*;
 use glam::*;
-use hittable::{HitPayload, Hittable};
-use hittable_list::HittableList;
-use image::{Rgb, RgbImage};
-use mesh::*;
-use onb::*;
 use ray::Ray;
-use rng::*;
-use std::f32::consts::PI;
-use std::num;
-use std::time::Instant;
-use triangle::*;
-use util::*;
 
 type Color = Vec3A;
 
@@ -35,98 +45,20 @@ const ASPECT_RATIO: f32 = 4.0 / 3.0;
 const IMG_WIDTH: u32 = 800;
 const IMG_HEIGHT: u32 = (IMG_WIDTH as f32 / ASPECT_RATIO) as u32;
 
-
 fn main() {
-    let app = Application::init(file!(), 1920, 1080);
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("render") {
+        // Offline render which doesn't require a window, Vulkan, or imgui.
+        if let Err(e) = headless::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Scene to open in the viewer.
+    let scene_path = args.get(1).map_or("scenes/cornell.toml", String::as_str);
+
+    let app = Application::init(file!(), 1920, 1080, scene_path);
     app.main_loop();
-
-    // let mut rng = Rng::from_seed(727);
-    // Scene
-    // let mut world = HittableList::new();
-
-    // let triangles = Mesh::from_triangles(random_triangles(&mut rng, 10_000));
-    // world.add(triangles);
-
-    // let mut cornell = Mesh::from_gltf("assets/cornell.glb").unwrap();
-    // println!("cube tri count: {}", cornell.num_triangles());
-    // world.add(cornell);
-
-    // let mut cube1 = Mesh::from_gltf("assets/cube.glb").unwrap();
-    // println!("cube tri count: {}", cube1.num_triangles());
-    // cube1.transformation(
-    //     Vec3::ONE,
-    //     Quat::from_rotation_y(PI * 0.25),
-    //     vec3(0.0, 0.0, 0.0),
-    // );
-    // world.add(cube1);
-
-    // let mut plane = Mesh::from_gltf("assets/plane.glb").unwrap();
-    // println!("plane tri count: {}", plane.num_triangles());
-    // plane.transformation(vec3(25.0, 1.0, 25.0), Quat::IDENTITY, vec3(0.0, -2.0, 0.0));
-    // world.add(plane);
-
-    // let mut bunny = Mesh::from_gltf("assets/bunny.glb").unwrap();
-    // println!("bunny tri count: {}", bunny.num_triangles());
-    // bunny.transformation(
-    //     Vec3::ONE,
-    //     Quat::from_rotation_y(PI * 0.25),
-    //     vec3(0.0, 1.5, 0.0),
-    // );
-    // world.add(bunny);
-
-    // let mut monkey = Mesh::from_gltf("assets/monkey.glb").unwrap();
-    // monkey.transformation(
-    //     Vec3::ONE,
-    //     Quat::from_rotation_y(PI * 0.25),
-    //     vec3(-4.0, 1.0, -1.0),
-    // );
-    // println!("monkey tri count: {}", monkey.num_triangles());
-    // world.add(monkey);
-
-    // let mut icosphere = Mesh::from_gltf("assets/icosphere.glb").unwrap();
-    // icosphere.transformation(
-    //     Vec3::ONE,
-    //     Quat::from_rotation_y(PI * 0.25),
-    //     vec3(4.0, 1.0, -1.0),
-    // );
-
-    // world.add(icosphere);
-
-    // // Camera settings.
-    // let cam = Camera::new(
-    //     vec3(0.0, 1.0, 3.0),
-    //     vec3(0.0, 1.0, 0.0),
-    //     vec3(0.0, 1.0, 0.0),
-    //     60.0,
-    //     ASPECT_RATIO,
-    // );
-
-    // let max_depth = 5;
-    // let num_samples = 1;
-
-    // let mut imgbuf = RgbImage::new(IMG_WIDTH, IMG_HEIGHT);
-
-    // let now = Instant::now();
-    // // Iterate over each pixel in the image.
-    // for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
-    //     let mut col = Vec3::ZERO;
-    //     for _ in 0..num_samples {
-    //         let u = (x as f32 + rng.random_uniform()) / (IMG_WIDTH - 1) as f32;
-    //         let v = 1.0 - ((y as f32 + rng.random_uniform()) / (IMG_HEIGHT - 1) as f32);
-
-    //         let view_ray = cam.get_ray(u, v);
-
-    //         col += ray_color(&view_ray, &world, max_depth, &mut rng);
-    //     }
-    //     write_color(pixel, col / num_samples as f32);
-
-    //     if x == 0 {
-    //         println!("Scanlines remaining: {}", IMG_HEIGHT - y);
-    //     }
-    // }
-    // let elapsed = now.elapsed();
-    // eprintln!("Done!");
-    // eprintln!("Time taken: {}ms", elapsed.as_millis());
-
-    // imgbuf.save("out.png").unwrap();
 }
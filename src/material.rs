@@ -0,0 +1,639 @@
+use crate::{hittable::HitPayload, onb::Onb, texture::Texture, util, Color};
+use glam::*;
+use rand::{Rng, RngCore};
+use std::{f32::consts::PI, fmt::Debug, sync::Arc};
+
+/// Result of sampling a material's BSDF.
+pub struct BsdfSample {
+    /// Sampled incident direction, pointing away from the surface.
+    pub direction: Vec3A,
+    /// BSDF value times the cosine term, divided by the pdf of the sample.
+    pub weight: Color,
+    /// Solid angle density of the sampled direction. Zero for specular samples.
+    pub pdf: f32,
+    /// Whether the direction was sampled from a delta distribution.
+    pub is_specular: bool,
+}
+
+/// Describes how light scatters off and is emitted from a surface.
+///
+/// All directions are in world space and point away from the surface. `wo` is the
+/// direction light leaves towards (the viewer), and `wi` the direction it arrives from.
+pub trait Material: Debug + Send + Sync {
+    /// Evaluate the BSDF for a pair of directions.
+    /// Delta distributions evaluate to zero and must be handled through `sample`.
+    fn eval(&self, hit: &HitPayload, wo: Vec3A, wi: Vec3A) -> Color;
+
+    /// Sample an incident direction given the outgoing direction.
+    fn sample(&self, hit: &HitPayload, wo: Vec3A, rng: &mut dyn RngCore) -> Option<BsdfSample>;
+
+    /// Solid angle density with which `sample` generates `wi`.
+    fn pdf(&self, hit: &HitPayload, wo: Vec3A, wi: Vec3A) -> f32;
+
+    /// Overall color of the surface, for debug visualizations and denoising.
+    fn albedo(&self, hit: &HitPayload) -> Color;
+
+    /// Radiance emitted from the surface.
+    fn emitted(&self, _hit: &HitPayload) -> Color {
+        Color::ZERO
+    }
+
+    /// Normal to shade the hit with, which materials may perturb e.g. with a normal map.
+    fn shading_normal(&self, hit: &HitPayload) -> Vec3A {
+        hit.shading_normal
+    }
+}
+
+/// Ideal diffuse reflector.
+#[derive(Debug, Clone)]
+pub struct Lambertian {
+    pub albedo: Color,
+    pub emission: Color,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            albedo,
+            emission: Color::ZERO,
+        }
+    }
+}
+
+impl Material for Lambertian {
+    fn eval(&self, hit: &HitPayload, wo: Vec3A, wi: Vec3A) -> Color {
+        let n = hit.shading_normal;
+        if n.dot(wo) <= 0.0 || n.dot(wi) <= 0.0 {
+            return Color::ZERO;
+        }
+        self.albedo / PI
+    }
+
+    fn sample(&self, hit: &HitPayload, wo: Vec3A, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let n = hit.shading_normal;
+        let wi = util::cosine_hemisphere_sample_world(rng, n);
+        let pdf = self.pdf(hit, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            direction: wi,
+            weight: self.eval(hit, wo, wi) * n.dot(wi) / pdf,
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit: &HitPayload, _wo: Vec3A, wi: Vec3A) -> f32 {
+        util::cosine_hemisphere_pdf(hit.shading_normal.dot(wi))
+    }
+
+    fn albedo(&self, _hit: &HitPayload) -> Color {
+        self.albedo
+    }
+
+    fn emitted(&self, _hit: &HitPayload) -> Color {
+        self.emission
+    }
+}
+
+/// Perfectly smooth mirror.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    pub color: Color,
+}
+
+impl Material for Mirror {
+    fn eval(&self, _hit: &HitPayload, _wo: Vec3A, _wi: Vec3A) -> Color {
+        Color::ZERO
+    }
+
+    fn sample(&self, hit: &HitPayload, wo: Vec3A, _rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            direction: reflect(wo, hit.shading_normal),
+            weight: self.color,
+            pdf: 0.0,
+            is_specular: true,
+        })
+    }
+
+    fn pdf(&self, _hit: &HitPayload, _wo: Vec3A, _wi: Vec3A) -> f32 {
+        0.0
+    }
+
+    fn albedo(&self, _hit: &HitPayload) -> Color {
+        self.color
+    }
+}
+
+/// Rough metal using the GGX microfacet distribution.
+#[derive(Debug, Clone)]
+pub struct RoughConductor {
+    /// Reflectance at normal incidence.
+    pub color: Color,
+    /// Perceptual roughness in [0, 1].
+    pub roughness: f32,
+}
+
+impl Material for RoughConductor {
+    fn eval(&self, hit: &HitPayload, wo: Vec3A, wi: Vec3A) -> Color {
+        let alpha = roughness_to_alpha(self.roughness);
+        ggx_eval(hit.shading_normal, wo, wi, alpha, self.color)
+    }
+
+    fn sample(&self, hit: &HitPayload, wo: Vec3A, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let n = hit.shading_normal;
+        let alpha = roughness_to_alpha(self.roughness);
+        let wi = ggx_sample(n, wo, alpha, rng)?;
+        let pdf = ggx_pdf(n, wo, wi, alpha);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            direction: wi,
+            weight: self.eval(hit, wo, wi) * n.dot(wi) / pdf,
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit: &HitPayload, wo: Vec3A, wi: Vec3A) -> f32 {
+        ggx_pdf(
+            hit.shading_normal,
+            wo,
+            wi,
+            roughness_to_alpha(self.roughness),
+        )
+    }
+
+    fn albedo(&self, _hit: &HitPayload) -> Color {
+        self.color
+    }
+}
+
+/// Smooth glass which reflects and refracts according to the Fresnel equations.
+#[derive(Debug, Clone)]
+pub struct Dielectric {
+    /// Index of refraction of the interior.
+    pub ior: f32,
+    /// Color filter applied to transmitted light.
+    pub tint: Color,
+}
+
+impl Material for Dielectric {
+    fn eval(&self, _hit: &HitPayload, _wo: Vec3A, _wi: Vec3A) -> Color {
+        Color::ZERO
+    }
+
+    fn sample(&self, hit: &HitPayload, wo: Vec3A, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let n = hit.shading_normal;
+        // Ratio of the indices of refraction on the incident and transmitted sides.
+        let eta = if hit.front_face {
+            1.0 / self.ior
+        } else {
+            self.ior
+        };
+
+        let cos_o = n.dot(wo).min(1.0);
+        let reflectance = fresnel_dielectric(cos_o, eta);
+
+        // Choose between reflection and refraction proportional to the Fresnel term.
+        let direction;
+        let weight;
+        if rng.gen::<f32>() < reflectance {
+            direction = reflect(wo, n);
+            weight = Color::ONE;
+        } else {
+            let sin2_t = eta * eta * (1.0 - cos_o * cos_o);
+            let cos_t = (1.0 - sin2_t).sqrt();
+            direction = (-wo * eta + n * (eta * cos_o - cos_t)).normalize();
+            weight = self.tint;
+        }
+
+        Some(BsdfSample {
+            direction,
+            weight,
+            pdf: 0.0,
+            is_specular: true,
+        })
+    }
+
+    fn pdf(&self, _hit: &HitPayload, _wo: Vec3A, _wi: Vec3A) -> f32 {
+        0.0
+    }
+
+    fn albedo(&self, _hit: &HitPayload) -> Color {
+        self.tint
+    }
+}
+
+/// The glTF metallic-roughness material: a diffuse base layered under a GGX specular
+/// lobe, blended towards a pure conductor by `metallic`.
+#[derive(Debug, Clone)]
+pub struct GltfMaterial {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: Color,
+    pub textures: GltfTextures,
+}
+
+/// Optional textures of a glTF material, each multiplying the corresponding factor.
+/// Textures are shared between all materials which use them.
+#[derive(Debug, Clone, Default)]
+pub struct GltfTextures {
+    pub base_color: Option<Arc<Texture>>,
+    pub metallic: Option<Arc<Texture>>,
+    pub roughness: Option<Arc<Texture>>,
+    pub emissive: Option<Arc<Texture>>,
+    pub normal: Option<NormalMap>,
+}
+
+/// Tangent space normal map.
+#[derive(Debug, Clone)]
+pub struct NormalMap {
+    pub texture: Arc<Texture>,
+    /// Scales the tangent components of the normals.
+    pub scale: f32,
+}
+
+/// Parameters of a glTF material at a point on the surface.
+struct GltfParams {
+    base_color: Color,
+    metallic: f32,
+    roughness: f32,
+}
+
+impl GltfParams {
+    /// Probability of sampling the specular lobe rather than the diffuse one.
+    fn specular_probability(&self) -> f32 {
+        0.5 * (1.0 + self.metallic)
+    }
+
+    /// Reflectance at normal incidence. Dielectrics reflect 4%.
+    fn f0(&self) -> Color {
+        Color::splat(0.04).lerp(self.base_color, self.metallic)
+    }
+
+    fn eval(&self, n: Vec3A, wo: Vec3A, wi: Vec3A) -> Color {
+        let cos_i = n.dot(wi);
+        let cos_o = n.dot(wo);
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return Color::ZERO;
+        }
+
+        let alpha = roughness_to_alpha(self.roughness);
+        let f0 = self.f0();
+        let specular = ggx_eval(n, wo, wi, alpha, f0);
+
+        // Energy which isn't reflected specularly enters the diffuse base.
+        let h = (wi + wo).normalize();
+        let fresnel = fresnel_schlick(f0, wi.dot(h));
+        let diffuse = (Color::ONE - fresnel) * (1.0 - self.metallic) * self.base_color / PI;
+
+        diffuse + specular
+    }
+
+    fn pdf(&self, n: Vec3A, wo: Vec3A, wi: Vec3A) -> f32 {
+        if n.dot(wi) <= 0.0 {
+            return 0.0;
+        }
+        let p_specular = self.specular_probability();
+        let specular_pdf = ggx_pdf(n, wo, wi, roughness_to_alpha(self.roughness));
+        let diffuse_pdf = util::cosine_hemisphere_pdf(n.dot(wi));
+
+        p_specular * specular_pdf + (1.0 - p_specular) * diffuse_pdf
+    }
+}
+
+impl GltfMaterial {
+    /// Look up the textures at the hit's texture coordinates.
+    fn params(&self, uv: Vec2) -> GltfParams {
+        let sample = |texture: &Option<Arc<Texture>>| texture.as_ref().map(|t| t.sample(uv));
+        let mut params = GltfParams {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+        };
+        if let Some(texel) = sample(&self.textures.base_color) {
+            params.base_color *= Color::from(texel.truncate());
+        }
+        // Single channel textures are stored as gray.
+        if let Some(texel) = sample(&self.textures.metallic) {
+            params.metallic *= texel.x;
+        }
+        if let Some(texel) = sample(&self.textures.roughness) {
+            params.roughness *= texel.x;
+        }
+        params
+    }
+}
+
+impl Material for GltfMaterial {
+    fn eval(&self, hit: &HitPayload, wo: Vec3A, wi: Vec3A) -> Color {
+        self.params(hit.uv).eval(hit.shading_normal, wo, wi)
+    }
+
+    fn sample(&self, hit: &HitPayload, wo: Vec3A, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let n = hit.shading_normal;
+        let params = self.params(hit.uv);
+        let wi = if rng.gen::<f32>() < params.specular_probability() {
+            ggx_sample(n, wo, roughness_to_alpha(params.roughness), rng)?
+        } else {
+            util::cosine_hemisphere_sample_world(rng, n)
+        };
+
+        let pdf = params.pdf(n, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample {
+            direction: wi,
+            weight: params.eval(n, wo, wi) * n.dot(wi) / pdf,
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit: &HitPayload, wo: Vec3A, wi: Vec3A) -> f32 {
+        self.params(hit.uv).pdf(hit.shading_normal, wo, wi)
+    }
+
+    fn albedo(&self, hit: &HitPayload) -> Color {
+        self.params(hit.uv).base_color
+    }
+
+    fn emitted(&self, hit: &HitPayload) -> Color {
+        match &self.textures.emissive {
+            Some(texture) => self.emissive * Color::from(texture.sample(hit.uv).truncate()),
+            None => self.emissive,
+        }
+    }
+
+    fn shading_normal(&self, hit: &HitPayload) -> Vec3A {
+        let n = hit.shading_normal;
+        let normal_map = match &self.textures.normal {
+            Some(normal_map) => normal_map,
+            None => return n,
+        };
+
+        // Build an orthonormal tangent frame around the shading normal. The texture's v axis
+        // points down the image while the green channel of normal maps points up.
+        let t = (hit.tangent - n * n.dot(hit.tangent)).normalize_or_zero();
+        let b = -(hit.bitangent - n * n.dot(hit.bitangent) - t * t.dot(hit.bitangent))
+            .normalize_or_zero();
+        if t == Vec3A::ZERO || b == Vec3A::ZERO {
+            return n;
+        }
+
+        let texel = normal_map.texture.sample(hit.uv);
+        let m = Vec3A::from(texel.truncate()) * 2.0 - 1.0;
+        (t * m.x * normal_map.scale + b * m.y * normal_map.scale + n * m.z).normalize_or_zero()
+    }
+}
+
+/// Mirror `w` about the normal `n`. Both point away from the surface.
+fn reflect(w: Vec3A, n: Vec3A) -> Vec3A {
+    2.0 * w.dot(n) * n - w
+}
+
+/// Fresnel reflectance of a smooth dielectric interface.
+/// `eta` is the ratio of the indices of refraction on the incident and transmitted sides.
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        // Total internal reflection.
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+/// Schlick's approximation of the Fresnel term.
+fn fresnel_schlick(f0: Color, cos_theta: f32) -> Color {
+    f0 + (Color::ONE - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+/// Map perceptual roughness to the GGX alpha parameter.
+/// Clamped so that very smooth surfaces don't degenerate into a delta distribution.
+fn roughness_to_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(1e-3)
+}
+
+/// GGX normal distribution function.
+fn ggx_d(cos_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+/// Smith masking function for a single direction.
+fn ggx_g1(cos_theta: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    2.0 * cos_theta / (cos_theta + (a2 + (1.0 - a2) * cos_theta * cos_theta).sqrt())
+}
+
+/// Evaluate a GGX microfacet reflection lobe with Schlick Fresnel.
+fn ggx_eval(n: Vec3A, wo: Vec3A, wi: Vec3A, alpha: f32, f0: Color) -> Color {
+    let cos_i = n.dot(wi);
+    let cos_o = n.dot(wo);
+    if cos_i <= 0.0 || cos_o <= 0.0 {
+        return Color::ZERO;
+    }
+
+    let h = (wi + wo).normalize();
+    let d = ggx_d(n.dot(h), alpha);
+    let g = ggx_g1(cos_o, alpha) * ggx_g1(cos_i, alpha);
+    let f = fresnel_schlick(f0, wi.dot(h));
+
+    f * (d * g / (4.0 * cos_i * cos_o))
+}
+
+/// Sample an incident direction by reflecting `wo` about a GGX distributed microfacet normal.
+fn ggx_sample(n: Vec3A, wo: Vec3A, alpha: f32, rng: &mut dyn RngCore) -> Option<Vec3A> {
+    let x1: f32 = rng.gen_range(0.0..1.0);
+    let x2: f32 = rng.gen_range(0.0..1.0);
+
+    let cos2_theta = (1.0 - x1) / (1.0 + (alpha * alpha - 1.0) * x1);
+    let cos_theta = cos2_theta.sqrt();
+    let sin_theta = (1.0 - cos2_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * x2;
+
+    let h = Onb::from_w(n).local(vec3a(
+        phi.cos() * sin_theta,
+        phi.sin() * sin_theta,
+        cos_theta,
+    ));
+    let wi = reflect(wo, h);
+    if n.dot(wi) <= 0.0 {
+        return None;
+    }
+    Some(wi)
+}
+
+/// Solid angle density of `ggx_sample`.
+fn ggx_pdf(n: Vec3A, wo: Vec3A, wi: Vec3A, alpha: f32) -> f32 {
+    if n.dot(wi) <= 0.0 || n.dot(wo) <= 0.0 {
+        return 0.0;
+    }
+    let h = (wi + wo).normalize();
+    let cos_h = n.dot(h);
+    ggx_d(cos_h, alpha) * cos_h / (4.0 * wo.dot(h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn test_hit() -> HitPayload<'static> {
+        let mut hit = HitPayload::new();
+        hit.world_normal = vec3a(0.0, 1.0, 0.0);
+        hit.shading_normal = hit.world_normal;
+        hit.front_face = true;
+        hit
+    }
+
+    /// Estimate the fraction of light reflected towards `wo` under uniform illumination.
+    fn albedo(material: &dyn Material, wo: Vec3A) -> Color {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(727);
+        let hit = test_hit();
+        let num_samples = 200_000;
+        let mut sum = Color::ZERO;
+        for _ in 0..num_samples {
+            if let Some(sample) = material.sample(&hit, wo, &mut rng) {
+                assert!(sample.weight.is_finite());
+                sum += sample.weight;
+            }
+        }
+        sum / num_samples as f32
+    }
+
+    #[test]
+    fn white_furnace() {
+        let wo = vec3a(0.3, 0.8, 0.1).normalize();
+
+        // A white diffuse surface reflects all light.
+        let a = albedo(&Lambertian::new(Color::ONE), wo);
+        assert!((a - Color::ONE).abs().max_element() < 0.01);
+
+        // Rough and layered surfaces may lose energy, but never create it.
+        for roughness in [0.1, 0.5, 1.0] {
+            let conductor = RoughConductor {
+                color: Color::ONE,
+                roughness,
+            };
+            assert!(albedo(&conductor, wo).max_element() < 1.01);
+
+            let gltf = GltfMaterial {
+                base_color: Color::ONE,
+                metallic: 0.0,
+                roughness,
+                emissive: Color::ZERO,
+                textures: GltfTextures::default(),
+            };
+            assert!(albedo(&gltf, wo).max_element() < 1.01);
+        }
+
+        // Clear glass either reflects or transmits everything.
+        let glass = Dielectric {
+            ior: 1.5,
+            tint: Color::ONE,
+        };
+        assert!((albedo(&glass, wo) - Color::ONE).abs().max_element() < 1e-5);
+    }
+
+    #[test]
+    fn textures_are_sampled_at_hit() {
+        use crate::{hittable::Hittable, ray::Ray, texture::Texture, triangle::Triangle};
+
+        // Black on the left half and white on the right half.
+        let black_white = Arc::new(Texture::new(2, 1, vec![Vec4::ZERO, Vec4::ONE]));
+        // Every normal is tilted towards the tangent.
+        let tilted = Arc::new(Texture::new(1, 1, vec![vec4(0.8, 0.5, 0.9, 1.0)]));
+        let material = Arc::new(GltfMaterial {
+            base_color: Color::ONE,
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive: Color::splat(2.0),
+            textures: GltfTextures {
+                base_color: Some(black_white.clone()),
+                emissive: Some(black_white),
+                normal: Some(NormalMap {
+                    texture: tilted,
+                    scale: 1.0,
+                }),
+                ..Default::default()
+            },
+        });
+
+        // A triangle in the xz plane, with u along x and v along z.
+        let mut triangle = Triangle::new(
+            vec3a(0.0, 0.0, 0.0),
+            vec3a(1.0, 0.0, 0.0),
+            vec3a(0.0, 0.0, 1.0),
+            material.clone(),
+        );
+        triangle.set_tex_coords([vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0)]);
+
+        let hit_at = |x: f32, z: f32| {
+            let r = Ray::new(vec3a(x, 1.0, z), vec3a(0.0, -1.0, 0.0));
+            let mut hit = HitPayload::new();
+            assert!(triangle.hit(&r, 0.001, f32::INFINITY, &mut hit));
+            hit
+        };
+
+        let hit = hit_at(0.25, 0.25);
+        assert!((hit.uv - vec2(0.25, 0.25)).length() < 1e-6);
+        assert_eq!(material.params(hit.uv).base_color, Color::ZERO);
+        assert_eq!(material.emitted(&hit), Color::ZERO);
+        let hit = hit_at(0.75, 0.1);
+        assert_eq!(material.params(hit.uv).base_color, Color::ONE);
+        assert_eq!(material.emitted(&hit), Color::splat(2.0));
+
+        // The green channel points against the texture's v axis, which runs along z.
+        let n = material.shading_normal(&hit);
+        assert!((n - vec3a(0.6, 0.8, 0.0).normalize()).length() < 1e-5);
+    }
+
+    #[test]
+    fn sample_weight_matches_eval() {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(69);
+        let hit = test_hit();
+        let wo = vec3a(-0.5, 0.6, 0.2).normalize();
+        let materials: [&dyn Material; 3] = [
+            &Lambertian::new(Color::splat(0.5)),
+            &RoughConductor {
+                color: Color::splat(0.9),
+                roughness: 0.3,
+            },
+            &GltfMaterial {
+                base_color: Color::new(0.8, 0.2, 0.1),
+                metallic: 0.5,
+                roughness: 0.4,
+                emissive: Color::ZERO,
+                textures: GltfTextures::default(),
+            },
+        ];
+
+        for material in materials {
+            for _ in 0..1000 {
+                let sample = match material.sample(&hit, wo, &mut rng) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                let wi = sample.direction;
+                let pdf = material.pdf(&hit, wo, wi);
+                let expected = material.eval(&hit, wo, wi) * wi.dot(hit.shading_normal) / pdf;
+                assert!((pdf - sample.pdf).abs() <= 1e-4 * pdf);
+                assert!((expected - sample.weight).abs().max_element() <= 1e-4);
+            }
+        }
+    }
+}
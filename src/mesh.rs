@@ -1,18 +1,51 @@
-use crate::{bvh::*, hittable::*, ray::*, triangle::*, Color};
-use easy_gltf::model::Mode;
+use crate::{
+    aabb::Aabb,
+    bvh::*,
+    hittable::*,
+    light::AreaLight,
+    material::{GltfMaterial, GltfTextures, Material, NormalMap},
+    obj, ply,
+    ray::*,
+    texture::{Texture, WrapMode},
+    triangle::*,
+    Color,
+};
+use easy_gltf::{model::Mode, Model};
 use glam::*;
-use rand::{Rng, SeedableRng};
-use std::error::Error;
+use image::{ImageBuffer, Pixel};
+use std::{collections::HashMap, error::Error, path::Path, sync::Arc};
 
-// TODO: Handle other data (normals, UV, materials, etc).
-
-/// For now, a Mesh is just a vector of Triangles.
+/// Triangles of a model and the BVH over them, in model space.
+/// Shared between every instance of the model.
 #[derive(Debug)]
-pub struct Mesh {
+pub struct MeshGeometry {
     // TODO: It would be more memory efficient to store an arrays of floats
     //       while also having an array of indices
     triangles: Vec<Triangle>,
     bvh: Bvh,
+}
+
+impl MeshGeometry {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        Self::with_config(triangles, BvhConfig::default())
+    }
+
+    pub fn with_config(triangles: Vec<Triangle>, config: BvhConfig) -> Self {
+        let bvh = Bvh::with_config(&triangle_bounds(&triangles), config);
+        Self { triangles, bvh }
+    }
+
+    pub fn bvh_stats(&self) -> BvhStats {
+        self.bvh.stats()
+    }
+}
+
+/// An instance of a model's geometry placed in the world.
+/// Cloning a mesh is cheap since the clone shares the geometry.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    geometry: Arc<MeshGeometry>,
+    material: Option<Arc<dyn Material>>, // Overrides the material of every triangle when set.
 
     scale: Vec3A,
     rotation: Quat,
@@ -20,82 +53,99 @@ pub struct Mesh {
 
     model_to_world: Affine3A,
     world_to_model: Affine3A,
+    normal_to_world: Mat3A, // Inverse transpose of the model to world transform.
 }
 
 #[allow(dead_code)]
 impl Mesh {
-    pub fn from_gltf(path: &str) -> Result<Self, Box<dyn Error>> {
-        // TODO: Change this. Currently just setting triangles to random colors.
-        let mut rng = rand_xoshiro::Xoroshiro128PlusPlus::from_entropy();
+    /// Load a mesh with the loader matching the file's extension, and build its BVH with
+    /// the given configuration. Files which aren't OBJ or PLY are loaded as glTF.
+    pub fn load(path: &str, config: BvhConfig) -> Result<Self, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("obj") => Self::from_obj(path, config),
+            Some("ply") => Self::from_ply(path, config),
+            _ => Self::from_gltf(path, config),
+        }
+    }
 
+    /// Load every model of a glTF file into a single mesh.
+    pub fn from_gltf(path: &str, config: BvhConfig) -> Result<Self, Box<dyn Error>> {
+        let (scenes, primitives) = load_gltf(path)?;
+        let models = scenes.iter().flat_map(|scene| &scene.models);
+        let mut converter = GltfConverter::default();
         let mut triangles = Vec::new();
-        let scenes = easy_gltf::load(path)?;
-        for scene in scenes {
-            for model in scene.models {
-                match model.mode() {
-                    Mode::TriangleFan | Mode::TriangleStrip | Mode::Triangles => {
-                        for tri in model.triangles().unwrap() {
-                            // Get vertex positions.
-                            let v0 =
-                                Vec3A::new(tri[0].position.x, tri[0].position.y, tri[0].position.z);
-                            let v1 =
-                                Vec3A::new(tri[1].position.x, tri[1].position.y, tri[1].position.z);
-                            let v2 =
-                                Vec3A::new(tri[2].position.x, tri[2].position.y, tri[2].position.z);
-
-                            // let albedo = Color::new(
-                            //     rng.gen_range(0.0..1.0),
-                            //     rng.gen_range(0.0..1.0),
-                            //     rng.gen_range(0.0..1.0),
-                            // );
-                            // let albedo = Color::new(1.0, 0.0, 0.0);
-                            let material = model.material();
-                            let albedo = material.get_base_color(tri[0].tex_coords);
-                            let emissive = material.get_emissive(tri[0].tex_coords);
-
-                            triangles.push(Triangle::new(
-                                v0,
-                                v1,
-                                v2,
-                                Color::new(albedo.x, albedo.y, albedo.z),
-                                Color::new(emissive.x, emissive.y, emissive.z),
-                            ));
-                        }
-                    }
-                    _ => panic!("Mesh must be a triangle mesh!"),
-                }
-            }
+        for (model, primitive) in models.zip(&primitives) {
+            triangles.extend(converter.triangles(model, &primitive.samplers)?);
         }
 
-        // Build bvh from triangles.
-        let bvh = Bvh::new(&triangles);
+        Ok(Self::from_triangles_with_config(triangles, config))
+    }
 
-        Ok(Self {
-            triangles,
-            bvh,
-            scale: Vec3A::ONE,
-            rotation: Quat::IDENTITY,
-            translation: Vec3A::ZERO,
-            model_to_world: Affine3A::IDENTITY,
-            world_to_model: Affine3A::IDENTITY,
-        })
+    /// Load a Wavefront OBJ file and the MTL materials it references.
+    pub fn from_obj(path: &str, config: BvhConfig) -> Result<Self, Box<dyn Error>> {
+        let triangles = obj::load(Path::new(path))?;
+        Ok(Self::from_triangles_with_config(triangles, config))
+    }
+
+    /// Load an ASCII or binary little-endian PLY file.
+    pub fn from_ply(path: &str, config: BvhConfig) -> Result<Self, Box<dyn Error>> {
+        let triangles = ply::load(Path::new(path))?;
+        Ok(Self::from_triangles_with_config(triangles, config))
     }
 
     pub fn from_triangles(triangles: Vec<Triangle>) -> Self {
-        let bvh = Bvh::new(&triangles);
+        Self::from_geometry(Arc::new(MeshGeometry::new(triangles)))
+    }
+
+    pub fn from_triangles_with_config(triangles: Vec<Triangle>, config: BvhConfig) -> Self {
+        Self::from_geometry(Arc::new(MeshGeometry::with_config(triangles, config)))
+    }
+
+    /// Create an instance of existing geometry with an identity transform.
+    pub fn from_geometry(geometry: Arc<MeshGeometry>) -> Self {
         Self {
-            triangles,
-            bvh,
+            geometry,
+            material: None,
             scale: Vec3A::ONE,
             rotation: Quat::IDENTITY,
             translation: Vec3A::ZERO,
             model_to_world: Affine3A::IDENTITY,
             world_to_model: Affine3A::IDENTITY,
+            normal_to_world: Mat3A::IDENTITY,
         }
     }
 
+    pub fn geometry(&self) -> &Arc<MeshGeometry> {
+        &self.geometry
+    }
+
+    /// Triangles of the mesh in model space.
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.geometry.triangles
+    }
+
     pub fn num_triangles(&self) -> usize {
-        self.triangles.len()
+        self.geometry.triangles.len()
+    }
+
+    /// Rebuild the mesh's BVH with a different configuration.
+    /// This gives the mesh its own copy of the geometry, other instances are unaffected.
+    pub fn build_bvh(&mut self, config: BvhConfig) {
+        let triangles = self.geometry.triangles.clone();
+        self.geometry = Arc::new(MeshGeometry::with_config(triangles, config));
+    }
+
+    pub fn bvh_stats(&self) -> BvhStats {
+        self.geometry.bvh_stats()
+    }
+
+    /// Use a single material for the whole mesh instead of the per-triangle materials.
+    pub fn set_material(&mut self, material: Arc<dyn Material>) {
+        self.material = Some(material);
     }
 
     /// Set the translation for the mesh.
@@ -104,68 +154,453 @@ impl Mesh {
         self.translation = translation;
         self.model_to_world = Affine3A::from_scale_rotation_translation(
             self.scale.into(),
-            self.rotation.into(),
+            self.rotation,
             self.translation.into(),
         );
-        self.world_to_model = self.model_to_world.inverse();
+        self.update_inverse_transforms();
+    }
+
+    /// Set the model to world transform of this instance.
+    pub fn set_transform(&mut self, model_to_world: Affine3A) {
+        let (scale, rotation, translation) = model_to_world.to_scale_rotation_translation();
+        self.scale = scale.into();
+        self.rotation = rotation;
+        self.translation = translation.into();
+        self.model_to_world = model_to_world;
+        self.update_inverse_transforms();
     }
 
     pub fn transformation(&mut self, scale: Vec3A, rotation: Quat, translation: Vec3A) {
         self.scale = scale;
         self.rotation = rotation;
         self.translation = translation;
-        self.model_to_world = Affine3A::from_scale_rotation_translation(
-            scale.into(),
-            rotation.into(),
-            translation.into(),
-        );
+        self.model_to_world =
+            Affine3A::from_scale_rotation_translation(scale.into(), rotation, translation.into());
+        self.update_inverse_transforms();
+    }
+
+    fn update_inverse_transforms(&mut self) {
         self.world_to_model = self.model_to_world.inverse();
+        // Normals stay perpendicular to the surface under non-uniform scale when transformed
+        // with the inverse transpose.
+        self.normal_to_world = self.world_to_model.matrix3.transpose();
     }
 }
 
 impl Hittable for Mesh {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload) -> bool {
-        // Transform the ray to model space.
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        // Transform the ray to model space. The direction isn't renormalized so that hit
+        // distances stay in world units and can be compared with those of other objects.
         let ray = Ray::new(
             self.world_to_model.transform_point3a(r.origin()),
-            self.world_to_model
-                .transform_vector3a(r.direction().normalize()),
+            self.world_to_model.transform_vector3a(r.direction()),
         );
 
-        let use_bvh = true;
-        if use_bvh {
-            let hit_anything = if self.bvh.hit(&ray, t_min, t_max, rec) {
-                // Transform the hit position and hit surface normal back to world space.
-                rec.world_position = self.model_to_world.transform_point3a(rec.world_position);
-                rec.world_normal = self.model_to_world.transform_vector3a(rec.world_normal);
-
-                true
+        // Triangles only record hits closer than the distance they're given.
+        let triangles = &self.geometry.triangles;
+        let mut visits = 0;
+        let bvh = &self.geometry.bvh;
+        let hit_anything = bvh.traverse(&ray, t_min, t_max, &mut visits, |i, t_max| {
+            if triangles[i].hit(&ray, t_min, t_max, rec) {
+                rec.primitive_index = i;
+                Some(rec.hit_distance)
             } else {
-                false
-            };
-            hit_anything
-        } else {
-            let hit_anything = false;
-            let mut temp_rec = HitPayload::new();
-            let mut hit_anything = false;
-            let mut closest_so_far = t_max;
-
-            for triangle in &self.triangles {
-                if triangle.hit(&ray, t_min, closest_so_far, &mut temp_rec) {
-                    hit_anything = true;
-                    closest_so_far = temp_rec.hit_distance;
-
-                    rec.world_position = temp_rec.world_position;
-                    rec.world_normal = temp_rec.world_normal;
-                    rec.hit_distance = temp_rec.hit_distance;
-                    rec.front_face = temp_rec.front_face;
-                }
+                None
             }
+        });
+        rec.stats.bvh_node_visits += visits;
+        if hit_anything {
             // Transform the hit position and hit surface normal back to world space.
             rec.world_position = self.model_to_world.transform_point3a(rec.world_position);
-            rec.world_normal = self.model_to_world.transform_vector3a(rec.world_normal);
+            rec.world_normal = (self.normal_to_world * rec.world_normal).normalize();
+            rec.shading_normal = (self.normal_to_world * rec.shading_normal).normalize();
+            rec.tangent = self.model_to_world.transform_vector3a(rec.tangent);
+            rec.bitangent = self.model_to_world.transform_vector3a(rec.bitangent);
+            if let Some(material) = &self.material {
+                rec.material = Some(material.as_ref());
+            }
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.geometry.bvh.bounds().transform(&self.model_to_world)
+    }
+
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        self.geometry
+            .triangles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, triangle)| {
+                let material = self.material.as_ref().unwrap_or(triangle.material());
+                // Emission doesn't depend on the transform, so only emitters are moved.
+                if triangle.emission_of(material.as_ref()) == Color::ZERO {
+                    return None;
+                }
+                let mut emitter = triangle.transformed(&self.model_to_world, &self.normal_to_world);
+                emitter.set_material(material.clone());
+                Some((i, Arc::new(emitter) as Arc<dyn AreaLight>))
+            })
+            .collect()
+    }
+}
+
+/// Model space bounds of each triangle, for building the mesh's BVH.
+fn triangle_bounds(triangles: &[Triangle]) -> Vec<Aabb> {
+    triangles.iter().map(|t| t.bounding_box()).collect()
+}
 
-            hit_anything
+/// What easy_gltf leaves out about one of the models it loads, which is read from the
+/// document itself.
+#[derive(Debug, Clone)]
+pub struct GltfPrimitive {
+    /// Index of the glTF mesh and of the primitive within it. Nodes which use the same
+    /// mesh load the same primitives.
+    pub mesh: usize,
+    pub primitive: usize,
+    /// Model to world transform of the node, which easy_gltf applies to the vertices.
+    pub transform: Affine3A,
+    pub samplers: GltfSamplers,
+}
+
+/// Wrap modes of the textures of a glTF material, along u and v.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GltfSamplers {
+    pub base_color: [WrapMode; 2],
+    pub metallic_roughness: [WrapMode; 2],
+    pub normal: [WrapMode; 2],
+    pub emissive: [WrapMode; 2],
+}
+
+impl GltfSamplers {
+    fn new(material: &gltf::Material) -> Self {
+        let wrap = |texture: Option<gltf::Texture>| match texture {
+            Some(texture) => {
+                let sampler = texture.sampler();
+                [sampler.wrap_s(), sampler.wrap_t()].map(|mode| match mode {
+                    gltf::texture::WrappingMode::Repeat => WrapMode::Repeat,
+                    gltf::texture::WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+                    gltf::texture::WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+                })
+            }
+            None => Default::default(),
+        };
+        let pbr = material.pbr_metallic_roughness();
+        Self {
+            base_color: wrap(pbr.base_color_texture().map(|info| info.texture())),
+            metallic_roughness: wrap(pbr.metallic_roughness_texture().map(|info| info.texture())),
+            normal: wrap(material.normal_texture().map(|normal| normal.texture())),
+            emissive: wrap(material.emissive_texture().map(|info| info.texture())),
+        }
+    }
+}
+
+/// Load a glTF file with easy_gltf, along with a primitive for each of the models of its
+/// scenes, in the same order.
+pub fn load_gltf(
+    path: &str,
+) -> Result<(Vec<easy_gltf::Scene>, Vec<GltfPrimitive>), Box<dyn Error>> {
+    let scenes = easy_gltf::load(path)?;
+    let document = gltf::Gltf::open(path)?;
+
+    // easy_gltf loads the nodes depth first, with the primitives of a node before those of
+    // its children.
+    fn visit(node: gltf::Node, parent: Mat4, primitives: &mut Vec<GltfPrimitive>) {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                primitives.push(GltfPrimitive {
+                    mesh: mesh.index(),
+                    primitive: primitive.index(),
+                    transform: Affine3A::from_mat4(transform),
+                    samplers: GltfSamplers::new(&primitive.material()),
+                });
+            }
+        }
+        for child in node.children() {
+            visit(child, transform, primitives);
+        }
+    }
+    let mut primitives = Vec::new();
+    for scene in document.scenes() {
+        for node in scene.nodes() {
+            visit(node, Mat4::IDENTITY, &mut primitives);
+        }
+    }
+
+    let num_models: usize = scenes.iter().map(|scene| scene.models.len()).sum();
+    if primitives.len() != num_models {
+        return Err(format!(
+            "Found {} primitives, but {} models were loaded",
+            primitives.len(),
+            num_models
+        )
+        .into());
+    }
+    Ok((scenes, primitives))
+}
+
+/// Converts glTF models to triangles. Materials and images are converted once and shared
+/// between all the triangles which use them.
+#[derive(Default)]
+pub struct GltfConverter {
+    materials: HashMap<usize, Arc<dyn Material>>,
+    // Keyed by the address of the image and the wrap modes.
+    textures: HashMap<(usize, [WrapMode; 2]), Arc<Texture>>,
+}
+
+impl GltfConverter {
+    /// Triangles of a model, in the space of the glTF scene.
+    pub fn triangles(
+        &mut self,
+        model: &Model,
+        samplers: &GltfSamplers,
+    ) -> Result<Vec<Triangle>, Box<dyn Error>> {
+        let mode = model.mode();
+        if !matches!(
+            mode,
+            Mode::TriangleFan | Mode::TriangleStrip | Mode::Triangles
+        ) {
+            return Err(
+                format!("Unsupported primitive mode {:?}, expected triangles", mode).into(),
+            );
+        }
+
+        let material = self.material(&model.material(), samplers);
+        let mut triangles = Vec::new();
+        for tri in model.triangles()? {
+            // Get vertex positions.
+            let v0 = Vec3A::new(tri[0].position.x, tri[0].position.y, tri[0].position.z);
+            let v1 = Vec3A::new(tri[1].position.x, tri[1].position.y, tri[1].position.z);
+            let v2 = Vec3A::new(tri[2].position.x, tri[2].position.y, tri[2].position.z);
+            // Models without normals have them zeroed and are shaded flat.
+            let normals =
+                [0, 1, 2].map(|i| Vec3A::new(tri[i].normal.x, tri[i].normal.y, tri[i].normal.z));
+
+            let mut triangle = Triangle::with_normals(v0, v1, v2, normals, material.clone());
+            if model.has_tex_coords() {
+                triangle.set_tex_coords(
+                    [0, 1, 2].map(|i| vec2(tri[i].tex_coords.x, tri[i].tex_coords.y)),
+                );
+            }
+            triangles.push(triangle);
+        }
+        Ok(triangles)
+    }
+
+    /// Convert a glTF material. Color textures are sRGB encoded, the others are linear.
+    /// The samplers belong to the material, so it's still converted only once.
+    fn material(
+        &mut self,
+        material: &Arc<easy_gltf::Material>,
+        samplers: &GltfSamplers,
+    ) -> Arc<dyn Material> {
+        let key = Arc::as_ptr(material) as usize;
+        if let Some(converted) = self.materials.get(&key) {
+            return converted.clone();
+        }
+
+        let pbr = &material.pbr;
+        let base_color = pbr.base_color_factor;
+        let emissive = material.emissive.factor;
+        let converted: Arc<dyn Material> = Arc::new(GltfMaterial {
+            base_color: Color::new(base_color.x, base_color.y, base_color.z),
+            metallic: pbr.metallic_factor,
+            roughness: pbr.roughness_factor,
+            emissive: Color::new(emissive.x, emissive.y, emissive.z),
+            textures: GltfTextures {
+                base_color: pbr
+                    .base_color_texture
+                    .as_ref()
+                    .map(|t| self.texture(t, true, samplers.base_color)),
+                metallic: pbr
+                    .metallic_texture
+                    .as_ref()
+                    .map(|t| self.texture(t, false, samplers.metallic_roughness)),
+                roughness: pbr
+                    .roughness_texture
+                    .as_ref()
+                    .map(|t| self.texture(t, false, samplers.metallic_roughness)),
+                emissive: material
+                    .emissive
+                    .texture
+                    .as_ref()
+                    .map(|t| self.texture(t, true, samplers.emissive)),
+                normal: material.normal.as_ref().map(|normal| NormalMap {
+                    texture: self.texture(&normal.texture, false, samplers.normal),
+                    scale: normal.factor,
+                }),
+            },
+        });
+        self.materials.insert(key, converted.clone());
+        converted
+    }
+
+    fn texture<P: Pixel<Subpixel = u8>>(
+        &mut self,
+        image: &Arc<ImageBuffer<P, Vec<u8>>>,
+        srgb: bool,
+        wrap: [WrapMode; 2],
+    ) -> Arc<Texture> {
+        self.textures
+            .entry((Arc::as_ptr(image) as usize, wrap))
+            .or_insert_with(|| {
+                let mut texture = Texture::from_image(image.as_ref(), srgb);
+                [texture.wrap_u, texture.wrap_v] = wrap;
+                Arc::new(texture)
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn instances_share_geometry() {
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        let triangle = Triangle::new(
+            vec3a(-1.0, -1.0, 0.0),
+            vec3a(1.0, -1.0, 0.0),
+            vec3a(0.0, 1.0, 0.0),
+            material,
+        );
+        let mesh = Mesh::from_triangles(vec![triangle]);
+        let mut near = Mesh::from_geometry(mesh.geometry().clone());
+        near.set_transform(Affine3A::from_translation(vec3(0.0, 0.0, -2.0)));
+        let mut far = near.clone();
+        far.set_transform(Affine3A::from_scale_rotation_translation(
+            Vec3::splat(2.0),
+            Quat::from_rotation_z(1.0),
+            vec3(0.0, 0.0, -5.0),
+        ));
+        let override_material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::ZERO));
+        far.set_material(override_material.clone());
+
+        assert!(Arc::ptr_eq(near.geometry(), far.geometry()));
+        assert_eq!(Arc::strong_count(mesh.geometry()), 3);
+
+        let r = Ray::new(Vec3A::ZERO, vec3a(0.0, 0.0, -1.0));
+        let mut rec = HitPayload::new();
+        assert!(near.hit(&r, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.hit_distance - 2.0).abs() < 1e-5);
+        let mut rec = HitPayload::new();
+        assert!(far.hit(&r, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.hit_distance - 5.0).abs() < 1e-5);
+        assert!(std::ptr::eq(
+            rec.material.unwrap() as *const dyn Material as *const u8,
+            override_material.as_ref() as *const dyn Material as *const u8,
+        ));
+
+        // Rebuilding one instance's BVH leaves the others alone.
+        far.build_bvh(BvhConfig::default());
+        assert!(!Arc::ptr_eq(near.geometry(), far.geometry()));
+    }
+
+    #[test]
+    fn normals_under_non_uniform_scale() {
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        // A triangle tilted by 45 degrees around the x axis, with its vertex normals
+        // bent outwards as if it were part of a curved surface.
+        let vertices = [
+            vec3a(-1.0, -1.0, 1.0),
+            vec3a(1.0, -1.0, 1.0),
+            vec3a(0.0, 1.0, -1.0),
+        ];
+        let face_normal = vec3a(0.0, 1.0, 1.0).normalize();
+        let normals = [
+            (face_normal + vec3a(-0.5, 0.0, 0.0)).normalize(),
+            (face_normal + vec3a(0.5, 0.0, 0.0)).normalize(),
+            face_normal,
+        ];
+        let [v0, v1, v2] = vertices;
+        let mut mesh =
+            Mesh::from_triangles(vec![Triangle::with_normals(v0, v1, v2, normals, material)]);
+        let m = Affine3A::from_scale(vec3(1.0, 4.0, 1.0));
+        mesh.set_transform(m);
+
+        let r = Ray::new(vec3a(0.0, 0.0, 5.0), vec3a(0.0, 0.0, -1.0));
+        let mut rec = HitPayload::new();
+        assert!(mesh.hit(&r, 0.001, f32::INFINITY, &mut rec));
+
+        // The geometric normal must be perpendicular to the world space edges.
+        let [w0, w1, w2] = vertices.map(|v| m.transform_point3a(v));
+        assert!(rec.world_normal.dot(w1 - w0).abs() < 1e-5);
+        assert!(rec.world_normal.dot(w2 - w0).abs() < 1e-5);
+        assert!(rec.world_normal.dot(r.direction()) < 0.0);
+        // On the symmetry axis of the triangle the bent normals cancel out.
+        assert!((rec.shading_normal - rec.world_normal).length() < 1e-4);
+
+        // Off the axis the shading normal leans towards the nearer vertex.
+        let r = Ray::new(vec3a(0.5, -2.0, 5.0), vec3a(0.0, 0.0, -1.0));
+        let mut rec = HitPayload::new();
+        assert!(mesh.hit(&r, 0.001, f32::INFINITY, &mut rec));
+        assert!(rec.shading_normal.x > 0.1);
+        assert!(rec.shading_normal.dot(rec.world_normal) > 0.0);
+        assert!((rec.shading_normal.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn gltf_samplers_wrap_textures() {
+        let path = "assets/fixtures/clamped_quad.gltf";
+        let (_, primitives) = load_gltf(path).unwrap();
+        assert_eq!(primitives.len(), 1);
+        assert_eq!(
+            primitives[0].samplers.base_color,
+            [WrapMode::ClampToEdge, WrapMode::MirroredRepeat]
+        );
+        assert_eq!(primitives[0].samplers.emissive, [WrapMode::Repeat; 2]);
+
+        // The texture coordinates start half a texture left of the quad, and the left
+        // column of the bottom row is red. Repeating the texture would show the white
+        // right column instead.
+        let mesh = Mesh::from_gltf(path, BvhConfig::default()).unwrap();
+        let r = Ray::new(vec3a(0.25, 0.5, 1.0), vec3a(0.0, 0.0, -1.0));
+        let mut rec = HitPayload::new();
+        assert!(mesh.hit(&r, 0.001, f32::INFINITY, &mut rec));
+        assert!((rec.uv - vec2(-0.25, 0.75)).length() < 1e-5);
+        let albedo = rec.material.unwrap().albedo(&rec);
+        assert!(
+            (albedo - vec3a(1.0, 0.0, 0.0)).length() < 1e-5,
+            "{}",
+            albedo
+        );
+    }
+
+    /// Compare the trees built for the bundled models.
+    /// Run with `cargo test --release sah_improves_bundled_assets -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn sah_improves_bundled_assets() {
+        let models = [
+            "assets/cornell.glb",
+            "assets/cornell_light.glb",
+            "assets/cube.glb",
+            "assets/icosphere.glb",
+            "assets/monkey.glb",
+            "assets/plane.glb",
+        ];
+        for path in models {
+            let midpoint_config = BvhConfig {
+                split_method: SplitMethod::Midpoint,
+                ..Default::default()
+            };
+            let mut mesh = Mesh::from_gltf(path, midpoint_config).unwrap();
+            let midpoint = mesh.bvh_stats();
+            mesh.build_bvh(BvhConfig::default());
+            let sah = mesh.bvh_stats();
+
+            println!("{} ({} triangles)", path, mesh.num_triangles());
+            println!("    Midpoint: {}", midpoint);
+            println!("    SAH:      {}", sah);
+
+            // The heuristic is greedy, so only expect an improvement on non-trivial models.
+            if mesh.num_triangles() >= 100 {
+                assert!(sah.sah_cost < midpoint.sah_cost, "{}", path);
+            }
         }
     }
 }
@@ -0,0 +1,308 @@
+use crate::{
+    material::{Dielectric, GltfMaterial, GltfTextures, Lambertian, Material},
+    texture::Texture,
+    triangle::Triangle,
+    Color,
+};
+use glam::*;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str::SplitWhitespace,
+    sync::Arc,
+};
+
+/// Load the triangles of a Wavefront OBJ file. Polygons are triangulated as fans.
+///
+/// Materials are read from the MTL files named by `mtllib`, which are resolved relative
+/// to the OBJ file. Faces without a material get a grey diffuse one.
+pub fn load(path: &Path) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    let src = fs::read_to_string(path)
+        .map_err(|e| format!("{}: Failed to read OBJ file: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut textures = HashMap::new();
+    let mut material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::splat(0.8)));
+
+    let mut positions = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut normals = Vec::new();
+    let mut triangles = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", path.display(), i + 1, message);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => positions.push(Vec3A::from(floats::<3>(&mut tokens).map_err(error)?)),
+            Some("vt") => {
+                let [u, v] = floats::<2>(&mut tokens).map_err(error)?;
+                // OBJ texture coordinates start at the bottom of the image.
+                tex_coords.push(vec2(u, 1.0 - v));
+            }
+            Some("vn") => normals.push(Vec3A::from(floats::<3>(&mut tokens).map_err(error)?)),
+            Some("f") => {
+                let counts = [positions.len(), tex_coords.len(), normals.len()];
+                let corners = tokens
+                    .map(|corner| face_corner(corner, counts))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+                if corners.len() < 3 {
+                    return Err(error("Faces need at least 3 vertices".into()).into());
+                }
+
+                for j in 1..corners.len() - 1 {
+                    let corners = [corners[0], corners[j], corners[j + 1]];
+                    let [v0, v1, v2] = corners.map(|c| positions[c[0].unwrap()]);
+                    let mut triangle = if corners.iter().all(|c| c[2].is_some()) {
+                        let n = corners.map(|c| normals[c[2].unwrap()]);
+                        Triangle::with_normals(v0, v1, v2, n, material.clone())
+                    } else {
+                        Triangle::new(v0, v1, v2, material.clone())
+                    };
+                    if corners.iter().all(|c| c[1].is_some()) {
+                        triangle.set_tex_coords(corners.map(|c| tex_coords[c[1].unwrap()]));
+                    }
+                    triangles.push(triangle);
+                }
+            }
+            Some("mtllib") => {
+                for name in tokens {
+                    let library = load_mtl(&base_dir.join(name), &mut textures)?;
+                    materials.extend(library);
+                }
+            }
+            Some("usemtl") => {
+                let name = tokens.next().unwrap_or_default();
+                material = materials
+                    .get(name)
+                    .ok_or_else(|| error(format!("Unknown material '{}'", name)))?
+                    .clone();
+            }
+            // Comments, objects, groups and smoothing groups don't affect the triangles.
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+/// Parameters of an MTL material which are converted to one of ours.
+#[derive(Default)]
+struct MtlDesc {
+    diffuse: Option<Color>,
+    emission: Option<Color>,
+    specular_exponent: Option<f32>,
+    ior: Option<f32>,
+    illum: Option<u32>,
+    diffuse_map: Option<PathBuf>,
+}
+
+impl MtlDesc {
+    fn to_material(
+        &self,
+        textures: &mut HashMap<PathBuf, Arc<Texture>>,
+    ) -> Result<Arc<dyn Material>, Box<dyn Error>> {
+        // Illumination models 4, 6, 7 and 9 are transparent.
+        if matches!(self.illum, Some(4 | 6 | 7 | 9)) {
+            return Ok(Arc::new(Dielectric {
+                ior: self.ior.unwrap_or(1.5),
+                tint: Color::ONE,
+            }));
+        }
+
+        // Textures shared by several materials are only loaded once.
+        let diffuse_map = match &self.diffuse_map {
+            Some(path) => Some(match textures.get(path) {
+                Some(texture) => texture.clone(),
+                None => {
+                    let image = image::open(path).map_err(|e| {
+                        format!("{}: Failed to load texture: {}", path.display(), e)
+                    })?;
+                    let texture = Arc::new(Texture::from_image(&image.to_rgba8(), true));
+                    textures.insert(path.clone(), texture.clone());
+                    texture
+                }
+            }),
+            None => None,
+        };
+
+        // Convert the Phong exponent to the roughness of a microfacet distribution with a
+        // similar highlight. The index of refraction of opaque materials is ignored.
+        let roughness = match self.specular_exponent {
+            Some(ns) => (2.0 / (ns.max(0.0) + 2.0)).sqrt().sqrt(),
+            None => 1.0,
+        };
+        Ok(Arc::new(GltfMaterial {
+            base_color: self.diffuse.unwrap_or(Color::splat(0.8)),
+            metallic: 0.0,
+            roughness,
+            emissive: self.emission.unwrap_or(Color::ZERO),
+            textures: GltfTextures {
+                base_color: diffuse_map,
+                ..Default::default()
+            },
+        }))
+    }
+}
+
+/// Load the materials of an MTL file. Texture paths are resolved relative to the file.
+fn load_mtl(
+    path: &Path,
+    textures: &mut HashMap<PathBuf, Arc<Texture>>,
+) -> Result<HashMap<String, Arc<dyn Material>>, Box<dyn Error>> {
+    let src = fs::read_to_string(path)
+        .map_err(|e| format!("{}: Failed to read MTL file: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut descs: Vec<(String, MtlDesc)> = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let error = |message: String| format!("{}:{}: {}", path.display(), i + 1, message);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = tokens
+                .next()
+                .ok_or_else(|| error("Missing material name".into()))?;
+            descs.push((name.to_string(), MtlDesc::default()));
+            continue;
+        }
+        if keyword.starts_with('#') {
+            continue;
+        }
+
+        let desc = match descs.last_mut() {
+            Some((_, desc)) => desc,
+            None => return Err(error("Material parameters before 'newmtl'".into()).into()),
+        };
+        match keyword {
+            "Kd" => desc.diffuse = Some(Color::from(floats::<3>(&mut tokens).map_err(error)?)),
+            "Ke" => desc.emission = Some(Color::from(floats::<3>(&mut tokens).map_err(error)?)),
+            "Ns" => desc.specular_exponent = Some(floats::<1>(&mut tokens).map_err(error)?[0]),
+            "Ni" => desc.ior = Some(floats::<1>(&mut tokens).map_err(error)?[0]),
+            "illum" => {
+                let illum = tokens.next().and_then(|t| t.parse().ok());
+                desc.illum = Some(illum.ok_or_else(|| error("Expected an integer".into()))?);
+            }
+            "map_Kd" => {
+                // Options come before the file name, which is the last token.
+                let name = tokens
+                    .last()
+                    .ok_or_else(|| error("Missing texture file".into()))?;
+                let texture_path = base_dir.join(name);
+                if !texture_path.is_file() {
+                    return Err(error(format!(
+                        "Texture file '{}' does not exist",
+                        texture_path.display()
+                    ))
+                    .into());
+                }
+                desc.diffuse_map = Some(texture_path);
+            }
+            // Other parameters have no equivalent in our materials.
+            _ => {}
+        }
+    }
+
+    let mut materials = HashMap::new();
+    for (name, desc) in descs {
+        materials.insert(name, desc.to_material(textures)?);
+    }
+    Ok(materials)
+}
+
+/// Parse the next `N` tokens as numbers. Extra tokens, like the optional `w` of
+/// positions, are ignored.
+fn floats<const N: usize>(tokens: &mut SplitWhitespace) -> Result<[f32; N], String> {
+    let mut values = [0.0; N];
+    for value in &mut values {
+        *value = tokens
+            .next()
+            .ok_or_else(|| format!("Expected {} numbers", N))?
+            .parse()
+            .map_err(|e| format!("Invalid number: {}", e))?;
+    }
+    Ok(values)
+}
+
+/// Parse a face corner of the form `v`, `v/vt`, `v//vn` or `v/vt/vn` into zero-based
+/// indices. Negative indices count back from the end of the elements read so far.
+fn face_corner(corner: &str, counts: [usize; 3]) -> Result<[Option<usize>; 3], String> {
+    let mut indices = [None; 3];
+    for (k, part) in corner.split('/').enumerate() {
+        if k >= 3 {
+            return Err(format!("Invalid face vertex '{}'", corner));
+        }
+        if part.is_empty() {
+            continue;
+        }
+        let index: i64 = part
+            .parse()
+            .map_err(|_| format!("Invalid face vertex '{}'", corner))?;
+        let count = counts[k] as i64;
+        let index = if index < 0 { count + index } else { index - 1 };
+        if index < 0 || index >= count {
+            return Err(format!("Face index {} is out of range", part));
+        }
+        indices[k] = Some(index as usize);
+    }
+    if indices[0].is_none() {
+        return Err(format!("Face vertex '{}' has no position", corner));
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::*, light::AreaLight, ray::Ray};
+
+    #[test]
+    fn load_fixture() {
+        let triangles = load(Path::new("assets/fixtures/quad.obj")).unwrap();
+        // A triangle without a material followed by a quad.
+        assert_eq!(triangles.len(), 3);
+
+        // The triangle uses negative indices and has the default material.
+        assert_eq!(triangles[0].vertices()[2], vec3a(0.0, 1.0, -1.0));
+        assert_eq!(triangles[0].tex_coords(), None);
+        assert_eq!(triangles[0].emission(), Color::ZERO);
+
+        // The quad's material emits light and is textured.
+        assert_eq!(triangles[1].emission(), Color::new(1.0, 2.0, 3.0));
+        let r = Ray::new(vec3a(0.25, 0.25, 1.0), vec3a(0.0, 0.0, -1.0));
+        let mut hit = HitPayload::new();
+        assert!(triangles[1..].iter().any(|triangle| triangle.hit(
+            &r,
+            0.0,
+            f32::INFINITY,
+            &mut hit
+        )));
+        // Smooth normals are read from `vn`.
+        assert!((hit.shading_normal - vec3a(0.0, 0.0, 1.0)).length() < 1e-5);
+        // The bottom left of the quad maps to the bottom left of the image.
+        assert!((hit.uv - vec2(0.25, 0.75)).length() < 1e-5);
+        // Kd is multiplied by the red bottom left quadrant of the texture.
+        let n = hit.shading_normal;
+        let f = hit.material.unwrap().eval(&hit, n, n);
+        assert!(f.x > f.y + 0.1);
+        assert_eq!(f.y, f.z);
+    }
+
+    #[test]
+    fn reports_bad_faces() {
+        assert!(face_corner("1/2/3", [1, 2, 3]).is_ok());
+        assert_eq!(
+            face_corner("-1//1", [4, 0, 1]),
+            Ok([Some(3), None, Some(0)])
+        );
+        assert!(face_corner("5", [4, 0, 0]).is_err());
+        assert!(face_corner("0", [4, 0, 0]).is_err());
+        assert!(face_corner("1/x", [4, 4, 0]).is_err());
+        assert!(face_corner("/1", [4, 4, 0]).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{aabb::Aabb, hittable::*, material::Material, onb::Onb, ray::*};
+use glam::*;
+use std::sync::Arc;
+
+/// Infinite plane through a point, such as a ground plane. The front face is on the side
+/// of `normal`.
+///
+/// Texture coordinates are distances along two directions in the plane, so textures repeat
+/// every unit. Planes have infinite area, so they can't be sampled as lights.
+#[derive(Debug, Clone)]
+pub struct Plane {
+    point: Vec3A,
+    normal: Vec3A,
+    // Directions in the plane along which the texture coordinates increase.
+    tangent: Vec3A,
+    bitangent: Vec3A,
+    material: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Vec3A, normal: Vec3A, material: Arc<dyn Material>) -> Self {
+        let onb = Onb::from_w(normal);
+        Self {
+            point,
+            normal: normal.normalize(),
+            tangent: onb.local(Vec3A::X),
+            bitangent: onb.local(-Vec3A::Y),
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        let denom = self.normal.dot(r.direction());
+        if denom.abs() < 1e-8 {
+            return false; // Ray is parallel to the plane.
+        }
+        let t = self.normal.dot(self.point - r.origin()) / denom;
+        if !(t_min..=t_max).contains(&t) {
+            return false;
+        }
+
+        let position = r.origin() + t * r.direction();
+        let offset = position - self.point;
+        rec.hit_distance = t;
+        rec.world_position = position;
+        rec.set_face_normal(r, self.normal);
+        rec.uv = vec2(offset.dot(self.tangent), offset.dot(self.bitangent));
+        rec.tangent = self.tangent;
+        rec.bitangent = self.bitangent;
+        rec.material = Some(self.material.as_ref());
+        rec.barycentrics = None;
+
+        true
+    }
+
+    /// Planes are unbounded, unless they're perpendicular to an axis.
+    fn bounding_box(&self) -> Aabb {
+        let mut aabb = Aabb {
+            min: Vec3A::splat(f32::NEG_INFINITY),
+            max: Vec3A::splat(f32::INFINITY),
+        };
+        for axis in 0..3 {
+            if self.normal[axis].abs() == 1.0 {
+                aabb.min[axis] = self.point[axis];
+                aabb.max[axis] = self.point[axis];
+            }
+        }
+        aabb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable_list::HittableList, material::Lambertian, sphere::Sphere, Color};
+
+    #[test]
+    fn ground_plane_in_list() {
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        let plane = Plane::new(Vec3A::ZERO, vec3a(0.0, 1.0, 0.0), material.clone());
+        let aabb = plane.bounding_box();
+        assert_eq!(aabb.min.y, 0.0);
+        assert_eq!(aabb.max.y, 0.0);
+        assert_eq!(aabb.max.x, f32::INFINITY);
+
+        // The plane is hit far outside of the top-level BVH's bounds.
+        let mut list = HittableList::new();
+        list.add(Sphere::new(vec3a(0.0, 1.0, 0.0), 1.0, material));
+        list.add(plane);
+        list.build_bvh();
+
+        let mut rec = HitPayload::new();
+        let r = Ray::new(vec3a(100.0, 1.0, 0.0), vec3a(0.0, -1.0, 0.0));
+        assert!(list.hit(&r, 0.0, f32::INFINITY, &mut rec));
+        assert_eq!(rec.object_index, 1);
+        assert_eq!(rec.hit_distance, 1.0);
+        assert_eq!(rec.world_normal, vec3a(0.0, 1.0, 0.0));
+
+        // The sphere is still found in front of the plane.
+        let r = Ray::new(vec3a(0.0, 5.0, 0.0), vec3a(0.0, -1.0, 0.0));
+        assert!(list.hit(&r, 0.0, f32::INFINITY, &mut rec));
+        assert_eq!(rec.object_index, 0);
+        assert_eq!(rec.hit_distance, 3.0);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::{
+    material::{Lambertian, Material},
+    triangle::Triangle,
+    Color,
+};
+use glam::*;
+use std::{error::Error, fs, path::Path, str::SplitAsciiWhitespace, sync::Arc};
+
+/// Load the triangles of a PLY file in the ASCII or binary little-endian format.
+///
+/// Vertices need `x`, `y` and `z` properties, and may have `nx`, `ny`, `nz` normals and
+/// `u`, `v` (or `s`, `t`) texture coordinates. Faces are read from the `vertex_indices`
+/// list and triangulated as fans. PLY files have no materials, so a grey diffuse one is used.
+pub fn load(path: &Path) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    let bytes = fs::read(path)
+        .map_err(|e| format!("{}: Failed to read PLY file: {}", path.display(), e))?;
+    parse(&bytes).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return Err(format!("Unknown property type '{}'", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    ty: ScalarType,
+    /// Type of the length of list properties.
+    count_ty: Option<ScalarType>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|p| p.count_ty.is_none() && names.contains(&p.name.as_str()))
+    }
+}
+
+/// Reads the values of the body in either format.
+struct BodyReader<'a> {
+    format: Format,
+    tokens: SplitAsciiWhitespace<'a>,
+    bytes: &'a [u8],
+}
+
+impl<'a> BodyReader<'a> {
+    /// Upper bound on the number of values of a type which are left to read. Binary files
+    /// have exactly this many, while ASCII values take at least a digit and a separator.
+    fn max_values(&self, ty: ScalarType) -> usize {
+        match self.format {
+            Format::Ascii => self.bytes.len() / 2 + 1,
+            Format::BinaryLittleEndian => self.bytes.len() / ty.size(),
+        }
+    }
+
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        match self.format {
+            Format::Ascii => self
+                .tokens
+                .next()
+                .ok_or("Unexpected end of file")?
+                .parse()
+                .map_err(|e| format!("Invalid number: {}", e)),
+            Format::BinaryLittleEndian => {
+                if self.bytes.len() < ty.size() {
+                    return Err("Unexpected end of file".into());
+                }
+                let (value, rest) = self.bytes.split_at(ty.size());
+                self.bytes = rest;
+                Ok(match ty {
+                    ScalarType::I8 => value[0] as i8 as f64,
+                    ScalarType::U8 => value[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes([value[0], value[1]]) as f64,
+                    ScalarType::U16 => u16::from_le_bytes([value[0], value[1]]) as f64,
+                    ScalarType::I32 => i32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::U32 => u32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::F32 => f32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(value.try_into().unwrap()),
+                })
+            }
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<Vec<Triangle>, String> {
+    // The header is ASCII text ending with an `end_header` line.
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = bytes
+        .windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or("Missing 'end_header'")?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| header_end + i + 1);
+    let header = std::str::from_utf8(&bytes[..header_end]).map_err(|_| "Header is not ASCII")?;
+
+    let mut lines = header.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some("ply") {
+        return Err("Not a PLY file".into());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (i, line) in lines {
+        let error = |message: String| format!("Line {}: {}", i + 1, message);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", other, ..] => {
+                return Err(error(format!("Unsupported format '{}'", other)));
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(format!("Invalid element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_ty, ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("Property outside of an element".into()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    ty: ScalarType::parse(ty).map_err(error)?,
+                    count_ty: Some(ScalarType::parse(count_ty).map_err(error)?),
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("Property outside of an element".into()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    ty: ScalarType::parse(ty).map_err(error)?,
+                    count_ty: None,
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(error(format!("Invalid header line '{}'", line))),
+        }
+    }
+    let format = format.ok_or("Missing 'format'")?;
+
+    let body = &bytes[body_start..];
+    let mut reader = BodyReader {
+        format,
+        tokens: std::str::from_utf8(if format == Format::Ascii { body } else { &[] })
+            .map_err(|_| "Body is not ASCII")?
+            .split_ascii_whitespace(),
+        bytes: body,
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut faces: Vec<Vec<usize>> = Vec::new();
+    for element in &elements {
+        let x = element.property(&["x"]);
+        let y = element.property(&["y"]);
+        let z = element.property(&["z"]);
+        let n = [
+            element.property(&["nx"]),
+            element.property(&["ny"]),
+            element.property(&["nz"]),
+        ];
+        let uv = [
+            element.property(&["u", "s", "texture_u"]),
+            element.property(&["v", "t", "texture_v"]),
+        ];
+        let indices = element
+            .properties
+            .iter()
+            .position(|p| p.name == "vertex_indices" || p.name == "vertex_index");
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            let mut list = Vec::new();
+            for (k, property) in element.properties.iter().enumerate() {
+                match property.count_ty {
+                    Some(count_ty) => {
+                        let count = reader.read(count_ty)? as usize;
+                        // The count comes from the file, so it's checked before allocating.
+                        if count > reader.max_values(property.ty) {
+                            return Err(format!(
+                                "List of {} values is longer than the rest of the file",
+                                count
+                            ));
+                        }
+                        let mut items = Vec::with_capacity(count);
+                        for _ in 0..count {
+                            items.push(reader.read(property.ty)?);
+                        }
+                        if Some(k) == indices {
+                            list = items;
+                        }
+                    }
+                    None => values[k] = reader.read(property.ty)?,
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let (x, y, z) = match (x, y, z) {
+                        (Some(x), Some(y), Some(z)) => (x, y, z),
+                        _ => return Err("Vertices need x, y and z properties".into()),
+                    };
+                    positions.push(vec3a(values[x] as f32, values[y] as f32, values[z] as f32));
+                    if let [Some(nx), Some(ny), Some(nz)] = n {
+                        normals.push(vec3a(
+                            values[nx] as f32,
+                            values[ny] as f32,
+                            values[nz] as f32,
+                        ));
+                    }
+                    if let [Some(u), Some(v)] = uv {
+                        // Texture coordinates start at the bottom of the image.
+                        tex_coords.push(vec2(values[u] as f32, 1.0 - values[v] as f32));
+                    }
+                }
+                "face" => faces.push(list.iter().map(|&i| i as usize).collect()),
+                // Other elements, like edges, are skipped.
+                _ => {}
+            }
+        }
+    }
+
+    let material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::splat(0.8)));
+    let mut triangles = Vec::new();
+    for face in faces {
+        if face.len() < 3 {
+            return Err("Faces need at least 3 vertices".into());
+        }
+        if let Some(&i) = face.iter().find(|&&i| i >= positions.len()) {
+            return Err(format!("Face index {} is out of range", i));
+        }
+
+        for j in 1..face.len() - 1 {
+            let corners = [face[0], face[j], face[j + 1]];
+            let [v0, v1, v2] = corners.map(|i| positions[i]);
+            let mut triangle = if normals.is_empty() {
+                Triangle::new(v0, v1, v2, material.clone())
+            } else {
+                Triangle::with_normals(v0, v1, v2, corners.map(|i| normals[i]), material.clone())
+            };
+            if !tex_coords.is_empty() {
+                triangle.set_tex_coords(corners.map(|i| tex_coords[i]));
+            }
+            triangles.push(triangle);
+        }
+    }
+
+    Ok(triangles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_and_binary_fixtures_match() {
+        let ascii = load(Path::new("assets/fixtures/pyramid_ascii.ply")).unwrap();
+        let binary = load(Path::new("assets/fixtures/pyramid_binary.ply")).unwrap();
+
+        // A square base split into two triangles and four sides.
+        assert_eq!(ascii.len(), 6);
+        assert_eq!(binary.len(), ascii.len());
+        for (a, b) in ascii.iter().zip(&binary) {
+            assert_eq!(a.vertices(), b.vertices());
+            assert_eq!(a.tex_coords(), b.tex_coords());
+        }
+        assert_eq!(
+            ascii[2].vertices(),
+            [
+                vec3a(-1.0, 0.0, -1.0),
+                vec3a(1.0, 0.0, -1.0),
+                vec3a(0.0, 1.0, 0.0)
+            ]
+        );
+        assert_eq!(ascii[0].tex_coords().unwrap()[0], vec2(0.0, 1.0));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(parse(b"ply\nformat binary_big_endian 1.0\nend_header\n").is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n").is_err());
+        let out_of_range = b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+            property float y\nproperty float z\nelement face 1\n\
+            property list uchar int vertex_indices\nend_header\n0 0 0\n3 0 0 1\n";
+        assert!(parse(out_of_range).is_err());
+    }
+
+    #[test]
+    fn rejects_lists_longer_than_file() {
+        let header = b"ply\nformat binary_little_endian 1.0\nelement face 1\n\
+            property list uint int vertex_indices\nend_header\n";
+        let mut oversized = header.to_vec();
+        oversized.extend(u32::MAX.to_le_bytes());
+        oversized.extend([0, 0, 0, 0]);
+        let err = parse(&oversized).err().unwrap();
+        assert!(err.contains("longer than the rest of the file"), "{}", err);
+
+        // Three indices are announced, but only two are left.
+        let mut truncated = header.to_vec();
+        truncated.extend(3u32.to_le_bytes());
+        truncated.extend([0; 8]);
+        assert!(parse(&truncated).is_err());
+
+        let ascii = b"ply\nformat ascii 1.0\nelement face 1\n\
+            property list uint int vertex_indices\nend_header\n4000000000 0 1 2\n";
+        assert!(parse(ascii).is_err());
+    }
+}
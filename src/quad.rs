@@ -0,0 +1,156 @@
+use crate::{
+    aabb::Aabb,
+    hittable::*,
+    light::{AreaLight, SurfacePoint},
+    material::Material,
+    ray::*,
+    Color,
+};
+use glam::*;
+use std::sync::Arc;
+
+/// Parallelogram spanned by two edges leaving a corner, such as a rectangular area light.
+/// The front face is on the side of `edge_u × edge_v`.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    corner: Vec3A,
+    edge_u: Vec3A,
+    edge_v: Vec3A,
+    normal: Vec3A,
+    w: Vec3A, // Unnormalized normal divided by its squared length, for finding edge coordinates.
+    material: Arc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3A, edge_u: Vec3A, edge_v: Vec3A, material: Arc<dyn Material>) -> Self {
+        let n = edge_u.cross(edge_v);
+        Self {
+            corner,
+            edge_u,
+            edge_v,
+            normal: n.normalize(),
+            w: n / n.length_squared(),
+            material,
+        }
+    }
+
+    /// Point at fractions `u` and `v` of the way along the edges.
+    fn point_at(&self, u: f32, v: f32) -> SurfacePoint {
+        let position = self.corner + u * self.edge_u + v * self.edge_v;
+        SurfacePoint::new(
+            position,
+            self.normal,
+            tex_coords(u, v),
+            self.material.as_ref(),
+        )
+    }
+}
+
+/// Texture coordinates at fractions `u` and `v` of the way along the edges. The image is
+/// upright when `edge_u` points right and `edge_v` points up.
+fn tex_coords(u: f32, v: f32) -> Vec2 {
+    vec2(u, 1.0 - v)
+}
+
+impl AreaLight for Quad {
+    fn area(&self) -> f32 {
+        self.edge_u.cross(self.edge_v).length()
+    }
+
+    /// Radiance emitted at the center of the quad.
+    fn emission(&self) -> Color {
+        self.point_at(0.5, 0.5).emission
+    }
+
+    fn sample_point(&self, u: Vec2) -> SurfacePoint {
+        self.point_at(u.x, u.y)
+    }
+}
+
+impl Hittable for Quad {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        let denom = self.normal.dot(r.direction());
+        if denom.abs() < 1e-8 {
+            return false; // Ray is parallel to the quad.
+        }
+        let t = self.normal.dot(self.corner - r.origin()) / denom;
+        // Also rejects degenerate quads, whose distances are NaN.
+        if !(t_min..=t_max).contains(&t) {
+            return false;
+        }
+
+        // Express the hit in coordinates along the edges.
+        let position = r.origin() + t * r.direction();
+        let offset = position - self.corner;
+        let u = self.w.dot(offset.cross(self.edge_v));
+        let v = self.w.dot(self.edge_u.cross(offset));
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return false;
+        }
+
+        rec.hit_distance = t;
+        rec.world_position = position;
+        rec.set_face_normal(r, self.normal);
+        rec.uv = tex_coords(u, v);
+        rec.tangent = self.edge_u;
+        rec.bitangent = -self.edge_v;
+        rec.material = Some(self.material.as_ref());
+        rec.barycentrics = None;
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points([
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ])
+    }
+
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        if self.emission() == Color::ZERO {
+            return Vec::new();
+        }
+        vec![(0, Arc::new(self.clone()))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn hit_inside_edges() {
+        let material = Arc::new(Lambertian::new(Color::ONE));
+        let quad = Quad::new(
+            vec3a(-1.0, 0.0, 0.0),
+            vec3a(2.0, 0.0, 0.0),
+            vec3a(0.0, 1.0, 0.0),
+            material,
+        );
+        let mut rec = HitPayload::new();
+        let r = Ray::new(vec3a(0.5, 0.25, 2.0), vec3a(0.0, 0.0, -1.0));
+        assert!(quad.hit(&r, 0.0, f32::INFINITY, &mut rec));
+        assert_eq!(rec.hit_distance, 2.0);
+        assert!(rec.front_face);
+        assert_eq!(rec.world_normal, vec3a(0.0, 0.0, 1.0));
+        assert!((rec.uv - vec2(0.75, 0.75)).length() < 1e-6);
+
+        // From behind, the normal faces the ray.
+        let r = Ray::new(vec3a(0.5, 0.25, -2.0), vec3a(0.0, 0.0, 1.0));
+        assert!(quad.hit(&r, 0.0, f32::INFINITY, &mut rec));
+        assert!(!rec.front_face);
+        assert_eq!(rec.world_normal, vec3a(0.0, 0.0, -1.0));
+
+        let miss = Ray::new(vec3a(1.5, 0.25, 2.0), vec3a(0.0, 0.0, -1.0));
+        assert!(!quad.hit(&miss, 0.0, f32::INFINITY, &mut rec));
+        assert_eq!(quad.area(), 2.0);
+        assert_eq!(
+            quad.bounding_box(),
+            Aabb::from_points([vec3a(-1.0, 0.0, 0.0), vec3a(1.0, 1.0, 0.0)])
+        );
+    }
+}
@@ -6,6 +6,7 @@ pub struct Ray {
     dir: Vec3A,
 }
 
+#[allow(dead_code)]
 impl Ray {
     /// Creates a new ray with a given origin and direction.
     pub fn new(origin: Vec3A, direction: Vec3A) -> Self {
@@ -1,30 +1,169 @@
 use crate::{
+    aov::{Aov, AovSample},
+    denoise::{Denoiser, Features},
+    display::DisplayTransform,
+    filter::Filter,
+    hdr_image::HdrImage,
     hittable::{HitPayload, Hittable},
     hittable_list::HittableList,
+    light::{power_heuristic, LightList},
+    material::Material,
     util, Camera, Color, Ray,
 };
-use glam::{vec3a, Vec3, Vec3A};
+use glam::{vec2, vec3a, Vec2, Vec3A};
 use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
-use std::{f32::consts::PI, time::Instant};
+use std::{fmt, str::FromStr, time::Instant};
 
 pub struct Renderer {
     image_width: usize,
     image_height: usize,
 
     image_data: Vec<u8>,
+    // Filter weighted sums of the samples around every pixel, and the sums of their weights.
     accumulation_data: Vec<Vec3A>,
+    weight_data: Vec<f32>,
+    // Sums of the AOV samples of every pixel. Empty unless AOVs are enabled.
+    aov_data: Vec<AovSample>,
     frame_index: u64,
+
+    settings: RenderSettings,
+    display_transform: DisplayTransform,
+    denoiser: Option<Denoiser>,
+}
+
+/// A sample of the image, at a position in pixels from the bottom left corner.
+#[derive(Debug, Clone, Copy, Default)]
+struct FilmSample {
+    position: Vec2,
+    color: Color,
+}
+
+/// Settings which control how the renderer integrates each pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    /// Maximum length of our light paths.
+    pub max_bounces: u32,
+    /// Number of bounces before paths may be terminated by Russian roulette.
+    /// Setting this to `max_bounces` disables Russian roulette.
+    pub min_bounces: u32,
+    /// Number of light paths traced per pixel every frame.
+    pub samples_per_pixel: u32,
+    /// Sample emitters and the environment directly at every bounce and combine with BSDF
+    /// sampling using MIS.
+    pub next_event_estimation: bool,
+    /// What is written to the image.
+    pub render_mode: RenderMode,
+    /// Value at the hot end of the heat map used by the depth, BVH, triangle test and time
+    /// modes.
+    pub heat_map_max: f32,
+    /// Reconstruction filter, which weights the jittered samples around each pixel.
+    pub filter: Filter,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            max_bounces: 16,
+            min_bounces: 3,
+            samples_per_pixel: 1,
+            next_event_estimation: true,
+            render_mode: RenderMode::PathTraced,
+            heat_map_max: 64.0,
+            filter: Filter::Box,
+        }
+    }
+}
+
+/// What the renderer writes to the image. Apart from the path traced image and the time per
+/// pixel, the modes visualize the closest hit of each camera ray. Rays which miss are black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    PathTraced,
+    /// Normals used for shading, e.g. interpolated or normal mapped, mapped to [0, 1].
+    ShadingNormals,
+    /// Normals of the actual geometry, mapped to [0, 1].
+    GeometricNormals,
+    /// Distance to the camera, as a heat map.
+    Depth,
+    Albedo,
+    /// A random color for each object in the scene.
+    ObjectIndex,
+    /// Barycentric coordinates of triangles in the red, green and blue channels.
+    Barycentrics,
+    /// Number of BVH nodes visited by the camera ray, as a heat map.
+    BvhNodeVisits,
+    /// Number of ray-triangle intersection tests of the camera ray, as a heat map.
+    TriangleTests,
+    /// Microseconds spent path tracing each pixel, as a heat map.
+    TimePerPixel,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 10] = [
+        RenderMode::PathTraced,
+        RenderMode::ShadingNormals,
+        RenderMode::GeometricNormals,
+        RenderMode::Depth,
+        RenderMode::Albedo,
+        RenderMode::ObjectIndex,
+        RenderMode::Barycentrics,
+        RenderMode::BvhNodeVisits,
+        RenderMode::TriangleTests,
+        RenderMode::TimePerPixel,
+    ];
+
+    /// Name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderMode::PathTraced => "path-traced",
+            RenderMode::ShadingNormals => "shading-normals",
+            RenderMode::GeometricNormals => "geometric-normals",
+            RenderMode::Depth => "depth",
+            RenderMode::Albedo => "albedo",
+            RenderMode::ObjectIndex => "object-index",
+            RenderMode::Barycentrics => "barycentrics",
+            RenderMode::BvhNodeVisits => "bvh-heatmap",
+            RenderMode::TriangleTests => "triangle-tests",
+            RenderMode::TimePerPixel => "time",
+        }
+    }
+
+    /// Whether the mode is shown as a heat map scaled by `RenderSettings::heat_map_max`.
+    pub fn is_heat_map(self) -> bool {
+        matches!(
+            self,
+            RenderMode::Depth
+                | RenderMode::BvhNodeVisits
+                | RenderMode::TriangleTests
+                | RenderMode::TimePerPixel
+        )
+    }
 }
 
-enum RenderMode {
-    Default,
-    Normals,
-    // TimePerPixel,
+impl fmt::Display for RenderMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
-// Maximum length of our light paths.
-const NUM_BOUNCES: u32 = 16;
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|m| m.name()).collect();
+                format!(
+                    "Unknown render mode '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
 
 impl Renderer {
     /// Create a new renderer.
@@ -33,9 +172,9 @@ impl Renderer {
             .map(|i| {
                 // Set every 4th value to 255, all else 0.
                 if i % 4 == 3 {
-                    return 255;
+                    255
                 } else {
-                    return 0;
+                    0
                 }
             })
             .collect();
@@ -48,23 +187,165 @@ impl Renderer {
         Self {
             image_data,
             accumulation_data,
+            weight_data: vec![0.0; image_width * image_height],
+            aov_data: Vec::new(),
             image_width,
             image_height,
             frame_index: 1,
+            settings: RenderSettings::default(),
+            display_transform: DisplayTransform::default(),
+            denoiser: None,
         }
     }
 
+    pub fn get_settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    /// Change the render settings. Since previously accumulated samples were
+    /// integrated with the old settings, the accumulation data is reset.
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        self.settings = settings;
+        self.reset_accumulation_data();
+    }
+
+    pub fn get_display_transform(&self) -> &DisplayTransform {
+        &self.display_transform
+    }
+
+    /// Change how radiance is displayed. The accumulated samples are kept, and the final
+    /// image is updated right away.
+    pub fn set_display_transform(&mut self, display_transform: DisplayTransform) {
+        self.display_transform = display_transform;
+        self.update_final_image();
+    }
+
+    pub fn get_denoiser(&self) -> Option<&Denoiser> {
+        self.denoiser.as_ref()
+    }
+
+    /// Denoise the final image, or stop doing so. The denoiser is guided by the AOVs, so
+    /// they are enabled if they weren't already, which resets the accumulation data.
+    pub fn set_denoiser(&mut self, denoiser: Option<Denoiser>) {
+        self.denoiser = denoiser;
+        if self.denoiser.is_some() && !self.aovs_enabled() {
+            self.set_aovs_enabled(true);
+        }
+        self.update_final_image();
+    }
+
+    /// Encode the average of the accumulated samples into the final image.
+    fn update_final_image(&mut self) {
+        let colors = self.denoised().unwrap_or_else(|| self.average());
+        let transform = &self.display_transform;
+        self.image_data
+            .par_chunks_mut(4)
+            .zip(colors.par_iter())
+            .for_each(|(pixel, color)| {
+                pixel[..3].copy_from_slice(&transform.apply(*color));
+            });
+    }
+
+    /// Average of the accumulated samples with the denoiser applied, in the renderer's row
+    /// order. `None` if there is no denoiser, or nothing is path traced.
+    fn denoised(&self) -> Option<Vec<Color>> {
+        let denoiser = self.denoiser.as_ref()?;
+        if self.settings.render_mode != RenderMode::PathTraced || !self.aovs_enabled() {
+            return None;
+        }
+
+        let aovs = self.aov_average();
+        let albedo: Vec<Color> = aovs.iter().map(|aov| aov.albedo).collect();
+        let normal: Vec<Vec3A> = aovs.iter().map(|aov| aov.normal).collect();
+        let depth: Vec<f32> = aovs.iter().map(|aov| aov.mean_depth()).collect();
+        let features = Features {
+            albedo: &albedo,
+            normal: &normal,
+            depth: &depth,
+        };
+        Some(denoiser.denoise(self.image_width, &self.average(), &features))
+    }
+
+    /// Average of the accumulated samples, in the renderer's row order.
+    fn average(&self) -> Vec<Color> {
+        self.accumulation_data
+            .iter()
+            .zip(&self.weight_data)
+            .map(|(acc, weight)| weighted_average(*acc, *weight))
+            .collect()
+    }
+
+    /// Average of the accumulated AOVs, weighted like the colors.
+    fn aov_average(&self) -> Vec<AovSample> {
+        self.aov_data
+            .iter()
+            .zip(&self.weight_data)
+            .map(|(sum, weight)| sum.average(*weight))
+            .collect()
+    }
+
     /// Get reference to final image buffer.
     pub fn get_final_image(&self) -> &Vec<u8> {
         &self.image_data
     }
 
+    /// Average of the samples accumulated so far, as linear radiance. Unlike the final image,
+    /// values aren't clamped or quantized.
+    pub fn get_linear_image(&self) -> HdrImage {
+        let average = self.average();
+        self.to_image(&average, |color| *color)
+    }
+
+    /// Whether AOVs are accumulated alongside the final image.
+    pub fn aovs_enabled(&self) -> bool {
+        !self.aov_data.is_empty()
+    }
+
+    /// Start or stop accumulating AOVs, which resets the accumulation data.
+    pub fn set_aovs_enabled(&mut self, enabled: bool) {
+        self.aov_data = if enabled {
+            vec![AovSample::default(); self.image_width * self.image_height]
+        } else {
+            Vec::new()
+        };
+        self.reset_accumulation_data();
+    }
+
+    /// Denoised average of the samples accumulated so far, or `None` if there is no
+    /// denoiser or the render mode isn't path traced.
+    pub fn get_denoised_image(&self) -> Option<HdrImage> {
+        let denoised = self.denoised()?;
+        Some(self.to_image(&denoised, |color| *color))
+    }
+
+    /// Average of an AOV over the samples accumulated so far, or `None` if AOVs aren't
+    /// enabled. AOVs are only recorded in the path traced render modes.
+    pub fn get_aov_image(&self, aov: Aov) -> Option<HdrImage> {
+        if !self.aovs_enabled() {
+            return None;
+        }
+        Some(self.to_image(&self.aov_average(), |average| average.get(aov)))
+    }
+
+    /// Convert per-pixel data to an image whose first row is the top.
+    fn to_image<T>(&self, data: &[T], color: impl Fn(&T) -> Color) -> HdrImage {
+        // The renderer's first row is the bottom of the image.
+        let pixels = data
+            .chunks(self.image_width)
+            .rev()
+            .flat_map(|row| row.iter().map(&color))
+            .collect();
+        HdrImage::new(self.image_width, self.image_height, pixels)
+    }
+
     pub fn reset_accumulation_data(&mut self) {
         // Reset the frame index.
         self.frame_index = 1;
 
         // Reset acc data.
         self.accumulation_data.fill(Vec3A::ZERO);
+        self.weight_data.fill(0.0);
+        self.aov_data.fill(AovSample::default());
     }
 
     pub fn get_frame_index(&self) -> u64 {
@@ -77,23 +358,36 @@ impl Renderer {
         // Take the ownership of the image and accumulation data.
         let mut image_data = std::mem::take(&mut self.image_data);
         let mut accumulation_data = std::mem::take(&mut self.accumulation_data);
+        let mut weight_data = std::mem::take(&mut self.weight_data);
+        let mut aov_data = std::mem::take(&mut self.aov_data);
 
         // Generate seeds for each thread.
         let seeds = (0..self.image_width * self.image_height)
             .map(|_| master_rng.gen())
             .collect::<Vec<u64>>();
 
-        // Split each pixel into a task.
-        image_data
-            .chunks_mut(4)
-            .zip(accumulation_data.iter_mut())
-            .zip(0..self.image_width * self.image_height)
-            .map(|((pixel, acc), i)| (i, pixel, acc))
+        // Split each pixel into a task, which traces the pixel's samples.
+        let num_samples = self.settings.samples_per_pixel.max(1) as usize;
+        let mut samples = vec![FilmSample::default(); seeds.len() * num_samples];
+        // Samples have no AOVs when AOVs are disabled.
+        let mut aov_samples = if aov_data.is_empty() {
+            Vec::new()
+        } else {
+            vec![AovSample::default(); samples.len()]
+        };
+        let aovs = aov_samples
+            .chunks_mut(num_samples)
+            .map(Some)
+            .chain(std::iter::repeat_with(|| None));
+        samples
+            .chunks_mut(num_samples)
             .zip(seeds)
-            .map(|((i, pixel, acc), seed)| (i, pixel, acc, seed))
-            .collect::<Vec<(usize, &mut [u8], &mut Vec3A, u64)>>()
+            .zip(aovs)
+            .enumerate()
+            .map(|(i, ((samples, seed), aovs))| (i, samples, seed, aovs))
+            .collect::<Vec<(usize, &mut [FilmSample], u64, Option<&mut [AovSample]>)>>()
             .into_par_iter()
-            .for_each(|(i, pixel, acc_data, seed)| {
+            .for_each(|(i, samples, seed, aovs)| {
                 // Get x and y position into final image.
                 let y = i / self.image_width;
                 let x = i % self.image_width;
@@ -101,18 +395,27 @@ impl Renderer {
                 // Create RNG
                 let mut rng = rand_xoshiro::Xoroshiro128PlusPlus::seed_from_u64(seed);
 
-                // Shoot ray and accumulate color data.
-                let col = self.per_pixel(&scene, cam, &mut rng, x, y);
-                *acc_data += col;
+                self.per_pixel(scene, cam, &mut rng, x, y, aovs, samples);
+            });
+
+        // Splat every sample into the pixels within the filter's radius. Each pixel gathers
+        // the samples around it, so that the pixels can be accumulated in parallel.
+        image_data
+            .par_chunks_mut(4)
+            .zip(accumulation_data.par_iter_mut())
+            .zip(weight_data.par_iter_mut())
+            .enumerate()
+            .for_each(|(i, ((pixel, acc_data), weight))| {
+                self.gather(&samples, i, |k, w| {
+                    *acc_data += w * samples[k].color;
+                    *weight += w;
+                });
 
                 // Average the accumulated data.
-                let accumulated_color = *acc_data / self.frame_index as f32;
+                let accumulated_color = weighted_average(*acc_data, *weight);
 
-                // Clamp color values to prevent under/over-flow.
-                accumulated_color.clamp(Vec3A::ZERO, Vec3A::ONE);
-                let r = (accumulated_color.x * 255.0) as u8;
-                let g = (accumulated_color.y * 255.0) as u8;
-                let b = (accumulated_color.z * 255.0) as u8;
+                // Expose, tone map and encode the color for display.
+                let [r, g, b] = self.display_transform.apply(accumulated_color);
 
                 // Write color to pixel
                 pixel[0] = r;
@@ -121,37 +424,61 @@ impl Renderer {
                 pixel[3] = 255;
             });
 
-        // // Split image data into mutable chunks.
-        // let image_bands: Vec<(usize, &mut [u8])> = image_data
-        //     .chunks_mut(self.image_width * 4)
-        //     .enumerate()
-        //     .collect();
-
-        // // Render each chunk of image data in parallel.
-        // image_bands.into_par_iter().for_each(|(y, image_data)| {
-        //     for x in 0..self.image_width {
-        //         let col = self.per_pixel(&scene, cam, x, y);
-        //         let r = (col.x * 255.0) as u8;
-        //         let g = (col.y * 255.0) as u8;
-        //         let b = (col.z * 255.0) as u8;
-
-        //         // Index into slice
-        //         let i = x * 4;
-        //         image_data[i] = r;
-        //         image_data[i + 1] = g;
-        //         image_data[i + 2] = b;
-        //     }
-        // });
+        // AOVs are filtered like the colors, so that they line up with the image, and share
+        // their weights.
+        aov_data.par_iter_mut().enumerate().for_each(|(i, sum)| {
+            self.gather(&samples, i, |k, w| sum.accumulate(&aov_samples[k], w));
+            // Object ids can't be filtered. Each pixel keeps the id of its sample nearest to
+            // its center over every frame, which is that of the object covering the center,
+            // rather than a mix of the objects the pixel straddles.
+            let center = vec2(
+                (i % self.image_width) as f32 + 0.5,
+                (i / self.image_width) as f32 + 0.5,
+            );
+            for k in i * num_samples..(i + 1) * num_samples {
+                let distance = (samples[k].position - center).length_squared();
+                sum.keep_nearest_object(&aov_samples[k], distance);
+            }
+        });
 
         // Give ownership back to self.
         self.image_data = image_data;
         self.accumulation_data = accumulation_data;
+        self.weight_data = weight_data;
+        self.aov_data = aov_data;
 
         // Increase frame index
         self.frame_index += 1;
+
+        // The denoiser filters the whole image, so it runs once every pixel is done.
+        if self.denoiser.is_some() {
+            self.update_final_image();
+        }
     }
 
-    /// RayGen shader
+    /// Call `add` with the index and filter weight of every sample within the filter's
+    /// radius of pixel `i`. Each pixel has the same number of consecutive `samples`.
+    fn gather(&self, samples: &[FilmSample], i: usize, mut add: impl FnMut(usize, f32)) {
+        let filter = self.settings.filter;
+        let num_samples = samples.len() / (self.image_width * self.image_height);
+        // Samples lie inside their pixel, so the filter reaches this many pixels further.
+        let reach = (filter.radius() - 0.5).ceil().max(0.0) as usize;
+        let y = i / self.image_width;
+        let x = i % self.image_width;
+        let center = vec2(x as f32 + 0.5, y as f32 + 0.5);
+        for qy in y.saturating_sub(reach)..(y + reach + 1).min(self.image_height) {
+            for qx in x.saturating_sub(reach)..(x + reach + 1).min(self.image_width) {
+                let q = (qx + qy * self.image_width) * num_samples;
+                for (k, sample) in (q..).zip(&samples[q..q + num_samples]) {
+                    add(k, filter.evaluate(sample.position - center));
+                }
+            }
+        }
+    }
+
+    /// RayGen shader. Traces one path through a random point of the pixel for each of
+    /// `samples`, and records the AOVs of each path in `aovs` if they're enabled.
+    #[allow(clippy::too_many_arguments)]
     fn per_pixel(
         &self,
         scene: &HittableList,
@@ -159,99 +486,340 @@ impl Renderer {
         rng: &mut impl Rng,
         x: usize,
         y: usize,
-    ) -> Color {
+        mut aovs: Option<&mut [AovSample]>,
+        samples: &mut [FilmSample],
+    ) {
         let t = Instant::now();
 
-        // Initialize the view ray.
-        let mut view_ray = Ray::new(Vec3A::ZERO, Vec3A::ZERO);
-        view_ray.set_origin(*cam.get_position());
-        view_ray.set_direction(cam.get_ray_directions()[(x + y * self.image_width)]);
-
-        self.ray_color(&view_ray, 0, scene, rng)
-
-        // // Begin integrating the light path.
-        // let num_bounces = 1; // Just do direct lighting for now.
-        // for i in 0..num_bounces + 1 {
-        //     let hit_payload = self.trace_ray(scene, &view_ray);
-        //     if hit_payload.hit_distance < 0.0 {
-        //         // If ray hits nothing, return background color.
-        //         let unit_dir = view_ray.direction().normalize();
-        //         let t = 0.5 * (unit_dir.y + 1.0);
-
-        //         // Returns a color lerped between white and blu-ish
-        //         color += (1.0 - t) * Color::ONE + t * Color::new(0.5, 0.7, 1.0) * 0.5;
-        //     }
-        // }
-
-        // TODO: Don't hardcode this.
-        // let render_mode = RenderMode::Normals;
-        // match render_mode {
-        //     RenderMode::Normals => {
-        //         // Map normals to a color
-        //         return (hit_payload.world_normal + 1.0) * 0.5;
-        //     }
-        //     RenderMode::Default => {
-        //         // TODO: Implement
-        //         return Vec3A::ZERO;
-        //     }
-        // }
-    }
-
-    /// Given a
-    /// TODO: Could v_inv just be a ray value?
+        let mode = self.settings.render_mode;
+        let debug = mode != RenderMode::PathTraced && mode != RenderMode::TimePerPixel;
+        for (k, sample) in samples.iter_mut().enumerate() {
+            // Each sample passes through a different point of the pixel and of the lens.
+            sample.position = vec2(x as f32 + rng.gen::<f32>(), y as f32 + rng.gen::<f32>());
+            let view_ray = cam.get_ray(sample.position, rng);
+            sample.color = if debug {
+                self.debug_color(&view_ray, scene)
+            } else {
+                let aov = aovs.as_mut().map(|aovs| &mut aovs[k]);
+                self.ray_color(&view_ray, scene, rng, aov)
+            };
+        }
+        if mode == RenderMode::TimePerPixel {
+            let micros = t.elapsed().as_secs_f32() * 1e6;
+            let heat = heat_map(micros / self.settings.heat_map_max);
+            samples.iter_mut().for_each(|sample| sample.color = heat);
+        }
+    }
+
+    /// Visualize the closest hit of a camera ray according to the render mode.
+    fn debug_color(&self, view_ray: &Ray, scene: &HittableList) -> Color {
+        let mut hit = HitPayload::new();
+        let found = scene.hit(view_ray, 0.0, f32::INFINITY, &mut hit);
+        let heat = |value: f32| heat_map(value / self.settings.heat_map_max);
+        match self.settings.render_mode {
+            // Traversal work is shown for rays which miss too.
+            RenderMode::BvhNodeVisits => return heat(hit.stats.bvh_node_visits as f32),
+            RenderMode::TriangleTests => return heat(hit.stats.triangle_tests as f32),
+            _ => {}
+        }
+        let material = match hit.material {
+            Some(material) if found => material,
+            _ => return Color::ZERO,
+        };
+
+        match self.settings.render_mode {
+            RenderMode::ShadingNormals => {
+                let shading_normal = material.shading_normal(&hit);
+                hit.set_shading_normal(view_ray, shading_normal);
+                hit.shading_normal * 0.5 + 0.5
+            }
+            RenderMode::GeometricNormals => hit.world_normal * 0.5 + 0.5,
+            RenderMode::Depth => heat(hit.hit_distance * view_ray.direction().length()),
+            RenderMode::Albedo => material.albedo(&hit),
+            RenderMode::ObjectIndex => index_color(hit.object_index),
+            RenderMode::Barycentrics => match hit.barycentrics {
+                Some(b) => vec3a(1.0 - b.x - b.y, b.x, b.y),
+                None => Color::ZERO,
+            },
+            _ => Color::ZERO,
+        }
+    }
+
+    /// Estimate the radiance arriving along a view ray by tracing a light path through the scene.
+    /// Paths are extended until they escape, get absorbed, reach `max_bounces`, or are
+    /// terminated by Russian roulette. The ray leaving the last bounce is still traced, but
+    /// only to find the emitter it hits, which completes the MIS estimate of the light
+    /// sampled there.
+    ///
+    /// If `aov` is given, the first hit is recorded in it, and the radiance is split up by
+    /// the number of bounces.
     fn ray_color(
         &self,
-        v_inv: &Ray,
-        depth: u32,
+        view_ray: &Ray,
+        scene: &HittableList,
+        rng: &mut impl Rng,
+        mut aov: Option<&mut AovSample>,
+    ) -> Color {
+        let use_nee = self.settings.next_event_estimation && !scene.lights().is_empty();
+        let environment = scene.environment();
+        let sample_environment = self.settings.next_event_estimation && !environment.is_black();
+
+        let mut ray = *view_ray;
+        let mut color = Color::ZERO;
+        // Product of the sample weights along the path so far.
+        let mut throughput = Color::ONE;
+        // Solid angle density with which the previous bounce sampled `ray`. It is `None` for
+        // camera rays and specular bounces, whose emission can't be found through light
+        // sampling and is therefore counted in full.
+        let mut bsdf_pdf = None;
+
+        for depth in 0..=self.settings.max_bounces.saturating_add(1) {
+            let mut hit_payload = self.trace_ray(scene, &ray);
+            if hit_payload.hit_distance < 0.0 {
+                // Ray missed everything in our scene, so it sees the environment. Like
+                // emitters, it could also have been sampled at the previous bounce.
+                let emitted = if depth == 0 && !environment.visible {
+                    Color::ZERO
+                } else {
+                    environment.radiance(ray.direction())
+                };
+                let radiance = throughput
+                    * match bsdf_pdf {
+                        Some(bsdf_pdf) if sample_environment => {
+                            let light_pdf = environment.pdf(ray.direction());
+                            emitted * power_heuristic(bsdf_pdf, light_pdf)
+                        }
+                        _ => emitted,
+                    };
+                color += radiance;
+                if let Some(aov) = &mut aov {
+                    aov.add_light(depth, radiance);
+                }
+                break;
+            }
+
+            // Ray hit an object in the scene.
+            let material = match hit_payload.material {
+                Some(material) => material,
+                None => break,
+            };
+            let shading_normal = material.shading_normal(&hit_payload);
+            hit_payload.set_shading_normal(&ray, shading_normal);
+            // Direction towards the viewer.
+            let wo = -ray.direction().normalize();
+            if let Some(aov) = &mut aov {
+                if depth == 0 {
+                    aov.albedo = material.albedo(&hit_payload);
+                    aov.normal = hit_payload.shading_normal;
+                    aov.depth = hit_payload.hit_distance * ray.direction().length();
+                    aov.coverage = 1.0;
+                    aov.position = hit_payload.world_position;
+                    aov.object_id = hit_payload.object_index as f32;
+                }
+            }
+
+            // Emission found by BSDF sampling. With NEE the same light could also have been
+            // reached by light sampling at the previous bounce, so weight it with MIS.
+            // Emitters which aren't in the light list, like planes, have a light pdf of zero
+            // and keep all of their emission.
+            let emitted = material.emitted(&hit_payload);
+            let mut radiance = throughput
+                * match bsdf_pdf {
+                    Some(bsdf_pdf) if use_nee && emitted != Color::ZERO => {
+                        let light_pdf = LightList::to_solid_angle(
+                            scene
+                                .lights()
+                                .pdf_area(hit_payload.object_index, hit_payload.primitive_index),
+                            ray.origin(),
+                            hit_payload.world_position,
+                            hit_payload.world_normal,
+                        );
+                        emitted * power_heuristic(bsdf_pdf, light_pdf)
+                    }
+                    _ => emitted,
+                };
+            color += radiance;
+            if let Some(aov) = &mut aov {
+                aov.add_light(depth, radiance);
+            }
+            if depth > self.settings.max_bounces {
+                break;
+            }
+
+            // Add direct lighting from a point sampled on the emitters.
+            radiance = Color::ZERO;
+            if use_nee {
+                radiance += throughput * self.sample_direct(scene, &hit_payload, material, wo, rng);
+            }
+            if sample_environment {
+                radiance +=
+                    throughput * self.sample_environment(scene, &hit_payload, material, wo, rng);
+            }
+            // Punctual lights can't be hit by rays, so they're always sampled.
+            radiance += throughput * self.sample_punctual(scene, &hit_payload, material, wo);
+            color += radiance;
+            if let Some(aov) = &mut aov {
+                aov.add_light(depth + 1, radiance);
+            }
+
+            // Generate new sample from the surface's BSDF.
+            let sample = match material.sample(&hit_payload, wo, rng) {
+                Some(sample) => sample,
+                None => break, // Path was absorbed.
+            };
+            // Directions below the geometric surface but above the shading surface, or the
+            // other way around, would let light leak through the surface.
+            if !hit_payload.is_consistent(sample.direction) {
+                break;
+            }
+            // The sample weight contains the BSDF, the cosine term, and the Monte-Carlo compensation.
+            throughput *= sample.weight;
+
+            // Randomly terminate paths carrying little energy. Surviving paths are weighted
+            // up by the inverse survival probability so the estimate stays unbiased.
+            if depth >= self.settings.min_bounces {
+                let survival_probability = throughput.max_element().min(0.95);
+                if rng.gen::<f32>() >= survival_probability {
+                    break;
+                }
+                throughput /= survival_probability;
+            }
+
+            // Continue the path in the sampled direction.
+            ray = hit_payload.spawn_ray(sample.direction);
+            bsdf_pdf = if sample.is_specular {
+                None
+            } else {
+                Some(sample.pdf)
+            };
+        }
+
+        color
+    }
+
+    /// Estimate the light arriving directly from the emitters at a surface point by
+    /// sampling a point on the lights and casting a shadow ray towards it.
+    fn sample_direct(
+        &self,
         scene: &HittableList,
+        hit: &HitPayload,
+        material: &dyn Material,
+        wo: Vec3A,
         rng: &mut impl Rng,
     ) -> Color {
-        if depth > NUM_BOUNCES {
-            // We've reached the maximum light path length.
+        let light_sample = match scene.lights().sample(rng) {
+            Some(light_sample) => light_sample,
+            None => return Color::ZERO,
+        };
+
+        let to_light = light_sample.position - hit.world_position;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        let light_pdf = LightList::to_solid_angle(
+            light_sample.pdf_area,
+            hit.world_position,
+            light_sample.position,
+            light_sample.normal,
+        );
+        if light_pdf <= 0.0 {
             return Color::ZERO;
         }
 
-        let hit_payload = self.trace_ray(scene, v_inv);
-        if hit_payload.hit_distance < 0.0 {
-            // // Ray missed everything in our scene. Return the background color
-            // let unit_dir = v_inv.direction().normalize();
-            // let t = 0.5 * (unit_dir.y + 1.0);
+        // Skip the shadow ray if the surface doesn't scatter light in this direction.
+        // This is always the case for specular materials.
+        if !hit.is_consistent(wi) {
+            return Color::ZERO;
+        }
+        let f = material.eval(hit, wo, wi);
+        let cos_theta = hit.shading_normal.dot(wi).abs();
+        if f == Color::ZERO || cos_theta <= 0.0 {
+            return Color::ZERO;
+        }
 
-            // // Returns a color lerped between white and blu-ish
-            // return (1.0 - t) * Color::ONE + t * Color::new(0.5, 0.7, 1.0) * 0.5;
+        if is_occluded(scene, hit, wi, distance) {
             return Color::ZERO;
         }
 
-        // Ray hit an object in the scene.
-        let mut color = Color::ZERO;
+        let weight = power_heuristic(light_pdf, material.pdf(hit, wo, wi));
+        f * light_sample.emission * cos_theta * weight / light_pdf
+    }
 
-        color += hit_payload.emissive;
+    /// Estimate the light arriving directly from the environment at a surface point by
+    /// sampling a direction towards it and casting a shadow ray.
+    fn sample_environment(
+        &self,
+        scene: &HittableList,
+        hit: &HitPayload,
+        material: &dyn Material,
+        wo: Vec3A,
+        rng: &mut impl Rng,
+    ) -> Color {
+        let sample = match scene.environment().sample(rng) {
+            Some(sample) => sample,
+            None => return Color::ZERO,
+        };
+        let wi = sample.direction;
+        if !hit.is_consistent(wi) {
+            return Color::ZERO;
+        }
+        let f = material.eval(hit, wo, wi);
+        let cos_theta = hit.shading_normal.dot(wi).abs();
+        if f == Color::ZERO || cos_theta <= 0.0 || sample.radiance == Color::ZERO {
+            return Color::ZERO;
+        }
 
-        // Generate new sample.
-        let omega = util::uniform_hemisphere_sample_world(rng, hit_payload.world_normal);
-        let pdf = 1.0 / (2.0 * PI);
+        // The environment is only visible if nothing in the scene is in the way.
+        let shadow_ray = hit.spawn_ray(wi);
+        let mut shadow_payload = HitPayload::new();
+        if scene.hit(&shadow_ray, 0.0, f32::INFINITY, &mut shadow_payload) {
+            return Color::ZERO;
+        }
 
-        // Create new outgoing ray.
-        let outgoing_ray = Ray::new(hit_payload.world_position, omega);
+        let weight = power_heuristic(sample.pdf, material.pdf(hit, wo, wi));
+        f * sample.radiance * cos_theta * weight / sample.pdf
+    }
 
-        // Add contribution of new sample.
-        let brdf = hit_payload.albedo / PI;
-        color += brdf
-            * hit_payload.world_normal.dot(omega) // Cosine term
-            * (1.0 / pdf) // Monte-carlo compensation.
-            * self.ray_color(&outgoing_ray, depth + 1, scene, rng); // Recursive call to new sample.
+    /// Light arriving directly from every punctual light in the scene.
+    fn sample_punctual(
+        &self,
+        scene: &HittableList,
+        hit: &HitPayload,
+        material: &dyn Material,
+        wo: Vec3A,
+    ) -> Color {
+        let mut color = Color::ZERO;
+        for light in scene.lights().punctual() {
+            let sample = match light.illuminate(hit.world_position) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let wi = sample.direction;
+            if !hit.is_consistent(wi) {
+                continue;
+            }
+            let f = material.eval(hit, wo, wi);
+            let cos_theta = hit.shading_normal.dot(wi).abs();
+            if f == Color::ZERO || cos_theta <= 0.0 {
+                continue;
+            }
+
+            if is_occluded(scene, hit, wi, sample.distance) {
+                continue;
+            }
 
+            color += f * sample.irradiance * cos_theta;
+        }
         color
     }
 
-    fn trace_ray(&self, scene: &HittableList, ray: &Ray) -> HitPayload {
+    fn trace_ray<'a>(&self, scene: &'a HittableList, ray: &Ray) -> HitPayload<'a> {
         // Check if ray intersects world.
         let mut hit_payload = HitPayload::new();
         hit_payload.hit_distance = f32::INFINITY;
 
         if !scene.hit(ray, 0.0, f32::INFINITY, &mut hit_payload) {
             // Invoke the miss function.
-            return self.miss(ray);
+            return self.miss();
         }
 
         // Will contain hit information about the closest intersection.
@@ -259,9 +827,813 @@ impl Renderer {
     }
 
     /// Invoked every time a ray misses every object in the scene.
-    fn miss(&self, ray: &Ray) -> HitPayload {
+    fn miss(&self) -> HitPayload<'static> {
         let mut hit_payload = HitPayload::new();
         hit_payload.hit_distance = -1.0;
         hit_payload
     }
 }
+
+/// Whether anything blocks the light arriving at a hit from `distance` away along
+/// `direction`. The distance is infinite for lights at infinity. The shadow ray stops just
+/// short of the light, so that the light itself doesn't count as an occluder.
+fn is_occluded(scene: &HittableList, hit: &HitPayload, direction: Vec3A, distance: f32) -> bool {
+    let shadow_ray = hit.spawn_ray(direction);
+    let max_distance = if distance.is_finite() {
+        let light_position = hit.world_position + distance * direction;
+        (light_position - shadow_ray.origin()).length() * (1.0 - 1e-3)
+    } else {
+        f32::INFINITY
+    };
+    let mut shadow_payload = HitPayload::new();
+    scene.hit(&shadow_ray, 0.0, max_distance, &mut shadow_payload)
+}
+
+/// Divide a filter weighted sum of samples by the sum of the weights. Filters with negative
+/// lobes can leave pixels without any positive weight, which are black.
+fn weighted_average(sum: Color, weight: f32) -> Color {
+    if weight > 0.0 {
+        sum / weight
+    } else {
+        Color::ZERO
+    }
+}
+
+/// Map a value in [0, 1] to a color going from blue over green to red. Values outside of
+/// the range are clamped.
+fn heat_map(t: f32) -> Color {
+    // Evenly spaced colors, given in sRGB.
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.5],
+        [0.0, 0.5, 1.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+    ];
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let (a, b) = (Vec3A::from(STOPS[i]), Vec3A::from(STOPS[i + 1]));
+    let c = a.lerp(b, x - i as f32);
+    Vec3A::from(c.to_array().map(util::srgb_to_linear))
+}
+
+/// A bright, randomly chosen color which is the same for every pixel of an object.
+fn index_color(index: usize) -> Color {
+    // Scramble the bits of the index so that neighbouring objects get unrelated colors.
+    let mut h = index as u64 ^ 0x9e37_79b9_7f4a_7c15;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f32 / 255.0;
+    vec3a(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::{Background, Environment, EnvironmentMap},
+        light::{Light, LightShape},
+        material::{GltfMaterial, GltfTextures, Lambertian},
+        plane::Plane,
+        quad::Quad,
+        sky::{Sky, SkyParams},
+        texture::Texture,
+        triangle::Triangle,
+    };
+    use glam::Vec4;
+    use std::{f32::consts::PI, sync::Arc};
+
+    /// A large floor lit by a small triangle light, which is partially blocked by an occluder.
+    fn small_light_scene() -> HittableList {
+        let floor: Arc<dyn Material> = Arc::new(Lambertian::new(Color::splat(0.8)));
+        let light: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::ZERO,
+            emission: Color::splat(50.0),
+        });
+
+        let mut scene = HittableList::new();
+        scene.add(Triangle::new(
+            vec3a(-10.0, 0.0, -10.0),
+            vec3a(-10.0, 0.0, 10.0),
+            vec3a(10.0, 0.0, 10.0),
+            floor.clone(),
+        ));
+        scene.add(Triangle::new(
+            vec3a(-10.0, 0.0, -10.0),
+            vec3a(10.0, 0.0, 10.0),
+            vec3a(10.0, 0.0, -10.0),
+            floor.clone(),
+        ));
+        scene.add(Triangle::new(
+            vec3a(-0.25, 1.0, -0.25),
+            vec3a(0.25, 1.0, -0.25),
+            vec3a(0.0, 1.0, 0.25),
+            light,
+        ));
+        scene.add(Triangle::new(
+            vec3a(0.0, 0.5, -0.2),
+            vec3a(0.3, 0.5, -0.2),
+            vec3a(0.0, 0.5, 0.2),
+            floor,
+        ));
+        scene
+    }
+
+    /// A light above a floor, enclosed by a ceiling so that light bounces between the two.
+    fn enclosed_scene() -> HittableList {
+        let mut scene = small_light_scene();
+        let ceiling: Arc<dyn Material> = Arc::new(Lambertian::new(Color::splat(0.7)));
+        scene.add(Triangle::new(
+            vec3a(-10.0, 1.1, -10.0),
+            vec3a(10.0, 1.1, 10.0),
+            vec3a(-10.0, 1.1, 10.0),
+            ceiling.clone(),
+        ));
+        scene.add(Triangle::new(
+            vec3a(-10.0, 1.1, -10.0),
+            vec3a(10.0, 1.1, -10.0),
+            vec3a(10.0, 1.1, 10.0),
+            ceiling,
+        ));
+        scene
+    }
+
+    /// Average the radiance along a ray looking down at the floor below the light.
+    fn mean_radiance(scene: &HittableList, settings: RenderSettings, num_samples: u32) -> Color {
+        let mut renderer = Renderer::new(1, 1);
+        renderer.set_settings(settings);
+
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(42);
+        let ray = Ray::new(vec3a(0.0, 0.5, 2.0), vec3a(0.0, -0.5, -2.0).normalize());
+        let mut sum = Color::ZERO;
+        for _ in 0..num_samples {
+            sum += renderer.ray_color(&ray, scene, &mut rng, None);
+        }
+        sum / num_samples as f32
+    }
+
+    /// Check that rendering with next event estimation converges to the same radiance as
+    /// rendering without it, which is given four times as many samples.
+    fn assert_matches_brute_force(
+        scene: &HittableList,
+        settings: RenderSettings,
+        samples: u32,
+        tolerance: f32,
+    ) {
+        let brute_force = mean_radiance(
+            scene,
+            RenderSettings {
+                next_event_estimation: false,
+                ..settings
+            },
+            4 * samples,
+        );
+        let nee = mean_radiance(scene, settings, samples);
+
+        assert!(brute_force.min_element() > 0.0, "{}", brute_force);
+        let relative_error = ((nee - brute_force) / brute_force).abs().max_element();
+        assert!(
+            relative_error < tolerance,
+            "Max bounces: {}, NEE: {}, brute force: {}",
+            settings.max_bounces,
+            nee,
+            brute_force
+        );
+    }
+
+    #[test]
+    fn next_event_estimation_matches_brute_force() {
+        let scene = small_light_scene();
+        assert_eq!(scene.lights().len(), 1);
+
+        let settings = RenderSettings {
+            max_bounces: 1,
+            ..Default::default()
+        };
+        assert_matches_brute_force(&scene, settings, 250_000, 0.03);
+    }
+
+    #[test]
+    fn textured_emitters_match_brute_force() {
+        // Lights are picked by their emission at the center, so the MIS weights of hits on
+        // them must use the same density, and not the emission at the hit.
+        let mut scene = small_light_scene();
+        let textured_light = |texels: Vec<Vec4>| -> Arc<dyn Material> {
+            Arc::new(GltfMaterial {
+                base_color: Color::ZERO,
+                metallic: 0.0,
+                roughness: 1.0,
+                emissive: Color::splat(5.0),
+                textures: GltfTextures {
+                    emissive: Some(Arc::new(Texture::new(texels.len(), 1, texels))),
+                    ..Default::default()
+                },
+            })
+        };
+        // Black on one half and white on the other.
+        scene.add(Quad::new(
+            vec3a(0.5, 0.8, -0.5),
+            vec3a(1.0, 0.0, 0.0),
+            vec3a(0.0, 0.0, 1.0),
+            textured_light(vec![Vec4::ZERO, Vec4::ONE]),
+        ));
+        // Black in the center, so it's never sampled and only found by BSDF sampling.
+        scene.add(Quad::new(
+            vec3a(-1.5, 0.8, -0.5),
+            vec3a(1.0, 0.0, 0.0),
+            vec3a(0.0, 0.0, 1.0),
+            textured_light(vec![Vec4::ONE, Vec4::ZERO, Vec4::ONE]),
+        ));
+        assert_eq!(scene.lights().len(), 2);
+
+        let settings = RenderSettings {
+            max_bounces: 1,
+            ..Default::default()
+        };
+        assert_matches_brute_force(&scene, settings, 100_000, 0.02);
+    }
+
+    #[test]
+    fn last_bounce_is_lit_with_and_without_nee() {
+        // A large light close to the floor, where BSDF sampling finds it as often as light
+        // sampling does, so that MIS splits its light evenly between the two.
+        let mut scene = enclosed_scene();
+        scene.add_light(Light {
+            shape: LightShape::Rect {
+                corner: vec3a(-1.0, 1.05, -1.0),
+                edge_u: vec3a(2.0, 0.0, 0.0),
+                edge_v: vec3a(0.0, 0.0, 2.0),
+            },
+            color: Color::ONE,
+            power: 50.0,
+        });
+
+        // The floor is lit directly even without any indirect bounces.
+        for max_bounces in [0, 1] {
+            let settings = RenderSettings {
+                max_bounces,
+                ..Default::default()
+            };
+            assert_matches_brute_force(&scene, settings, 100_000, 0.02);
+        }
+    }
+
+    #[test]
+    fn unsampled_emitters_match_brute_force() {
+        // An emissive plane can't be sampled by NEE, so only BSDF sampling finds it, while
+        // the small light is found by both.
+        let mut scene = small_light_scene();
+        let glow: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::ZERO,
+            emission: Color::splat(0.5),
+        });
+        scene.add(Plane::new(
+            vec3a(0.0, 1.1, 0.0),
+            vec3a(0.0, -1.0, 0.0),
+            glow,
+        ));
+        assert_eq!(scene.lights().len(), 1);
+
+        let settings = RenderSettings {
+            max_bounces: 1,
+            ..Default::default()
+        };
+        assert_matches_brute_force(&scene, settings, 100_000, 0.02);
+    }
+
+    #[test]
+    fn environment_sampling_matches_brute_force() {
+        // A floor lit by an uneven, rotated environment map, and by a sky with a sun large
+        // enough to be found by BSDF sampling.
+        let mut scene = HittableList::new();
+        let floor = Arc::new(Lambertian::new(Color::splat(0.5)));
+        scene.add(Plane::new(Vec3A::ZERO, vec3a(0.0, 1.0, 0.0), floor));
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height)
+            .map(|i| vec3a(1.0, 0.5, 0.25) * ((i * 7) % 5) as f32)
+            .collect();
+        let map = EnvironmentMap::new(HdrImage::new(width, height, pixels));
+        let sky = Sky::new(SkyParams {
+            sun_elevation: 30.0,
+            sun_size: 20.0,
+            ..Default::default()
+        });
+
+        let settings = RenderSettings {
+            max_bounces: 1,
+            ..Default::default()
+        };
+        for background in [Background::Map(Arc::new(map)), Background::Sky(sky)] {
+            scene.set_environment(Environment {
+                background,
+                rotation: 0.5,
+                ..Default::default()
+            });
+            // Without any indirect bounces, the sampled environment light relies on the ray
+            // leaving the last bounce for its MIS weights.
+            for max_bounces in [0, 1] {
+                let settings = RenderSettings {
+                    max_bounces,
+                    ..settings
+                };
+                assert_matches_brute_force(&scene, settings, 50_000, 0.03);
+            }
+        }
+
+        // Under a constant background, the floor reflects its albedo times the radiance.
+        scene.set_environment(Environment {
+            background: Background::Constant(Color::splat(2.0)),
+            visible: false,
+            ..Default::default()
+        });
+        let radiance = mean_radiance(&scene, settings, 10_000);
+        assert!(
+            (radiance - Color::ONE).abs().max_element() < 0.02,
+            "{}",
+            radiance
+        );
+
+        // A hidden background is black to the camera.
+        let renderer = Renderer::new(1, 1);
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(3);
+        let sky = Ray::new(vec3a(0.0, 1.0, 0.0), vec3a(0.0, 1.0, 0.0));
+        assert_eq!(
+            renderer.ray_color(&sky, &scene, &mut rng, None),
+            Color::ZERO
+        );
+    }
+
+    #[test]
+    fn scene_lights_are_sampled() {
+        let floor: Arc<dyn Material> = Arc::new(Lambertian::new(Color::splat(0.8)));
+        let mut scene = HittableList::new();
+        scene.add(Triangle::new(
+            vec3a(-10.0, 0.0, -10.0),
+            vec3a(-10.0, 0.0, 10.0),
+            vec3a(10.0, 0.0, 10.0),
+            floor.clone(),
+        ));
+        scene.add(Triangle::new(
+            vec3a(-10.0, 0.0, -10.0),
+            vec3a(10.0, 0.0, 10.0),
+            vec3a(10.0, 0.0, -10.0),
+            floor.clone(),
+        ));
+        let settings = RenderSettings {
+            max_bounces: 0,
+            ..Default::default()
+        };
+        // The floor below the light reflects albedo / pi of the irradiance.
+        let expected = Color::splat(0.8 / PI * 4.0);
+
+        // A point light emitting 16π W has an intensity of 4 W/sr.
+        scene.add_light(Light {
+            shape: LightShape::Point {
+                position: vec3a(0.0, 1.0, 0.0),
+            },
+            color: Color::ONE,
+            power: 16.0 * PI,
+        });
+        let radiance = mean_radiance(&scene, settings, 1);
+        assert!((radiance - expected).abs().max_element() < 1e-4);
+
+        // Half of the light is blocked by an occluder.
+        scene.add_light(Light::from_intensity(
+            LightShape::Spot {
+                position: vec3a(0.0, 2.0, 0.0),
+                direction: vec3a(0.0, -1.0, 0.0),
+                inner_angle: 0.9f32.acos(),
+                outer_angle: 0.8f32.acos(),
+            },
+            Color::ONE,
+            16.0,
+        ));
+        scene.add(Triangle::new(
+            vec3a(-0.5, 1.5, -0.5),
+            vec3a(0.5, 1.5, -0.5),
+            vec3a(0.0, 1.5, 0.5),
+            floor,
+        ));
+        let radiance = mean_radiance(&scene, settings, 1);
+        assert!((radiance - expected).abs().max_element() < 1e-4);
+
+        // Directional lights arrive with the same irradiance everywhere.
+        scene.add_light(Light {
+            shape: LightShape::Directional {
+                direction: vec3a(0.0, -1.0, 1.0),
+            },
+            color: Color::ONE,
+            power: 2.0,
+        });
+        let radiance = mean_radiance(&scene, settings, 1);
+        let expected = expected + Color::splat(0.8 / PI * 2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        assert!((radiance - expected).abs().max_element() < 1e-4);
+    }
+
+    #[test]
+    fn area_lights_emit_their_power() {
+        let floor: Arc<dyn Material> = Arc::new(Lambertian::new(Color::splat(0.8)));
+        let mut scene = HittableList::new();
+        scene.add(Plane::new(Vec3A::ZERO, vec3a(0.0, 1.0, 0.0), floor));
+        let settings = RenderSettings {
+            max_bounces: 0,
+            ..Default::default()
+        };
+        let assert_close = |radiance: Color, expected: Color| {
+            let relative_error = ((radiance - expected) / expected).abs().max_element();
+            assert!(relative_error < 0.02, "{} vs. {}", radiance, expected);
+        };
+
+        // Seen from outside, a diffuse sphere is as bright as a point light of the same power.
+        scene.add_light(Light {
+            shape: LightShape::Sphere {
+                center: vec3a(0.0, 1.0, 0.0),
+                radius: 0.1,
+            },
+            color: Color::ONE,
+            power: 16.0 * PI,
+        });
+        assert_eq!(scene.lights().len(), 1);
+        assert_close(
+            mean_radiance(&scene, settings, 20_000),
+            Color::splat(0.8 / PI * 4.0),
+        );
+
+        // A small rect emits half of its power downwards.
+        scene.set_light(
+            0,
+            Light {
+                shape: LightShape::Rect {
+                    corner: vec3a(-0.01, 1.0, -0.01),
+                    edge_u: vec3a(0.02, 0.0, 0.0),
+                    edge_v: vec3a(0.0, 0.0, 0.02),
+                },
+                color: Color::ONE,
+                power: 8.0 * PI,
+            },
+        );
+        assert_eq!(scene.lights().len(), 1);
+        assert_close(
+            mean_radiance(&scene, settings, 1000),
+            Color::splat(0.8 / PI * 4.0),
+        );
+
+        scene.remove_light(0);
+        assert!(scene.lights().is_empty());
+        assert_eq!(mean_radiance(&scene, settings, 1), Color::ZERO);
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let scene = enclosed_scene();
+        let settings = RenderSettings {
+            max_bounces: 8,
+            min_bounces: 8,
+            ..Default::default()
+        };
+        let full_depth = mean_radiance(&scene, settings, 200_000);
+        let roulette = mean_radiance(
+            &scene,
+            RenderSettings {
+                min_bounces: 0,
+                ..settings
+            },
+            200_000,
+        );
+
+        let relative_error = ((roulette - full_depth) / full_depth).abs().max_element();
+        assert!(
+            relative_error < 0.02,
+            "Russian roulette: {}, full depth: {}",
+            roulette,
+            full_depth
+        );
+    }
+
+    #[test]
+    fn debug_modes_show_first_hit() {
+        let mut scene = small_light_scene();
+        let mut renderer = Renderer::new(1, 1);
+        let mut color = |scene: &HittableList, render_mode, ray: &Ray| {
+            renderer.set_settings(RenderSettings {
+                render_mode,
+                heat_map_max: 8.0,
+                ..Default::default()
+            });
+            renderer.debug_color(ray, scene)
+        };
+
+        // Looking straight down at the floor next to the occluder.
+        let ray = Ray::new(vec3a(2.0, 5.0, 0.0), vec3a(0.0, -1.0, 0.0));
+        let normal = color(&scene, RenderMode::GeometricNormals, &ray);
+        assert_eq!(normal, vec3a(0.5, 1.0, 0.5));
+        assert_eq!(color(&scene, RenderMode::Albedo, &ray), Color::splat(0.8));
+        assert_eq!(color(&scene, RenderMode::Depth, &ray), heat_map(5.0 / 8.0));
+        let barycentrics = color(&scene, RenderMode::Barycentrics, &ray);
+        assert!((barycentrics.dot(Vec3A::ONE) - 1.0).abs() < 1e-5);
+        assert!(barycentrics.cmpge(Vec3A::ZERO).all());
+        let mut hit = HitPayload::new();
+        scene.hit(&ray, 0.0, f32::INFINITY, &mut hit);
+        let object = color(&scene, RenderMode::ObjectIndex, &ray);
+        assert_eq!(object, index_color(hit.object_index));
+        assert_ne!(object, index_color(hit.object_index + 1));
+
+        // Without a BVH every triangle is tested.
+        let tests = color(&scene, RenderMode::TriangleTests, &ray);
+        assert_eq!(tests, heat_map(4.0 / 8.0));
+        let cold = heat_map(0.0);
+        assert_eq!(color(&scene, RenderMode::BvhNodeVisits, &ray), cold);
+        scene.build_bvh();
+        assert_ne!(color(&scene, RenderMode::BvhNodeVisits, &ray), cold);
+
+        // Rays which miss are black.
+        let miss = Ray::new(vec3a(2.0, 5.0, 0.0), vec3a(0.0, 1.0, 0.0));
+        assert_eq!(color(&scene, RenderMode::Albedo, &miss), Color::ZERO);
+    }
+
+    #[test]
+    fn aovs_split_the_lighting() {
+        let scene = enclosed_scene();
+        let renderer = Renderer::new(1, 1);
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(3);
+
+        // Looking at the light, which is seen directly.
+        let ray = Ray::new(vec3a(-0.1, 0.2, -0.1), vec3a(0.0, 1.0, 0.0));
+        let mut aov = AovSample::default();
+        let color = renderer.ray_color(&ray, &scene, &mut rng, Some(&mut aov));
+        assert_eq!(aov.emission, Color::splat(50.0));
+        assert!((aov.depth - 0.8).abs() < 1e-6);
+        assert_eq!(aov.normal, vec3a(0.0, -1.0, 0.0));
+        assert_eq!(aov.albedo, Color::ZERO);
+        assert_eq!(aov.object_id, 2.0);
+        assert!((aov.emission + aov.direct + aov.indirect - color).length() < 1e-4);
+
+        // The floor is lit both directly and by light bouncing off the ceiling.
+        let ray = Ray::new(vec3a(2.0, 1.0, 0.0), vec3a(0.0, -1.0, 0.0));
+        let mut sum = AovSample::default();
+        let mut total = Color::ZERO;
+        for _ in 0..1000 {
+            let mut aov = AovSample::default();
+            total += renderer.ray_color(&ray, &scene, &mut rng, Some(&mut aov));
+            sum.accumulate(&aov, 1.0);
+        }
+        let mean = sum.average(1000.0);
+        assert_eq!(mean.emission, Color::ZERO);
+        assert!(mean.direct.x > 0.0 && mean.indirect.x > 0.0);
+        assert!((mean.albedo - Color::splat(0.8)).length() < 1e-4);
+        assert!((mean.position - vec3a(2.0, 0.0, 0.0)).length() < 1e-4);
+        let lighting = mean.emission + mean.direct + mean.indirect;
+        assert!((lighting - total / 1000.0).length() < 1e-3);
+    }
+
+    #[test]
+    fn aov_depth_ignores_misses() {
+        let scene = small_light_scene();
+        let renderer = Renderer::new(1, 1);
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(3);
+
+        // Half of the rays hit the floor 2 units below, the others miss upwards.
+        let hit = Ray::new(vec3a(2.0, 2.0, 0.0), vec3a(0.0, -1.0, 0.0));
+        let miss = Ray::new(vec3a(2.0, 2.0, 0.0), vec3a(0.0, 1.0, 0.0));
+        let mut sum = AovSample::default();
+        for ray in [hit, miss, hit, miss] {
+            let mut aov = AovSample::default();
+            renderer.ray_color(&ray, &scene, &mut rng, Some(&mut aov));
+            sum.accumulate(&aov, 1.0);
+        }
+        let mean = sum.average(4.0);
+        assert_eq!(mean.coverage, 0.5);
+        assert!((mean.mean_depth() - 2.0).abs() < 1e-5);
+        assert!(mean.get(Aov::Depth).is_finite());
+
+        let mut aov = AovSample::default();
+        renderer.ray_color(&miss, &scene, &mut rng, Some(&mut aov));
+        assert_eq!(aov.mean_depth(), f32::INFINITY);
+    }
+
+    #[test]
+    fn object_id_is_taken_at_the_pixel_center() {
+        // A narrow strip runs through the center of a single pixel, in front of a wall which
+        // covers most of the pixel.
+        let mut scene = HittableList::new();
+        let grey = Arc::new(Lambertian::new(Color::splat(0.5)));
+        scene.add(Quad::new(
+            vec3a(-0.5, -10.0, -5.0),
+            vec3a(1.0, 0.0, 0.0),
+            vec3a(0.0, 20.0, 0.0),
+            grey.clone(),
+        ));
+        scene.add(Quad::new(
+            vec3a(-10.0, -10.0, -6.0),
+            vec3a(20.0, 0.0, 0.0),
+            vec3a(0.0, 20.0, 0.0),
+            grey,
+        ));
+        let mut camera = Camera::new(45.0, 0.1, 100.0, 1, 1);
+        camera.set_position(Vec3A::ZERO);
+        camera.set_forward_direction(vec3a(0.0, 0.0, -1.0));
+
+        let mut renderer = Renderer::new(1, 1);
+        renderer.set_aovs_enabled(true);
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(11);
+        for _ in 0..64 {
+            renderer.render(&scene, &camera, &mut rng);
+        }
+        let object_id = renderer.get_aov_image(Aov::ObjectId).unwrap().pixels()[0];
+        assert_eq!(object_id, Color::splat(0.0));
+    }
+
+    #[test]
+    fn parse_render_modes() {
+        for mode in RenderMode::ALL {
+            assert_eq!(mode.name().parse(), Ok(mode));
+        }
+        assert!("normals".parse::<RenderMode>().is_err());
+    }
+
+    /// A Cornell box lit by a small light in the ceiling, with the camera looking through
+    /// the open front.
+    fn cornell_box() -> HittableList {
+        let lambertian = |albedo: Color| -> Arc<dyn Material> { Arc::new(Lambertian::new(albedo)) };
+        let white = lambertian(Color::splat(0.75));
+        let red = lambertian(vec3a(0.75, 0.1, 0.1));
+        let green = lambertian(vec3a(0.1, 0.75, 0.1));
+        let light: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: Color::ZERO,
+            emission: Color::splat(15.0),
+        });
+
+        let (x, y, z) = (2.0 * Vec3A::X, 2.0 * Vec3A::Y, 2.0 * Vec3A::Z);
+        let corner = vec3a(-1.0, 0.0, -1.0);
+        let mut scene = HittableList::new();
+        scene.add(Quad::new(corner, z, x, white.clone())); // Floor.
+        scene.add(Quad::new(corner + y, x, z, white.clone())); // Ceiling.
+        scene.add(Quad::new(corner, x, y, white)); // Back.
+        scene.add(Quad::new(corner, y, z, red)); // Left.
+        scene.add(Quad::new(corner + x, z, y, green)); // Right.
+        scene.add(Quad::new(
+            vec3a(-0.25, 1.99, -0.25),
+            0.5 * Vec3A::X,
+            0.5 * Vec3A::Z,
+            light,
+        ));
+        scene.build_bvh();
+        scene
+    }
+
+    #[test]
+    fn denoiser_reduces_error_of_fixed_seed_render() {
+        // Large enough for the walls to be much wider than their edges, which the denoiser
+        // preserves along with their noise.
+        let scene = cornell_box();
+        let (width, height) = (64, 64);
+        let camera = Camera::new(45.0, 0.1, 100.0, width, height);
+        let render = |frames: u32, seed: u64, denoiser: Option<Denoiser>| {
+            let mut renderer = Renderer::new(width as usize, height as usize);
+            renderer.set_denoiser(denoiser);
+            let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(seed);
+            for _ in 0..frames {
+                renderer.render(&scene, &camera, &mut rng);
+            }
+            renderer
+        };
+
+        // The reference has a seed of its own, so that it doesn't contain the noisy frames.
+        let reference = render(256, 1, None).get_linear_image();
+        let noisy = render(4, 2024, Some(Denoiser::default()));
+        // Compare compressed colors, so that a few bright pixels don't dominate.
+        let error = |image: &HdrImage| {
+            let compress = |c: Color| c / (1.0 + c);
+            let sum: f32 = image
+                .pixels()
+                .iter()
+                .zip(reference.pixels())
+                .map(|(a, b)| (compress(*a) - compress(*b)).length_squared())
+                .sum();
+            sum / image.pixels().len() as f32
+        };
+        let noisy_error = error(&noisy.get_linear_image());
+        let denoised_error = error(&noisy.get_denoised_image().unwrap());
+        assert!(
+            denoised_error < 0.5 * noisy_error,
+            "Denoised: {}, noisy: {}",
+            denoised_error,
+            noisy_error
+        );
+
+        // The final image shows the denoised render.
+        let denoised = noisy.get_denoised_image().unwrap();
+        let top_down_first_pixel = noisy.get_final_image().len() - width as usize * 4;
+        let expected = noisy.display_transform.apply(denoised.pixels()[0]);
+        assert_eq!(
+            noisy.get_final_image()[top_down_first_pixel..top_down_first_pixel + 3],
+            expected
+        );
+    }
+
+    #[test]
+    fn jittered_samples_are_filtered() {
+        // A black wall covers the left half of the image, and its edge runs through the
+        // middle of the center column.
+        let mut scene = HittableList::new();
+        let black = Arc::new(Lambertian::new(Color::ZERO));
+        scene.add(Quad::new(
+            vec3a(-10.0, -10.0, -5.0),
+            vec3a(10.0, 0.0, 0.0),
+            vec3a(0.0, 20.0, 0.0),
+            black,
+        ));
+        scene.set_environment(Environment {
+            background: Background::Constant(Color::ONE),
+            ..Default::default()
+        });
+        let mut camera = Camera::new(45.0, 0.1, 100.0, 9, 9);
+        camera.set_position(Vec3A::ZERO);
+        camera.set_forward_direction(vec3a(0.0, 0.0, -1.0));
+
+        let render = |filter: Filter| {
+            let mut renderer = Renderer::new(9, 9);
+            renderer.set_settings(RenderSettings {
+                filter,
+                ..Default::default()
+            });
+            renderer.set_aovs_enabled(true);
+            let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(5);
+            for _ in 0..256 {
+                renderer.render(&scene, &camera, &mut rng);
+            }
+            renderer
+        };
+        // Pixels of the middle row, from left to right.
+        let row = |image: &HdrImage, x: usize| image.pixels()[4 * 9 + x].x;
+
+        // The box filter averages the samples inside each pixel, so the edge is anti-aliased
+        // without blurring the columns next to it.
+        let image = render(Filter::Box).get_linear_image();
+        assert!((row(&image, 4) - 0.5).abs() < 0.1, "{}", row(&image, 4));
+        assert_eq!(row(&image, 3), 0.0);
+        assert_eq!(row(&image, 5), 1.0);
+
+        // Wider filters blend in the samples of the neighbors.
+        let renderer = render(Filter::Gaussian);
+        let image = renderer.get_linear_image();
+        assert!((row(&image, 4) - 0.5).abs() < 0.1, "{}", row(&image, 4));
+        assert!(
+            row(&image, 3) > 0.0 && row(&image, 3) < 0.5,
+            "{}",
+            row(&image, 3)
+        );
+        assert_eq!(row(&image, 1), 0.0);
+
+        // AOVs are filtered in the same way, so the background seen by the camera lines up
+        // with the image.
+        let emission = renderer.get_aov_image(Aov::Emission).unwrap();
+        for (a, b) in emission.pixels().iter().zip(image.pixels()) {
+            assert!((*a - *b).length() < 1e-5, "{} {}", a, b);
+        }
+
+        // Weights are normalized, even for filters with negative lobes and at the borders.
+        for filter in Filter::ALL {
+            let image = render(filter).get_linear_image();
+            for i in [8, 4 * 9 + 8, 80] {
+                assert!((image.pixels()[i].x - 1.0).abs() < 1e-5, "{}", filter);
+            }
+        }
+    }
+
+    #[test]
+    fn linear_image_is_unclamped_average() {
+        // A black floor below a bright background.
+        let mut scene = HittableList::new();
+        let black = Arc::new(Lambertian::new(Color::ZERO));
+        scene.add(Plane::new(Vec3A::ZERO, vec3a(0.0, 1.0, 0.0), black));
+        scene.set_environment(Environment {
+            background: Background::Constant(Color::splat(5.0)),
+            ..Default::default()
+        });
+        let camera = Camera::new(45.0, 0.1, 100.0, 4, 4);
+
+        let mut renderer = Renderer::new(4, 4);
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(1);
+        for _ in 0..3 {
+            renderer.render(&scene, &camera, &mut rng);
+        }
+
+        // The first row of the linear image is the top, which sees the background.
+        let image = renderer.get_linear_image();
+        let pixels = image.pixels();
+        assert_eq!(pixels[0], Color::splat(5.0));
+        assert_eq!(pixels[pixels.len() - 1], Color::ZERO);
+        assert!(renderer.get_aov_image(Aov::Depth).is_none());
+
+        // AOVs are accumulated from the next frame on.
+        renderer.set_aovs_enabled(true);
+        renderer.render(&scene, &camera, &mut rng);
+        let depth = renderer.get_aov_image(Aov::Depth).unwrap();
+        assert_eq!(depth.pixels()[0], Vec3A::splat(f32::INFINITY));
+        assert!(depth.pixels()[pixels.len() - 1].x.is_finite());
+        let emission = renderer.get_aov_image(Aov::Emission).unwrap();
+        assert_eq!(emission.pixels()[0], Color::splat(5.0));
+    }
+}
@@ -52,7 +52,7 @@ mod tests {
         let mut x;
         for _ in 0..10_000_000 {
             x = rng.random_uniform();
-            assert!(x >= 0.0 && x < 1.0);
+            assert!((0.0..1.0).contains(&x));
         }
     }
 
@@ -63,7 +63,7 @@ mod tests {
         let mut x;
         for _ in 0..10_000_000 {
             x = rng.random_range(-1.2, 5.3);
-            assert!(x >= -1.2 && x < 5.3);
+            assert!((-1.2..5.3).contains(&x));
         }
     }
 }
@@ -0,0 +1,1439 @@
+use crate::{
+    bvh::{BvhConfig, SplitMethod},
+    camera::Camera,
+    cuboid::Cuboid,
+    disk::Disk,
+    environment::{Background, Environment, EnvironmentMap},
+    filter::Filter,
+    gltf_scene::GltfScene,
+    hittable_list::HittableList,
+    light::{Light, LightShape},
+    material::{
+        Dielectric, GltfMaterial, GltfTextures, Lambertian, Material, Mirror, RoughConductor,
+    },
+    mesh::{Mesh, MeshGeometry},
+    plane::Plane,
+    quad::Quad,
+    renderer::RenderSettings,
+    sky::{Sky, SkyParams},
+    sphere::Sphere,
+    Color,
+};
+use glam::{vec3a, EulerRot, Quat, Vec3A};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use toml::{Spanned, Value};
+
+// Camera used when a scene doesn't specify one.
+const DEFAULT_FOV: f32 = 45.0;
+const DEFAULT_NEAR: f32 = 0.1;
+const DEFAULT_FAR: f32 = 100.0;
+
+/// A scene loaded from a scene description file.
+///
+/// Scene files are TOML documents with optional `[camera]`, `[render]` and `[environment]`
+/// tables and any number of `[[mesh]]`, `[[shape]]` and `[[light]]` tables.
+/// See `scenes/cornell.toml`.
+pub struct Scene {
+    pub world: HittableList,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+}
+
+/// Error produced when a scene description can't be loaded.
+#[derive(Debug)]
+pub struct SceneError {
+    path: PathBuf,
+    line: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: Option<CameraDesc>,
+    render: Option<RenderDesc>,
+    environment: Option<EnvironmentDesc>,
+    #[serde(default, rename = "mesh")]
+    meshes: Vec<MeshDesc>,
+    #[serde(default, rename = "shape")]
+    shapes: Vec<ShapeDesc>,
+    #[serde(default, rename = "light")]
+    lights: Vec<LightDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    fov: Option<Spanned<f32>>,
+    near: Option<Spanned<f32>>,
+    far: Option<Spanned<f32>>,
+    position: Option<Spanned<Value>>,
+    forward: Option<Spanned<Value>>,
+    aperture_radius: Option<Spanned<f32>>,
+    f_stop: Option<Spanned<f32>>,
+    focus_distance: Option<Spanned<f32>>,
+    aperture_blades: Option<Spanned<u32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RenderDesc {
+    bounces: Option<u32>,
+    min_bounces: Option<Spanned<u32>>,
+    samples_per_pixel: Option<Spanned<u32>>,
+    background: Option<Spanned<Value>>,
+    next_event_estimation: Option<bool>,
+    filter: Option<Spanned<String>>,
+    bvh: Option<Spanned<String>>,
+}
+
+/// The background. Which of the parameters are used depends on the type.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDesc {
+    #[serde(rename = "type")]
+    ty: Spanned<String>,
+    color: Option<Spanned<Value>>,
+    bottom: Option<Spanned<Value>>,
+    top: Option<Spanned<Value>>,
+    path: Option<Spanned<Value>>,
+    rotation: Option<Spanned<Value>>,
+    sun_elevation: Option<Spanned<Value>>,
+    sun_azimuth: Option<Spanned<Value>>,
+    turbidity: Option<Spanned<Value>>,
+    sun_size: Option<Spanned<Value>>,
+    intensity: Option<Spanned<Value>>,
+    visible: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    path: Spanned<String>,
+    scale: Option<Spanned<Value>>,
+    rotation: Option<Spanned<Value>>,
+    translation: Option<Spanned<Value>>,
+    material: Option<MaterialDesc>,
+}
+
+/// An analytic shape. Which of the parameters are used depends on the type.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShapeDesc {
+    #[serde(rename = "type")]
+    ty: Spanned<String>,
+    center: Option<Spanned<Value>>,
+    radius: Option<Spanned<Value>>,
+    point: Option<Spanned<Value>>,
+    normal: Option<Spanned<Value>>,
+    corner: Option<Spanned<Value>>,
+    edge_u: Option<Spanned<Value>>,
+    edge_v: Option<Spanned<Value>>,
+    min: Option<Spanned<Value>>,
+    max: Option<Spanned<Value>>,
+    material: Option<MaterialDesc>,
+}
+
+/// A material table. Which of the parameters are used depends on the type.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    #[serde(rename = "type")]
+    ty: Spanned<String>,
+    albedo: Option<Spanned<Value>>,
+    emission: Option<Spanned<Value>>,
+    color: Option<Spanned<Value>>,
+    roughness: Option<Spanned<Value>>,
+    ior: Option<Spanned<Value>>,
+    tint: Option<Spanned<Value>>,
+    base_color: Option<Spanned<Value>>,
+    metallic: Option<Spanned<Value>>,
+    emissive: Option<Spanned<Value>>,
+}
+
+/// A light. Which of the parameters are used depends on the type.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LightDesc {
+    #[serde(rename = "type")]
+    ty: Spanned<String>,
+    position: Option<Spanned<Value>>,
+    direction: Option<Spanned<Value>>,
+    inner_angle: Option<Spanned<Value>>,
+    outer_angle: Option<Spanned<Value>>,
+    center: Option<Spanned<Value>>,
+    radius: Option<Spanned<Value>>,
+    corner: Option<Spanned<Value>>,
+    edge_u: Option<Spanned<Value>>,
+    edge_v: Option<Spanned<Value>>,
+    color: Option<Spanned<Value>>,
+    power: Option<Spanned<Value>>,
+    irradiance: Option<Spanned<Value>>,
+}
+
+impl Scene {
+    /// Load a scene description file, or import a `.gltf` or `.glb` file directly.
+    /// The camera is created with the given viewport dimensions.
+    pub fn load(
+        path: impl AsRef<Path>,
+        viewport_width: u32,
+        viewport_height: u32,
+    ) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("gltf" | "glb")
+        ) {
+            return Self::from_gltf(path, viewport_width, viewport_height);
+        }
+
+        let src = fs::read_to_string(path).map_err(|e| SceneError {
+            path: path.to_path_buf(),
+            line: None,
+            message: format!("Failed to read scene file: {}", e),
+        })?;
+
+        Self::parse(&src, path, viewport_width, viewport_height)
+    }
+
+    /// Import a glTF file with its meshes, lights and first camera.
+    /// Files without a camera get the default one of scene files.
+    pub fn from_gltf(
+        path: &Path,
+        viewport_width: u32,
+        viewport_height: u32,
+    ) -> Result<Self, SceneError> {
+        let path_str = path.to_str().ok_or_else(|| SceneError {
+            path: path.to_path_buf(),
+            line: None,
+            message: "Path is not valid UTF-8".to_string(),
+        })?;
+        let GltfScene { world, camera } =
+            GltfScene::load(path_str, viewport_width, viewport_height).map_err(|e| SceneError {
+                path: path.to_path_buf(),
+                line: None,
+                message: format!("Failed to import glTF file: {}", e),
+            })?;
+        let mut camera = camera.unwrap_or_else(|| {
+            Camera::new(
+                DEFAULT_FOV,
+                DEFAULT_NEAR,
+                DEFAULT_FAR,
+                viewport_width,
+                viewport_height,
+            )
+        });
+        // glTF cameras have no focus distance.
+        camera.focus_on_scene(&world);
+
+        Ok(Self {
+            world,
+            camera,
+            settings: RenderSettings::default(),
+        })
+    }
+
+    /// Parse a scene description. Mesh paths are resolved relative to the
+    /// directory containing `path`, which is also used for error messages.
+    pub fn parse(
+        src: &str,
+        path: &Path,
+        viewport_width: u32,
+        viewport_height: u32,
+    ) -> Result<Self, SceneError> {
+        let parser = Parser { src, path };
+
+        let desc: SceneDesc = toml::from_str(src).map_err(|e| SceneError {
+            path: path.to_path_buf(),
+            line: e.line_col().map(|(line, _)| line + 1),
+            message: e.to_string(),
+        })?;
+
+        // Camera.
+        let mut fov = DEFAULT_FOV;
+        let mut near = DEFAULT_NEAR;
+        let mut far = DEFAULT_FAR;
+        let mut position = None;
+        let mut forward = None;
+        if let Some(cam) = &desc.camera {
+            if let Some(v) = &cam.fov {
+                fov = *v.get_ref();
+                if !(fov > 0.0 && fov < 180.0) {
+                    return Err(
+                        parser.error(v.start(), "Field of view must be between 0 and 180 degrees")
+                    );
+                }
+            }
+            if let Some(v) = &cam.near {
+                near = *v.get_ref();
+                if near.is_nan() || near <= 0.0 {
+                    return Err(parser.error(v.start(), "Near clip must be positive"));
+                }
+            }
+            if let Some(v) = &cam.far {
+                far = *v.get_ref();
+                if far.is_nan() || far <= near {
+                    return Err(parser.error(v.start(), "Far clip must be beyond the near clip"));
+                }
+            }
+            if let Some(v) = &cam.position {
+                position = Some(parser.vec3(v.get_ref(), v.start())?);
+            }
+            if let Some(v) = &cam.forward {
+                let dir = parser.vec3(v.get_ref(), v.start())?;
+                if dir.length_squared() == 0.0 {
+                    return Err(parser.error(v.start(), "Forward direction must be non-zero"));
+                }
+                forward = Some(dir);
+            }
+        }
+
+        let mut camera = Camera::new(fov, near, far, viewport_width, viewport_height);
+        if let Some(position) = position {
+            camera.set_position(position);
+        }
+        if let Some(forward) = forward {
+            camera.set_forward_direction(forward);
+        }
+
+        // Depth of field.
+        if let Some(cam) = &desc.camera {
+            match (&cam.aperture_radius, &cam.f_stop) {
+                (Some(_), Some(v)) => {
+                    return Err(parser.error(
+                        v.start(),
+                        "The f-stop sets the aperture radius, only one can be given",
+                    ))
+                }
+                (Some(v), None) => {
+                    if !(v.get_ref().is_finite() && *v.get_ref() >= 0.0) {
+                        return Err(parser.error(v.start(), "Aperture radius must not be negative"));
+                    }
+                    camera.set_aperture_radius(*v.get_ref());
+                }
+                (None, Some(v)) => {
+                    if !(v.get_ref().is_finite() && *v.get_ref() > 0.0) {
+                        return Err(parser.error(v.start(), "F-stop must be positive"));
+                    }
+                    camera.set_f_stop(*v.get_ref());
+                }
+                (None, None) => {}
+            }
+            if let Some(v) = &cam.focus_distance {
+                if !(v.get_ref().is_finite() && *v.get_ref() > 0.0) {
+                    return Err(parser.error(v.start(), "Focus distance must be positive"));
+                }
+                camera.set_focus_distance(*v.get_ref());
+            }
+            if let Some(v) = &cam.aperture_blades {
+                if matches!(*v.get_ref(), 1 | 2) {
+                    return Err(parser.error(
+                        v.start(),
+                        "Aperture blades must be 0 for a round aperture, or at least 3",
+                    ));
+                }
+                camera.set_aperture_blades(*v.get_ref());
+            }
+        }
+
+        // Render settings.
+        let mut settings = RenderSettings::default();
+        if let Some(render) = &desc.render {
+            if let Some(bounces) = render.bounces {
+                settings.max_bounces = bounces;
+            }
+            if let Some(v) = &render.min_bounces {
+                settings.min_bounces = *v.get_ref();
+                if settings.min_bounces > settings.max_bounces {
+                    return Err(parser.error(
+                        v.start(),
+                        "Minimum bounces must not exceed the maximum bounces",
+                    ));
+                }
+            }
+            if let Some(v) = &render.samples_per_pixel {
+                settings.samples_per_pixel = *v.get_ref();
+                if settings.samples_per_pixel == 0 {
+                    return Err(parser.error(v.start(), "Samples per pixel must be at least 1"));
+                }
+            }
+            if let Some(nee) = render.next_event_estimation {
+                settings.next_event_estimation = nee;
+            }
+            if let Some(v) = &render.filter {
+                settings.filter = v
+                    .get_ref()
+                    .parse::<Filter>()
+                    .map_err(|e| parser.error(v.start(), &e))?;
+            }
+        }
+
+        // BVH builder used for the meshes.
+        let bvh_config = match desc.render.as_ref().and_then(|render| render.bvh.as_ref()) {
+            Some(v) => {
+                let split_method = match v.get_ref().as_str() {
+                    "sah" => SplitMethod::Sah,
+                    "midpoint" => SplitMethod::Midpoint,
+                    other => {
+                        return Err(parser.error(
+                            v.start(),
+                            &format!(
+                                "Unknown BVH builder '{}', expected 'sah' or 'midpoint'",
+                                other
+                            ),
+                        ))
+                    }
+                };
+                BvhConfig {
+                    split_method,
+                    ..Default::default()
+                }
+            }
+            None => BvhConfig::default(),
+        };
+
+        // Meshes.
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        let mut world = HittableList::new();
+        // Meshes referencing the same file are instances of one shared geometry.
+        let mut geometries: HashMap<PathBuf, Arc<MeshGeometry>> = HashMap::new();
+        for mesh_desc in &desc.meshes {
+            let mesh_path = base_dir.join(mesh_desc.path.get_ref());
+            if !mesh_path.is_file() {
+                return Err(parser.error(
+                    mesh_desc.path.start(),
+                    &format!("Mesh file '{}' does not exist", mesh_path.display()),
+                ));
+            }
+
+            let scale = match &mesh_desc.scale {
+                Some(v) => {
+                    let scale = match v.get_ref() {
+                        // A single number is a uniform scale.
+                        Value::Integer(_) | Value::Float(_) => {
+                            Vec3A::splat(parser.number(v.get_ref(), v.start())?)
+                        }
+                        _ => parser.vec3(v.get_ref(), v.start())?,
+                    };
+                    if scale.cmpeq(Vec3A::ZERO).any() {
+                        return Err(parser.error(v.start(), "Scale components must be non-zero"));
+                    }
+                    scale
+                }
+                None => Vec3A::ONE,
+            };
+            let rotation = match &mesh_desc.rotation {
+                // Euler angles in degrees, applied in XYZ order.
+                Some(v) => {
+                    let angles = parser.vec3(v.get_ref(), v.start())?;
+                    Quat::from_euler(
+                        EulerRot::XYZ,
+                        angles.x.to_radians(),
+                        angles.y.to_radians(),
+                        angles.z.to_radians(),
+                    )
+                }
+                None => Quat::IDENTITY,
+            };
+            let translation = match &mesh_desc.translation {
+                Some(v) => parser.vec3(v.get_ref(), v.start())?,
+                None => Vec3A::ZERO,
+            };
+            let material = match &mesh_desc.material {
+                Some(material) => Some(parser.material(material)?),
+                None => None,
+            };
+
+            let geometry = match geometries.get(&mesh_path) {
+                Some(geometry) => geometry.clone(),
+                None => {
+                    let mesh_path_str = mesh_path.to_str().ok_or_else(|| {
+                        parser.error(
+                            mesh_desc.path.start(),
+                            &format!("Mesh path '{}' is not valid UTF-8", mesh_path.display()),
+                        )
+                    })?;
+                    let mesh = Mesh::load(mesh_path_str, bvh_config).map_err(|e| {
+                        parser.error(
+                            mesh_desc.path.start(),
+                            &format!("Failed to load mesh '{}': {}", mesh_path.display(), e),
+                        )
+                    })?;
+                    geometries.insert(mesh_path.clone(), mesh.geometry().clone());
+                    mesh.geometry().clone()
+                }
+            };
+
+            let mut mesh = Mesh::from_geometry(geometry);
+            mesh.transformation(scale, rotation, translation);
+            if let Some(material) = material {
+                mesh.set_material(material);
+            }
+            world.add(mesh);
+        }
+
+        // Shapes.
+        for shape_desc in &desc.shapes {
+            parser.add_shape(shape_desc, &mut world)?;
+        }
+
+        // Lights.
+        for light_desc in &desc.lights {
+            world.add_light(parser.light(light_desc)?);
+        }
+
+        // Environment. A plain background color can also be given with the render settings.
+        let background = desc.render.as_ref().and_then(|r| r.background.as_ref());
+        match (background, &desc.environment) {
+            (Some(_), Some(env_desc)) => {
+                return Err(parser.error(
+                    env_desc.ty.start(),
+                    "The environment replaces the render background, only one can be given",
+                ))
+            }
+            (Some(v), None) => world.set_environment(Environment {
+                background: Background::Constant(parser.vec3(v.get_ref(), v.start())?),
+                ..Default::default()
+            }),
+            (None, Some(env_desc)) => {
+                world.set_environment(parser.environment(env_desc, base_dir)?);
+            }
+            (None, None) => {}
+        }
+        world.build_bvh();
+
+        // The camera is posed by now, so it can focus on what it sees.
+        let focus_distance = desc
+            .camera
+            .as_ref()
+            .and_then(|cam| cam.focus_distance.as_ref());
+        if focus_distance.is_none() {
+            camera.focus_on_scene(&world);
+        }
+
+        Ok(Self {
+            world,
+            camera,
+            settings,
+        })
+    }
+}
+
+/// Helper for interpreting values of a scene description and reporting errors
+/// at the line they occurred on.
+struct Parser<'a> {
+    src: &'a str,
+    path: &'a Path,
+}
+
+impl<'a> Parser<'a> {
+    /// Create an error located at the given byte offset into the source.
+    fn error(&self, offset: usize, message: &str) -> SceneError {
+        let line = self.src[..offset.min(self.src.len())].matches('\n').count() + 1;
+        SceneError {
+            path: self.path.to_path_buf(),
+            line: Some(line),
+            message: message.to_string(),
+        }
+    }
+
+    fn number(&self, value: &Value, offset: usize) -> Result<f32, SceneError> {
+        let x = match value {
+            Value::Integer(i) => *i as f32,
+            Value::Float(f) => *f as f32,
+            other => {
+                return Err(self.error(
+                    offset,
+                    &format!("Expected a number, found {}", other.type_str()),
+                ))
+            }
+        };
+        if !x.is_finite() {
+            return Err(self.error(offset, "Expected a finite number"));
+        }
+        Ok(x)
+    }
+
+    fn vec3(&self, value: &Value, offset: usize) -> Result<Vec3A, SceneError> {
+        let expected = "Expected an array of 3 numbers";
+        let array = match value {
+            Value::Array(array) if array.len() == 3 => array,
+            Value::Array(array) => {
+                return Err(self.error(
+                    offset,
+                    &format!("{}, found {} elements", expected, array.len()),
+                ))
+            }
+            other => {
+                return Err(self.error(offset, &format!("{}, found {}", expected, other.type_str())))
+            }
+        };
+
+        let mut xyz = [0.0; 3];
+        for (x, value) in xyz.iter_mut().zip(array) {
+            *x = match value {
+                Value::Integer(i) => *i as f32,
+                Value::Float(f) if f.is_finite() => *f as f32,
+                other => {
+                    return Err(
+                        self.error(offset, &format!("{}, found element {}", expected, other))
+                    )
+                }
+            };
+        }
+
+        Ok(vec3a(xyz[0], xyz[1], xyz[2]))
+    }
+
+    fn string<'v>(&self, value: &'v Value, offset: usize) -> Result<&'v str, SceneError> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(self.error(
+                offset,
+                &format!("Expected a string, found {}", other.type_str()),
+            )),
+        }
+    }
+
+    /// Check that a table whose `type` decides which parameters it accepts, such as a light,
+    /// only has parameters of its type. `kind` names the table in errors.
+    fn check_parameters(
+        &self,
+        kind: &str,
+        ty: &Spanned<String>,
+        parameters: &[&str],
+        fields: &[(&str, &Option<Spanned<Value>>)],
+    ) -> Result<(), SceneError> {
+        for (name, value) in fields {
+            if let Some(v) = value {
+                if !parameters.contains(name) {
+                    return Err(self.error(
+                        v.start(),
+                        &format!("Unknown parameter '{}' for {} {}", name, ty.get_ref(), kind),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the parameters of a table like `check_parameters`, and return a lookup of
+    /// required parameters, which reports missing ones at the type.
+    fn typed_fields<'v>(
+        &'v self,
+        kind: &'v str,
+        ty: &'v Spanned<String>,
+        parameters: &[&str],
+        fields: Vec<(&'v str, &'v Option<Spanned<Value>>)>,
+    ) -> Result<impl Fn(&str) -> Result<&'v Spanned<Value>, SceneError> + 'v, SceneError> {
+        self.check_parameters(kind, ty, parameters, &fields)?;
+
+        // Parsers only differ in how long they borrow the source.
+        let parser: &'v Parser<'v> = self;
+        Ok(move |name: &str| {
+            let (_, value) = fields.iter().find(|(n, _)| *n == name).unwrap();
+            value.as_ref().ok_or_else(|| {
+                parser.error(
+                    ty.start(),
+                    &format!("Missing parameter '{}' for {} {}", name, ty.get_ref(), kind),
+                )
+            })
+        })
+    }
+
+    /// Create an analytic shape such as `{ type = "sphere", center = [0, 1, 0], radius = 1 }`
+    /// and add it to the world. Shapes without a material are grey and diffuse.
+    fn add_shape(&self, desc: &ShapeDesc, world: &mut HittableList) -> Result<(), SceneError> {
+        let ty = desc.ty.get_ref().as_str();
+        let parameters: &[&str] = match ty {
+            "sphere" => &["center", "radius"],
+            "plane" => &["point", "normal"],
+            "disk" => &["center", "normal", "radius"],
+            "quad" => &["corner", "edge_u", "edge_v"],
+            "box" => &["min", "max"],
+            _ => {
+                return Err(self.error(
+                    desc.ty.start(),
+                    &format!(
+                        "Unknown shape type '{}', expected sphere, plane, disk, quad or box",
+                        ty
+                    ),
+                ))
+            }
+        };
+
+        let field = self.typed_fields(
+            "shape",
+            &desc.ty,
+            parameters,
+            vec![
+                ("center", &desc.center),
+                ("radius", &desc.radius),
+                ("point", &desc.point),
+                ("normal", &desc.normal),
+                ("corner", &desc.corner),
+                ("edge_u", &desc.edge_u),
+                ("edge_v", &desc.edge_v),
+                ("min", &desc.min),
+                ("max", &desc.max),
+            ],
+        )?;
+        let vector = |name: &str| field(name).and_then(|v| self.vec3(v.get_ref(), v.start()));
+        let radius = || {
+            let v = field("radius")?;
+            let radius = self.number(v.get_ref(), v.start())?;
+            if radius <= 0.0 {
+                return Err(self.error(v.start(), "Radius must be positive"));
+            }
+            Ok(radius)
+        };
+        let normal = || {
+            let normal = vector("normal")?;
+            if normal.length_squared() == 0.0 {
+                return Err(self.error(field("normal")?.start(), "Normal must be non-zero"));
+            }
+            Ok(normal)
+        };
+
+        let material = match &desc.material {
+            Some(material) => self.material(material)?,
+            None => Arc::new(Lambertian::new(Color::splat(0.8))),
+        };
+        match ty {
+            "sphere" => world.add(Sphere::new(vector("center")?, radius()?, material)),
+            "plane" => world.add(Plane::new(vector("point")?, normal()?, material)),
+            "disk" => world.add(Disk::new(vector("center")?, normal()?, radius()?, material)),
+            "quad" => {
+                let (edge_u, edge_v) = (vector("edge_u")?, vector("edge_v")?);
+                if edge_u.cross(edge_v).length_squared() == 0.0 {
+                    return Err(self.error(
+                        desc.ty.start(),
+                        "Quad edges must be non-zero and not parallel",
+                    ));
+                }
+                world.add(Quad::new(vector("corner")?, edge_u, edge_v, material));
+            }
+            _ => {
+                let (min, max) = (vector("min")?, vector("max")?);
+                if min.cmpeq(max).any() {
+                    return Err(self.error(desc.ty.start(), "Box must have a non-zero size"));
+                }
+                world.add(Cuboid::new(min, max, material));
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a light such as `{ type = "point", position = [0, 2, 0], power = 100 }`.
+    /// Lights are white unless given a color. Their power is in watts, except for directional
+    /// lights which are given their irradiance in W/m² instead.
+    fn light(&self, desc: &LightDesc) -> Result<Light, SceneError> {
+        let ty = desc.ty.get_ref().as_str();
+        let parameters: &[&str] = match ty {
+            "point" => &["position", "color", "power"],
+            "spot" => &[
+                "position",
+                "direction",
+                "inner_angle",
+                "outer_angle",
+                "color",
+                "power",
+            ],
+            "directional" => &["direction", "color", "irradiance"],
+            "sphere" => &["center", "radius", "color", "power"],
+            "rect" => &["corner", "edge_u", "edge_v", "color", "power"],
+            _ => {
+                return Err(self.error(
+                    desc.ty.start(),
+                    &format!(
+                        "Unknown light type '{}', expected point, spot, directional, sphere \
+                         or rect",
+                        ty
+                    ),
+                ))
+            }
+        };
+
+        let field = self.typed_fields(
+            "light",
+            &desc.ty,
+            parameters,
+            vec![
+                ("position", &desc.position),
+                ("direction", &desc.direction),
+                ("inner_angle", &desc.inner_angle),
+                ("outer_angle", &desc.outer_angle),
+                ("center", &desc.center),
+                ("radius", &desc.radius),
+                ("corner", &desc.corner),
+                ("edge_u", &desc.edge_u),
+                ("edge_v", &desc.edge_v),
+                ("color", &desc.color),
+                ("power", &desc.power),
+                ("irradiance", &desc.irradiance),
+            ],
+        )?;
+        let vector = |name: &str| field(name).and_then(|v| self.vec3(v.get_ref(), v.start()));
+        let direction = || {
+            let direction = vector("direction")?;
+            if direction.length_squared() == 0.0 {
+                return Err(self.error(field("direction")?.start(), "Direction must be non-zero"));
+            }
+            Ok(direction.normalize())
+        };
+        // A non-negative number, such as the power.
+        let amount = |name: &str| {
+            let v = field(name)?;
+            let x = self.number(v.get_ref(), v.start())?;
+            if x < 0.0 {
+                return Err(self.error(v.start(), &format!("{} must not be negative", name)));
+            }
+            Ok(x)
+        };
+        // Cone angle in degrees.
+        let angle = |v: &Option<Spanned<Value>>, default: f32| match v {
+            Some(v) => {
+                let x = self.number(v.get_ref(), v.start())?;
+                if !(0.0..=90.0).contains(&x) {
+                    return Err(self.error(v.start(), "Expected an angle between 0 and 90"));
+                }
+                Ok(x.to_radians())
+            }
+            None => Ok(default.to_radians()),
+        };
+
+        let shape = match ty {
+            "point" => LightShape::Point {
+                position: vector("position")?,
+            },
+            "spot" => {
+                // The defaults of glTF's spot lights.
+                let inner_angle = angle(&desc.inner_angle, 0.0)?;
+                let outer_angle = angle(&desc.outer_angle, 45.0)?;
+                if inner_angle > outer_angle {
+                    return Err(self.error(
+                        field("inner_angle")?.start(),
+                        "Inner angle must not be larger than the outer angle",
+                    ));
+                }
+                LightShape::Spot {
+                    position: vector("position")?,
+                    direction: direction()?,
+                    inner_angle,
+                    outer_angle,
+                }
+            }
+            "directional" => LightShape::Directional {
+                direction: direction()?,
+            },
+            "sphere" => {
+                let v = field("radius")?;
+                let radius = self.number(v.get_ref(), v.start())?;
+                if radius <= 0.0 {
+                    return Err(self.error(v.start(), "Radius must be positive"));
+                }
+                LightShape::Sphere {
+                    center: vector("center")?,
+                    radius,
+                }
+            }
+            _ => {
+                let (edge_u, edge_v) = (vector("edge_u")?, vector("edge_v")?);
+                if edge_u.cross(edge_v).length_squared() == 0.0 {
+                    return Err(self.error(
+                        desc.ty.start(),
+                        "Rect edges must be non-zero and not parallel",
+                    ));
+                }
+                LightShape::Rect {
+                    corner: vector("corner")?,
+                    edge_u,
+                    edge_v,
+                }
+            }
+        };
+        let color = match &desc.color {
+            Some(v) => self.vec3(v.get_ref(), v.start())?,
+            None => Color::ONE,
+        };
+        let power = if ty == "directional" {
+            amount("irradiance")?
+        } else {
+            amount("power")?
+        };
+        Ok(Light {
+            shape,
+            color,
+            power,
+        })
+    }
+
+    /// Create the environment, loading its map relative to `base_dir`.
+    fn environment(
+        &self,
+        desc: &EnvironmentDesc,
+        base_dir: &Path,
+    ) -> Result<Environment, SceneError> {
+        let ty = desc.ty.get_ref().as_str();
+        let parameters: &[&str] = match ty {
+            "constant" => &["color"],
+            "gradient" => &["bottom", "top"],
+            "map" => &["path", "rotation"],
+            "sky" => &["sun_elevation", "sun_azimuth", "turbidity", "sun_size"],
+            _ => {
+                return Err(self.error(
+                    desc.ty.start(),
+                    &format!(
+                        "Unknown environment type '{}', expected constant, gradient, map or sky",
+                        ty
+                    ),
+                ))
+            }
+        };
+
+        let field = self.typed_fields(
+            "environment",
+            &desc.ty,
+            parameters,
+            vec![
+                ("color", &desc.color),
+                ("bottom", &desc.bottom),
+                ("top", &desc.top),
+                ("path", &desc.path),
+                ("rotation", &desc.rotation),
+                ("sun_elevation", &desc.sun_elevation),
+                ("sun_azimuth", &desc.sun_azimuth),
+                ("turbidity", &desc.turbidity),
+                ("sun_size", &desc.sun_size),
+            ],
+        )?;
+        let color = |v: &Option<Spanned<Value>>, default: Color| match v {
+            Some(v) => self.vec3(v.get_ref(), v.start()),
+            None => Ok(default),
+        };
+        // A number within the given range.
+        let scalar = |v: &Option<Spanned<Value>>, default: f32, min: f32, max: f32| match v {
+            Some(v) => {
+                let x = self.number(v.get_ref(), v.start())?;
+                if x < min || x > max {
+                    return Err(self.error(
+                        v.start(),
+                        &format!("Expected a number between {} and {}", min, max),
+                    ));
+                }
+                Ok(x)
+            }
+            None => Ok(default),
+        };
+
+        let mut environment = Environment::default();
+        match ty {
+            "constant" => {
+                environment.background = Background::Constant(color(&desc.color, Color::ZERO)?)
+            }
+            "gradient" => {
+                environment.background = Background::Gradient {
+                    bottom: color(&desc.bottom, Color::ONE)?,
+                    top: color(&desc.top, vec3a(0.5, 0.7, 1.0))?,
+                };
+            }
+            "sky" => {
+                let default = SkyParams::default();
+                environment.background = Background::Sky(Sky::new(SkyParams {
+                    sun_elevation: scalar(&desc.sun_elevation, default.sun_elevation, 0.0, 90.0)?,
+                    sun_azimuth: scalar(&desc.sun_azimuth, default.sun_azimuth, -360.0, 360.0)?,
+                    turbidity: scalar(&desc.turbidity, default.turbidity, 2.0, 10.0)?,
+                    sun_size: scalar(&desc.sun_size, default.sun_size, 0.01, 45.0)?,
+                }));
+            }
+            _ => {
+                let v = field("path")?;
+                let map_path = base_dir.join(self.string(v.get_ref(), v.start())?);
+                if !map_path.is_file() {
+                    return Err(self.error(
+                        v.start(),
+                        &format!("Environment map '{}' does not exist", map_path.display()),
+                    ));
+                }
+                let map = EnvironmentMap::load(&map_path).map_err(|e| {
+                    self.error(
+                        v.start(),
+                        &format!(
+                            "Failed to load environment map '{}': {}",
+                            map_path.display(),
+                            e
+                        ),
+                    )
+                })?;
+                environment.background = Background::Map(Arc::new(map));
+                if let Some(v) = &desc.rotation {
+                    environment.rotation = self.number(v.get_ref(), v.start())?.to_radians();
+                }
+            }
+        }
+
+        if let Some(v) = &desc.intensity {
+            environment.intensity = self.number(v.get_ref(), v.start())?;
+            if environment.intensity < 0.0 {
+                return Err(self.error(v.start(), "Intensity must not be negative"));
+            }
+        }
+        if let Some(visible) = desc.visible {
+            environment.visible = visible;
+        }
+        Ok(environment)
+    }
+
+    /// Interpret a material table such as `{ type = "dielectric", ior = 1.5 }`.
+    /// Parameters which aren't given take on default values.
+    fn material(&self, desc: &MaterialDesc) -> Result<Arc<dyn Material>, SceneError> {
+        let ty = desc.ty.get_ref().as_str();
+        let parameters: &[&str] = match ty {
+            "lambertian" => &["albedo", "emission"],
+            "mirror" => &["color"],
+            "conductor" => &["color", "roughness"],
+            "dielectric" => &["ior", "tint"],
+            "gltf" => &["base_color", "metallic", "roughness", "emissive"],
+            _ => {
+                return Err(self.error(desc.ty.start(), &format!("Unknown material type '{}'", ty)));
+            }
+        };
+        // Missing parameters have defaults, so only the unknown ones are checked.
+        self.check_parameters(
+            "material",
+            &desc.ty,
+            parameters,
+            &[
+                ("albedo", &desc.albedo),
+                ("emission", &desc.emission),
+                ("color", &desc.color),
+                ("roughness", &desc.roughness),
+                ("ior", &desc.ior),
+                ("tint", &desc.tint),
+                ("base_color", &desc.base_color),
+                ("metallic", &desc.metallic),
+                ("emissive", &desc.emissive),
+            ],
+        )?;
+
+        let color = |name: &str, value: &Option<Spanned<Value>>, default: f32| match value {
+            Some(v) => {
+                let color = self.vec3(v.get_ref(), v.start())?;
+                if color.min_element() < 0.0 {
+                    return Err(self.error(
+                        v.start(),
+                        &format!("Parameter '{}' must not be negative", name),
+                    ));
+                }
+                Ok(color)
+            }
+            None => Ok(Color::splat(default)),
+        };
+        let fraction = |name: &str, value: &Option<Spanned<Value>>, default: f32| match value {
+            Some(v) => {
+                let x = self.number(v.get_ref(), v.start())?;
+                if !(0.0..=1.0).contains(&x) {
+                    return Err(self.error(
+                        v.start(),
+                        &format!("Parameter '{}' must be between 0 and 1", name),
+                    ));
+                }
+                Ok(x)
+            }
+            None => Ok(default),
+        };
+        let ior = || match &desc.ior {
+            Some(v) => {
+                let ior = self.number(v.get_ref(), v.start())?;
+                if ior <= 0.0 {
+                    return Err(self.error(v.start(), "Parameter 'ior' must be positive"));
+                }
+                Ok(ior)
+            }
+            None => Ok(1.5),
+        };
+
+        let material: Arc<dyn Material> = match ty {
+            "lambertian" => Arc::new(Lambertian {
+                albedo: color("albedo", &desc.albedo, 0.8)?,
+                emission: color("emission", &desc.emission, 0.0)?,
+            }),
+            "mirror" => Arc::new(Mirror {
+                color: color("color", &desc.color, 1.0)?,
+            }),
+            "conductor" => Arc::new(RoughConductor {
+                color: color("color", &desc.color, 1.0)?,
+                roughness: fraction("roughness", &desc.roughness, 0.5)?,
+            }),
+            "dielectric" => Arc::new(Dielectric {
+                ior: ior()?,
+                tint: color("tint", &desc.tint, 1.0)?,
+            }),
+            _ => Arc::new(GltfMaterial {
+                base_color: color("base_color", &desc.base_color, 1.0)?,
+                metallic: fraction("metallic", &desc.metallic, 0.0)?,
+                roughness: fraction("roughness", &desc.roughness, 1.0)?,
+                emissive: color("emissive", &desc.emissive, 0.0)?,
+                textures: GltfTextures::default(),
+            }),
+        };
+
+        Ok(material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdr_image::{HdrFormat, HdrImage};
+
+    fn parse(src: &str) -> Result<Scene, SceneError> {
+        Scene::parse(src, Path::new("test.toml"), 8, 6)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reject_non_utf8_gltf_path() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let path = Path::new(OsStr::from_bytes(b"scene\xff.gltf"));
+        let err = Scene::from_gltf(path, 8, 6).err().unwrap();
+        assert_eq!(err.message, "Path is not valid UTF-8");
+    }
+
+    #[test]
+    fn parse_settings() {
+        let scene = parse(
+            "[camera]\n\
+             fov = 60\n\
+             position = [0, 2, 5.5]\n\
+             [render]\n\
+             bounces = 4\n\
+             min_bounces = 2\n\
+             samples_per_pixel = 2\n\
+             background = [0.5, 0.7, 1.0]\n\
+             next_event_estimation = false\n\
+             filter = \"mitchell\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(*scene.camera.get_position(), vec3a(0.0, 2.0, 5.5));
+        assert_eq!(scene.settings.max_bounces, 4);
+        assert_eq!(scene.settings.min_bounces, 2);
+        assert_eq!(scene.settings.samples_per_pixel, 2);
+        assert!(!scene.settings.next_event_estimation);
+        assert_eq!(scene.settings.filter, Filter::Mitchell);
+        assert_eq!(
+            scene.world.environment().radiance(vec3a(1.0, 0.0, 0.0)),
+            vec3a(0.5, 0.7, 1.0)
+        );
+
+        let err = parse("[render]\nfilter = \"lanczos\"\n").err().unwrap();
+        assert_eq!(err.line, Some(2));
+
+        let err = parse("[render]\nbounces = 2\nmin_bounces = 3\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+    }
+
+    #[test]
+    fn parse_depth_of_field() {
+        let scene = parse(
+            "[camera]\n\
+             fov = 45\n\
+             f_stop = 2.8\n\
+             focus_distance = 4\n\
+             aperture_blades = 6\n",
+        )
+        .unwrap();
+        let camera = &scene.camera;
+        assert!((camera.get_f_stop() - 2.8).abs() < 1e-5);
+        assert_eq!(camera.get_focus_distance(), 4.0);
+        assert_eq!(camera.get_aperture_blades(), 6);
+
+        let err = parse("[camera]\naperture_radius = 0.1\nf_stop = 2\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+
+        // Without a focus distance, the camera focuses on what it sees at the center, or
+        // else on the origin.
+        let camera_at =
+            |z: f32| format!("[camera]\nposition = [0, 1, {}]\nforward = [0, 0, -1]\n", z);
+        let scene = parse(&camera_at(10.0)).unwrap();
+        assert!((scene.camera.get_focus_distance() - 10.0).abs() < 1e-5);
+        let src =
+            camera_at(10.0) + "[[shape]]\ntype = \"sphere\"\ncenter = [0, 1, 0]\nradius = 1\n";
+        let scene = parse(&src).unwrap();
+        assert!((scene.camera.get_focus_distance() - 9.0).abs() < 1e-4);
+
+        let err = parse("[camera]\nfocus_distance = 0\n").err().unwrap();
+        assert_eq!(err.line, Some(2));
+
+        let err = parse("[camera]\naperture_blades = 2\n").err().unwrap();
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn parse_environment() {
+        let scene = parse(
+            "[environment]\n\
+             type = \"gradient\"\n\
+             top = [0, 0, 2]\n\
+             intensity = 0.5\n\
+             visible = false\n",
+        )
+        .unwrap();
+        let environment = scene.world.environment();
+        assert_eq!(
+            environment.radiance(vec3a(0.0, 1.0, 0.0)),
+            vec3a(0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            environment.radiance(vec3a(0.0, -1.0, 0.0)),
+            Color::splat(0.5)
+        );
+        assert!(!environment.visible);
+
+        // Maps are loaded relative to the scene file.
+        // The process id keeps concurrent test runs from sharing the file.
+        let dir = std::env::temp_dir();
+        let name = format!("leia_environment_test_{}.hdr", std::process::id());
+        let map = HdrImage::new(2, 1, vec![Color::splat(3.0), Color::ONE]);
+        map.save(&dir.join(&name), HdrFormat::Hdr).unwrap();
+        let src = format!(
+            "[environment]\ntype = \"map\"\npath = \"{}\"\nrotation = 90\n",
+            name
+        );
+        let scene = Scene::parse(&src, &dir.join("test.toml"), 8, 6);
+        std::fs::remove_file(dir.join(&name)).unwrap();
+        let environment = scene.unwrap().world.environment().clone();
+        assert!((environment.rotation - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        // The left half of the map is rotated from -x to +z.
+        assert_eq!(
+            environment.radiance(vec3a(0.0, 0.0, 1.0)),
+            Color::splat(3.0)
+        );
+
+        let err = parse("[environment]\ntype = \"map\"\npath = \"missing.hdr\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.contains("missing.hdr"));
+        let err = parse("[environment]\ntype = \"map\"\npath = 1\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+        let err = parse("[environment]\ntype = \"map\"\n").err().unwrap();
+        assert_eq!(err.message, "Missing parameter 'path' for map environment");
+
+        let scene =
+            parse("[environment]\ntype = \"sky\"\nsun_elevation = 90\nturbidity = 2\n").unwrap();
+        match &scene.world.environment().background {
+            Background::Sky(sky) => {
+                assert!((sky.sun_direction() - vec3a(0.0, 1.0, 0.0)).length() < 1e-6);
+                assert_eq!(sky.params().turbidity, 2.0);
+            }
+            other => panic!("Expected a sky, found {:?}", other),
+        }
+        let err = parse("[environment]\ntype = \"sky\"\nturbidity = 20\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+
+        let err = parse("[environment]\ntype = \"constant\"\nrotation = 90\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+
+        let err = parse("[render]\nbackground = [1, 1, 1]\n[environment]\ntype = \"constant\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(4));
+    }
+
+    #[test]
+    fn unknown_bvh_builder_reports_line() {
+        let err = parse("[render]\nbounces = 4\nbvh = \"octree\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.contains("octree"));
+    }
+
+    #[test]
+    fn missing_mesh_reports_line() {
+        let err = parse("[render]\nbounces = 4\n\n[[mesh]]\npath = \"missing.glb\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(5));
+        assert!(err.message.contains("missing.glb"));
+    }
+
+    #[test]
+    fn malformed_vector_reports_line() {
+        let err = parse("[camera]\nfov = 45\nposition = [0, 1]\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+
+        let err = parse("[camera]\nforward = [0, \"up\", 0]\n").err().unwrap();
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn malformed_material_reports_line() {
+        let material = |params: &str| {
+            let src = format!(
+                "[[shape]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n\
+                 [shape.material]\n{}",
+                params
+            );
+            parse(&src).map_err(|err| (err.line, err.message))
+        };
+        let err = material("type = \"glass\"").err().unwrap();
+        assert_eq!(err.0, Some(6));
+
+        // Errors are reported at the parameter.
+        let err = material("type = \"dielectric\"\nroughness = 0.5")
+            .err()
+            .unwrap();
+        assert_eq!(err.0, Some(7));
+        assert!(err.1.contains("Unknown parameter 'roughness'"));
+        assert!(material("type = \"mirror\"\ncolor = [1, 1, 1]").is_ok());
+
+        for params in [
+            "type = \"dielectric\"\nior = 0",
+            "type = \"conductor\"\nroughness = 1.5",
+            "type = \"gltf\"\nmetallic = -0.5",
+            "type = \"lambertian\"\nalbedo = [0.5, -0.1, 0.5]",
+        ] {
+            let err = material(params).err().unwrap();
+            assert_eq!(err.0, Some(7), "{}", err.1);
+        }
+    }
+
+    #[test]
+    fn parse_shapes() {
+        let scene = parse(
+            "[[shape]]\n\
+             type = \"sphere\"\n\
+             center = [0, 1, 0]\n\
+             radius = 1\n\
+             [[shape]]\n\
+             type = \"plane\"\n\
+             point = [0, 0, 0]\n\
+             normal = [0, 1, 0]\n\
+             [[shape]]\n\
+             type = \"quad\"\n\
+             corner = [-0.5, 3, -0.5]\n\
+             edge_u = [1, 0, 0]\n\
+             edge_v = [0, 0, 1]\n\
+             material = { type = \"lambertian\", emission = [4, 4, 4] }\n",
+        )
+        .unwrap();
+        assert_eq!(scene.world.len(), 3);
+        // The quad is an area light.
+        assert_eq!(scene.world.lights().len(), 1);
+
+        let err = parse("[[shape]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains("radius"));
+
+        let err = parse("[[shape]]\ntype = \"box\"\nmin = [0, 0, 0]\nradius = 1\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(4));
+
+        let err = parse("[[shape]]\ntype = \"cone\"\n").err().unwrap();
+        assert!(err.message.contains("cone"));
+    }
+
+    #[test]
+    fn parse_lights() {
+        let scene = parse(
+            "[[light]]\n\
+             type = \"spot\"\n\
+             position = [0, 2, 0]\n\
+             direction = [0, -2, 0]\n\
+             outer_angle = 30\n\
+             color = [1, 0.5, 0.5]\n\
+             power = 100\n\
+             [[light]]\n\
+             type = \"directional\"\n\
+             direction = [0, -1, 0]\n\
+             irradiance = 3\n\
+             [[light]]\n\
+             type = \"rect\"\n\
+             corner = [-0.5, 3, -0.5]\n\
+             edge_u = [1, 0, 0]\n\
+             edge_v = [0, 0, 1]\n\
+             power = 50\n",
+        )
+        .unwrap();
+        let lights = scene.world.scene_lights();
+        assert_eq!(lights.len(), 3);
+        assert_eq!(
+            lights[0].shape,
+            LightShape::Spot {
+                position: vec3a(0.0, 2.0, 0.0),
+                direction: vec3a(0.0, -1.0, 0.0),
+                inner_angle: 0.0,
+                outer_angle: 30f32.to_radians(),
+            }
+        );
+        assert_eq!(lights[0].color, vec3a(1.0, 0.5, 0.5));
+        assert_eq!(lights[1].intensity(), Color::splat(3.0));
+        assert_eq!(scene.world.lights().punctual().len(), 2);
+        // The rect is an area light.
+        assert_eq!(scene.world.lights().len(), 1);
+
+        let err = parse("[[light]]\ntype = \"point\"\nposition = [0, 0, 0]\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains("power"));
+
+        let err = parse("[[light]]\ntype = \"point\"\nradius = 1\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+
+        let err = parse(
+            "[[light]]\ntype = \"spot\"\nposition = [0, 0, 0]\ndirection = [0, -1, 0]\n\
+             inner_angle = 50\npower = 1\n",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.line, Some(5));
+
+        let err = parse("[[light]]\ntype = \"area\"\n").err().unwrap();
+        assert!(err.message.contains("area"));
+    }
+
+    #[test]
+    fn syntax_error_reports_line() {
+        let err = parse("[camera]\nfov = 45\nnear =\n").err().unwrap();
+        assert_eq!(err.line, Some(3));
+    }
+}
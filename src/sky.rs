@@ -0,0 +1,314 @@
+use crate::{onb::Onb, util, Color};
+use glam::*;
+use std::f32::consts::PI;
+
+/// Parameters of the procedural sky, in the units used by scene files and the UI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyParams {
+    /// Angle of the sun above the horizon in degrees, between 0 and 90.
+    pub sun_elevation: f32,
+    /// Angle of the sun about the y axis in degrees. At 0 the sun is in the -z direction,
+    /// and at 90 in the +x direction.
+    pub sun_azimuth: f32,
+    /// Haziness of the atmosphere, from 2 for a clear sky to 10 for a hazy one.
+    pub turbidity: f32,
+    /// Angular diameter of the sun disk in degrees. Larger suns cast softer shadows, but
+    /// give off the same amount of light.
+    pub sun_size: f32,
+}
+
+impl Default for SkyParams {
+    fn default() -> Self {
+        Self {
+            sun_elevation: 45.0,
+            sun_azimuth: 0.0,
+            turbidity: 3.0,
+            sun_size: 0.53,
+        }
+    }
+}
+
+/// Illuminance of the sun outside of the atmosphere, in kilolux.
+const SUN_ILLUMINANCE: f32 = 128.0;
+
+/// Wavelengths in micrometers used for the red, green and blue extinction of sunlight.
+const WAVELENGTHS: [f32; 3] = [0.680, 0.550, 0.440];
+
+/// Clear sky model of Preetham et al. 1999 "A Practical Analytic Model for Daylight", with
+/// a sun disk. Radiance is given in kcd/m², so daylight needs an exposure of around -5 EV.
+/// Below the horizon the sky is black.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    params: SkyParams,
+    /// Direction towards the center of the sun.
+    sun_direction: Vec3A,
+    cos_sun_radius: f32,
+    sun_radiance: Color,
+    /// Perez coefficients A to E of the luminance Y and the chromaticities x and y.
+    perez: [[f32; 5]; 3],
+    /// Y, x and y at the zenith, divided by the Perez function at the zenith.
+    zenith: [f32; 3],
+    /// Probability of sampling the sun rather than the sky.
+    sun_probability: f32,
+}
+
+impl Sky {
+    pub fn new(params: SkyParams) -> Self {
+        let elevation = params.sun_elevation.clamp(0.0, 90.0).to_radians();
+        let azimuth = params.sun_azimuth.to_radians();
+        let sun_direction = vec3a(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let theta_sun = PI / 2.0 - elevation;
+        let t = params.turbidity;
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (s, s2, s3) = (theta_sun, theta_sun * theta_sun, theta_sun.powi(3));
+        let zenith_x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886);
+        let zenith_y = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688);
+        let mut zenith = [zenith_luminance, zenith_x, zenith_y];
+        for (value, coefficients) in zenith.iter_mut().zip(&perez) {
+            *value /= perez_function(coefficients, 1.0, theta_sun);
+        }
+
+        // Extinction of sunlight by Rayleigh scattering and aerosols along its path through
+        // the atmosphere, whose relative length is given by Kasten and Young's air mass.
+        let air_mass =
+            1.0 / (theta_sun.cos() + 0.50572 * (96.07995 - theta_sun.to_degrees()).powf(-1.6364));
+        let beta = 0.04608 * t - 0.04586;
+        let transmittance = Vec3A::from(WAVELENGTHS.map(|lambda| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        }));
+        let cos_sun_radius = (0.5 * params.sun_size.clamp(1e-3, 90.0)).to_radians().cos();
+        let sun_solid_angle = 2.0 * PI * (1.0 - cos_sun_radius);
+        let sun_radiance = transmittance * SUN_ILLUMINANCE / sun_solid_angle;
+
+        let mut sky = Self {
+            params,
+            sun_direction,
+            cos_sun_radius,
+            sun_radiance,
+            perez,
+            zenith,
+            sun_probability: 0.0,
+        };
+
+        // Sample the sun and the sky in proportion to the light they give off, as seen by a
+        // surface facing up. Neither is starved, since either can be the one in view.
+        let sun_power = util::luminance(sun_radiance) * sun_solid_angle * elevation.sin();
+        let mut sky_power = 0.0;
+        let (rings, segments) = (16, 32);
+        for i in 0..rings {
+            let cos_theta = (i as f32 + 0.5) / rings as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..segments {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / segments as f32;
+                let direction = vec3a(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                sky_power += util::luminance(sky.sky_radiance(direction)) * cos_theta;
+            }
+        }
+        sky_power *= 2.0 * PI / (rings * segments) as f32;
+        sky.sun_probability = (sun_power / (sun_power + sky_power)).clamp(0.1, 0.9);
+        sky
+    }
+
+    pub fn params(&self) -> &SkyParams {
+        &self.params
+    }
+
+    #[allow(dead_code)]
+    pub fn sun_direction(&self) -> Vec3A {
+        self.sun_direction
+    }
+
+    /// Radiance arriving from a normalized direction, including the sun.
+    pub fn radiance(&self, direction: Vec3A) -> Color {
+        if direction.y <= 0.0 {
+            return Color::ZERO;
+        }
+        let mut radiance = self.sky_radiance(direction);
+        if direction.dot(self.sun_direction) >= self.cos_sun_radius {
+            radiance += self.sun_radiance;
+        }
+        radiance
+    }
+
+    /// Radiance of the sky without the sun, in a direction above the horizon.
+    fn sky_radiance(&self, direction: Vec3A) -> Color {
+        let cos_theta = direction.y.max(1e-4);
+        let gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] =
+            [0, 1, 2].map(|i| self.zenith[i] * perez_function(&self.perez[i], cos_theta, gamma));
+        if y <= 0.0 {
+            return Color::ZERO;
+        }
+
+        // xyY to XYZ to linear sRGB.
+        let xyz = vec3a(x * luminance / y, luminance, (1.0 - x - y) * luminance / y);
+        let xyz_to_srgb = Mat3A::from_cols(
+            vec3a(3.24045, -0.969266, 0.0556434),
+            vec3a(-1.53714, 1.87601, -0.204026),
+            vec3a(-0.498531, 0.041556, 1.05723),
+        );
+        (xyz_to_srgb * xyz).max(Color::ZERO)
+    }
+
+    /// Map a point of the unit square to a direction, which is either in the sun disk or
+    /// uniformly distributed over the upper hemisphere. Returns the direction and its solid
+    /// angle density.
+    pub fn sample(&self, mut u: Vec2) -> (Vec3A, f32) {
+        let direction = if u.x < self.sun_probability {
+            u.x /= self.sun_probability;
+            let cos_theta = 1.0 - u.x * (1.0 - self.cos_sun_radius);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.y;
+            Onb::from_w(self.sun_direction).local(vec3a(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                cos_theta,
+            ))
+        } else {
+            u.x = (u.x - self.sun_probability) / (1.0 - self.sun_probability);
+            let cos_theta = u.x;
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.y;
+            vec3a(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin())
+        };
+        (direction, self.pdf(direction))
+    }
+
+    /// Solid angle density with which `sample` generates a normalized direction.
+    pub fn pdf(&self, direction: Vec3A) -> f32 {
+        let mut pdf = 0.0;
+        if direction.y > 0.0 {
+            pdf += (1.0 - self.sun_probability) / (2.0 * PI);
+        }
+        if direction.dot(self.sun_direction) >= self.cos_sun_radius {
+            pdf += self.sun_probability / (2.0 * PI * (1.0 - self.cos_sun_radius));
+        }
+        pdf
+    }
+}
+
+/// Perez et al.'s sky luminance distribution for a direction whose angle to the zenith has
+/// the cosine `cos_theta`, and whose angle to the sun is `gamma`.
+fn perez_function(coefficients: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / cos_theta.max(1e-4)).exp())
+        * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_is_blue_and_sun_is_bright() {
+        let sky = Sky::new(SkyParams::default());
+        assert!((sky.sun_direction() - vec3a(0.0, 1.0, -1.0).normalize()).length() < 1e-6);
+
+        // The sky is bluish, brightest around the sun, and black below the horizon.
+        let zenith = sky.radiance(Vec3A::Y);
+        assert!(zenith.z > zenith.x, "{}", zenith);
+        let near_sun = sky.radiance(vec3a(0.0, 1.0, -0.8).normalize());
+        let away_from_sun = sky.radiance(vec3a(0.0, 1.0, 0.8).normalize());
+        assert!(util::luminance(near_sun) > util::luminance(away_from_sun));
+        assert_eq!(sky.radiance(vec3a(0.0, -0.1, 1.0).normalize()), Color::ZERO);
+        // Preetham's zenith luminance is a few kcd/m².
+        assert!(util::luminance(zenith) > 1.0 && util::luminance(zenith) < 20.0);
+
+        // The sun is reddened by the atmosphere, more so when it's low.
+        let sun = sky.sun_radiance;
+        assert!(sun.x > sun.z);
+        assert!(util::luminance(sky.radiance(sky.sun_direction())) > 1e5);
+        let sunset = Sky::new(SkyParams {
+            sun_elevation: 5.0,
+            ..Default::default()
+        });
+        let low_sun = sunset.sun_radiance;
+        assert!(low_sun.x / low_sun.z > sun.x / sun.z);
+
+        // A larger sun gives off the same light.
+        let solid_angle = |size: f32| 2.0 * PI * (1.0 - (0.5 * size).to_radians().cos());
+        let large = Sky::new(SkyParams {
+            sun_size: 5.0,
+            ..Default::default()
+        });
+        let ratio = util::luminance(sky.sun_radiance) * solid_angle(0.53)
+            / (util::luminance(large.sun_radiance) * solid_angle(5.0));
+        assert!((ratio - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn samples_match_pdf() {
+        let sky = Sky::new(SkyParams {
+            sun_size: 10.0,
+            ..Default::default()
+        });
+        // The density integrates to 1 over the sphere.
+        let n = 512;
+        let mut integral = 0.0;
+        for i in 0..n {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+            for j in 0..2 * n {
+                let phi = PI * (j as f32 + 0.5) / n as f32;
+                let r = (1.0 - y * y).sqrt();
+                integral += sky.pdf(vec3a(r * phi.cos(), y, r * phi.sin()));
+            }
+        }
+        integral *= 4.0 * PI / (2 * n * n) as f32;
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+
+        // Samples are consistent with the density, and land in the sun as often as expected.
+        let mut in_sun = 0;
+        for i in 0..64 {
+            for j in 0..64 {
+                let u = vec2((i as f32 + 0.5) / 64.0, (j as f32 + 0.5) / 64.0);
+                let (direction, pdf) = sky.sample(u);
+                assert!((direction.length() - 1.0).abs() < 1e-4);
+                assert_eq!(pdf, sky.pdf(direction));
+                if direction.dot(sky.sun_direction()) >= sky.cos_sun_radius {
+                    in_sun += 1;
+                }
+            }
+        }
+        // Uniform samples of the hemisphere hit the sun with its share of the solid angle.
+        let expected =
+            sky.sun_probability + (1.0 - sky.sun_probability) * (1.0 - sky.cos_sun_radius);
+        assert!((in_sun as f32 / 4096.0 - expected).abs() < 0.02);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    aabb::Aabb,
+    hittable::*,
+    light::{AreaLight, SurfacePoint},
+    material::Material,
+    ray::*,
+    Color,
+};
+use glam::*;
+use std::{f32::consts::PI, sync::Arc};
+
+/// Sphere with exact intersections, unlike a tessellated mesh.
+///
+/// Texture coordinates are longitude and latitude: `u` goes around the y axis and `v` goes
+/// from the top of the sphere to the bottom.
+#[derive(Debug, Clone)]
+pub struct Sphere {
+    center: Vec3A,
+    radius: f32,
+    material: Arc<dyn Material>,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3A, radius: f32, material: Arc<dyn Material>) -> Self {
+        Self {
+            center,
+            radius,
+            material,
+        }
+    }
+
+    /// Texture coordinates of a point with the given outward normal, and the derivatives of
+    /// the position with respect to them.
+    fn tex_coords(&self, n: Vec3A) -> (Vec2, Vec3A, Vec3A) {
+        let phi = f32::atan2(-n.z, n.x);
+        let theta = n.y.clamp(-1.0, 1.0).acos();
+        let uv = vec2((phi + PI) / (2.0 * PI), theta / PI);
+
+        let sin_theta = (n.x * n.x + n.z * n.z).sqrt();
+        if sin_theta < 1e-6 {
+            // The texture coordinates are singular at the poles.
+            return (uv, Vec3A::ZERO, Vec3A::ZERO);
+        }
+        let tangent = 2.0 * PI * self.radius * vec3a(n.z, 0.0, -n.x);
+        let bitangent =
+            PI * self.radius / sin_theta * vec3a(n.y * n.x, -sin_theta * sin_theta, n.y * n.z);
+        (uv, tangent, bitangent)
+    }
+}
+
+impl AreaLight for Sphere {
+    fn area(&self) -> f32 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    /// Radiance emitted at a point on the equator.
+    fn emission(&self) -> Color {
+        self.sample_point(vec2(0.5, 0.0)).emission
+    }
+
+    fn sample_point(&self, u: Vec2) -> SurfacePoint {
+        // Uniform on the sphere, since slices of equal height have equal areas.
+        let y = 1.0 - 2.0 * u.x;
+        let r = (1.0 - y * y).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.y;
+        let normal = vec3a(r * phi.cos(), y, r * phi.sin());
+
+        let (uv, _, _) = self.tex_coords(normal);
+        let position = self.center + self.radius * normal;
+        SurfacePoint::new(position, normal, uv, self.material.as_ref())
+    }
+}
+
+impl Hittable for Sphere {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        let oc = r.origin() - self.center;
+        let a = r.direction().length_squared();
+        let half_b = oc.dot(r.direction());
+        let c = oc.length_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0.0 {
+            return false;
+        }
+
+        // Find the nearest root in the acceptable range.
+        let sqrt_d = discriminant.sqrt();
+        let mut t = (-half_b - sqrt_d) / a;
+        if !(t_min..=t_max).contains(&t) {
+            t = (-half_b + sqrt_d) / a;
+            if !(t_min..=t_max).contains(&t) {
+                return false;
+            }
+        }
+
+        let position = r.origin() + t * r.direction();
+        let normal = (position - self.center) / self.radius;
+        let (uv, tangent, bitangent) = self.tex_coords(normal);
+
+        rec.hit_distance = t;
+        rec.world_position = position;
+        rec.set_face_normal(r, normal);
+        rec.uv = uv;
+        rec.tangent = tangent;
+        rec.bitangent = bitangent;
+        rec.material = Some(self.material.as_ref());
+        rec.barycentrics = None;
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points([
+            self.center - Vec3A::splat(self.radius),
+            self.center + Vec3A::splat(self.radius),
+        ])
+    }
+
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        if self.emission() == Color::ZERO {
+            return Vec::new();
+        }
+        vec![(0, Arc::new(self.clone()))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn unit_sphere() -> Sphere {
+        Sphere::new(Vec3A::ZERO, 1.0, Arc::new(Lambertian::new(Color::ONE)))
+    }
+
+    #[test]
+    fn hit_from_outside_and_inside() {
+        let sphere = unit_sphere();
+        let mut rec = HitPayload::new();
+        let r = Ray::new(vec3a(0.0, 0.0, 3.0), vec3a(0.0, 0.0, -1.0));
+        assert!(sphere.hit(&r, 0.0, f32::INFINITY, &mut rec));
+        assert_eq!(rec.hit_distance, 2.0);
+        assert!(rec.front_face);
+        assert_eq!(rec.world_normal, vec3a(0.0, 0.0, 1.0));
+        assert!((rec.uv - vec2(0.25, 0.5)).length() < 1e-6);
+
+        // Rays starting inside hit the far side from within.
+        let r = Ray::new(Vec3A::ZERO, vec3a(0.0, 1.0, 0.0));
+        assert!(sphere.hit(&r, 0.0, f32::INFINITY, &mut rec));
+        assert_eq!(rec.hit_distance, 1.0);
+        assert!(!rec.front_face);
+        assert_eq!(rec.world_normal, vec3a(0.0, -1.0, 0.0));
+        assert!(rec.uv.y < 1e-6);
+
+        let miss = Ray::new(vec3a(0.0, 1.5, 3.0), vec3a(0.0, 0.0, -1.0));
+        assert!(!sphere.hit(&miss, 0.0, f32::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn tangents_follow_tex_coords() {
+        let sphere = unit_sphere();
+        let n = vec3a(0.3, 0.5, -0.4).normalize();
+        let (uv, tangent, bitangent) = sphere.tex_coords(n);
+
+        // Compare with finite differences of the position.
+        let h = 1e-3;
+        let position = |uv: Vec2| {
+            let phi = uv.x * 2.0 * PI - PI;
+            let theta = uv.y * PI;
+            vec3a(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            )
+        };
+        assert!((position(uv) - n).length() < 1e-5);
+        let du = (position(uv + vec2(h, 0.0)) - position(uv - vec2(h, 0.0))) / (2.0 * h);
+        let dv = (position(uv + vec2(0.0, h)) - position(uv - vec2(0.0, h))) / (2.0 * h);
+        assert!((du - tangent).length() < 1e-2);
+        assert!((dv - bitangent).length() < 1e-2);
+    }
+}
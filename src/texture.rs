@@ -0,0 +1,151 @@
+use crate::util;
+use glam::*;
+use image::{ImageBuffer, Pixel};
+
+/// How texture coordinates outside of [0, 1] are mapped onto the texture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+/// An image with linear RGBA texels, sampled with bilinear filtering.
+/// Texture coordinates start at the top left corner of the image.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec4>,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, texels: Vec<Vec4>) -> Self {
+        assert_eq!(texels.len(), width * height);
+        assert!(width > 0 && height > 0);
+        Self {
+            width,
+            height,
+            texels,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+        }
+    }
+
+    /// Convert an 8-bit image. Color textures are usually sRGB encoded and are decoded to
+    /// linear values when `srgb` is set. Alpha is always linear.
+    pub fn from_image<P: Pixel<Subpixel = u8>>(
+        image: &ImageBuffer<P, Vec<u8>>,
+        srgb: bool,
+    ) -> Self {
+        let texels = image
+            .pixels()
+            .map(|p| {
+                let [r, g, b, a] = p.to_rgba().0.map(|c| c as f32 / 255.0);
+                if srgb {
+                    let decode = util::srgb_to_linear;
+                    vec4(decode(r), decode(g), decode(b), a)
+                } else {
+                    vec4(r, g, b, a)
+                }
+            })
+            .collect();
+        Self::new(image.width() as usize, image.height() as usize, texels)
+    }
+
+    /// Bilinearly filtered lookup.
+    pub fn sample(&self, uv: Vec2) -> Vec4 {
+        // Texel centers are at half-integer coordinates.
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let x0_wrapped = wrap(x0, self.width, self.wrap_u);
+        let x1_wrapped = wrap(x0 + 1, self.width, self.wrap_u);
+        let y0_wrapped = wrap(y0, self.height, self.wrap_v);
+        let y1_wrapped = wrap(y0 + 1, self.height, self.wrap_v);
+
+        let top = self
+            .texel(x0_wrapped, y0_wrapped)
+            .lerp(self.texel(x1_wrapped, y0_wrapped), tx);
+        let bottom = self
+            .texel(x0_wrapped, y1_wrapped)
+            .lerp(self.texel(x1_wrapped, y1_wrapped), tx);
+        top.lerp(bottom, ty)
+    }
+
+    fn texel(&self, x: usize, y: usize) -> Vec4 {
+        self.texels[y * self.width + x]
+    }
+}
+
+/// Map an integer texel coordinate into [0, size).
+fn wrap(i: i64, size: usize, mode: WrapMode) -> usize {
+    let size = size as i64;
+    let i = match mode {
+        WrapMode::Repeat => i.rem_euclid(size),
+        WrapMode::MirroredRepeat => {
+            // Every other repetition of the texture is flipped.
+            let i = i.rem_euclid(2 * size);
+            if i < size {
+                i
+            } else {
+                2 * size - 1 - i
+            }
+        }
+        WrapMode::ClampToEdge => i.clamp(0, size - 1),
+    };
+    i as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x1 texture which is black on the left and white on the right.
+    fn black_white() -> Texture {
+        Texture::new(2, 1, vec![Vec4::ZERO, Vec4::ONE])
+    }
+
+    #[test]
+    fn bilinear_filtering() {
+        let texture = black_white();
+        // Texel centers return the texel's value, and values in between are interpolated.
+        assert_eq!(texture.sample(vec2(0.25, 0.5)), Vec4::ZERO);
+        assert_eq!(texture.sample(vec2(0.75, 0.5)), Vec4::ONE);
+        assert!((texture.sample(vec2(0.5, 0.5)) - Vec4::splat(0.5)).length() < 1e-6);
+        assert!((texture.sample(vec2(0.375, 0.0)) - Vec4::splat(0.25)).length() < 1e-6);
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let mut texture = black_white();
+        // Halfway between the last texel and the first one of the next repetition.
+        let uv = vec2(1.0, 0.5);
+        assert!((texture.sample(uv) - Vec4::splat(0.5)).length() < 1e-6);
+        texture.wrap_u = WrapMode::ClampToEdge;
+        assert_eq!(texture.sample(uv), Vec4::ONE);
+        texture.wrap_u = WrapMode::MirroredRepeat;
+        assert_eq!(texture.sample(uv), Vec4::ONE);
+        assert_eq!(texture.sample(vec2(1.75, 0.5)), Vec4::ZERO);
+
+        assert_eq!(wrap(-1, 4, WrapMode::Repeat), 3);
+        assert_eq!(wrap(-1, 4, WrapMode::MirroredRepeat), 0);
+        assert_eq!(wrap(9, 4, WrapMode::MirroredRepeat), 1);
+        assert_eq!(wrap(-7, 4, WrapMode::ClampToEdge), 0);
+    }
+
+    #[test]
+    fn srgb_images_are_decoded() {
+        let image = image::RgbImage::from_pixel(1, 1, image::Rgb([255, 188, 0]));
+        let texel = Texture::from_image(&image, true).sample(vec2(0.5, 0.5));
+        assert!((texel - vec4(1.0, 0.5, 0.0, 1.0)).abs().max_element() < 0.01);
+        let texel = Texture::from_image(&image, false).sample(vec2(0.5, 0.5));
+        assert!((texel.y - 188.0 / 255.0).abs() < 1e-6);
+    }
+}
@@ -1,57 +1,165 @@
-use crate::{hittable::*, ray::*, Color};
+use crate::{
+    aabb::Aabb,
+    hittable::*,
+    light::{AreaLight, SurfacePoint},
+    material::Material,
+    ray::*,
+    Color,
+};
 use glam::*;
+use std::sync::Arc;
 
 /// Triangle's vertices are defined in CCW winding.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Triangle {
     v0: Vec3A,
     v1: Vec3A,
     v2: Vec3A,
-    normal: Vec3A,   // Triangle's surface normal.
-    centroid: Vec3A, // Used for BVH construction.
-    albedo: Color,
-    emissive: Color,
+    normal: Vec3A,                      // Triangle's surface normal.
+    vertex_normals: Option<[Vec3A; 3]>, // Interpolated for smooth shading when set.
+    tex_coords: Option<[Vec2; 3]>,
+    // Derivatives of the position with respect to the texture coordinates.
+    tangent: Vec3A,
+    bitangent: Vec3A,
+    material: Arc<dyn Material>,
 }
 
 impl Triangle {
-    pub fn new(v0: Vec3A, v1: Vec3A, v2: Vec3A, albedo: Color, emissive: Color) -> Self {
+    pub fn new(v0: Vec3A, v1: Vec3A, v2: Vec3A, material: Arc<dyn Material>) -> Self {
         // Compute the surface normal of the plane defined by the triangle.
         let normal = Vec3A::cross(v1 - v0, v2 - v0).normalize();
 
-        let centroid = (v0 + v1 + v2) * (1.0 / 3.0);
-
         Self {
             v0,
             v1,
             v2,
             normal,
-            centroid,
-            albedo,
-            emissive,
+            vertex_normals: None,
+            tex_coords: None,
+            tangent: Vec3A::ZERO,
+            bitangent: Vec3A::ZERO,
+            material,
+        }
+    }
+
+    /// Create a smooth shaded triangle. Falls back to flat shading if any of the vertex
+    /// normals is degenerate.
+    pub fn with_normals(
+        v0: Vec3A,
+        v1: Vec3A,
+        v2: Vec3A,
+        normals: [Vec3A; 3],
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let mut triangle = Self::new(v0, v1, v2, material);
+        if normals
+            .iter()
+            .all(|n| n.is_finite() && n.length_squared() > 0.0)
+        {
+            triangle.vertex_normals = Some(normals.map(|n| n.normalize()));
+        }
+        triangle
+    }
+
+    /// Set the texture coordinates of the vertices, which are interpolated at hits.
+    pub fn set_tex_coords(&mut self, tex_coords: [Vec2; 3]) {
+        self.tex_coords = Some(tex_coords);
+
+        // Solve for the derivatives of the position along the edges of the triangle.
+        let [uv0, uv1, uv2] = tex_coords;
+        let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+        let (edge1, edge2) = (self.v1 - self.v0, self.v2 - self.v0);
+        let det = duv1.x * duv2.y - duv1.y * duv2.x;
+        if det.abs() < 1e-12 {
+            // Degenerate mapping, normal maps can't be applied.
+            self.tangent = Vec3A::ZERO;
+            self.bitangent = Vec3A::ZERO;
+        } else {
+            self.tangent = (duv2.y * edge1 - duv1.y * edge2) / det;
+            self.bitangent = (duv1.x * edge2 - duv2.x * edge1) / det;
         }
     }
 
+    #[allow(dead_code)]
+    pub fn tex_coords(&self) -> Option<[Vec2; 3]> {
+        self.tex_coords
+    }
+
     pub fn vertices(&self) -> [Vec3A; 3] {
         [self.v0, self.v1, self.v2]
     }
 
-    pub fn centroid(&self) -> Vec3A {
-        self.centroid
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.material
+    }
+
+    pub fn set_material(&mut self, material: Arc<dyn Material>) {
+        self.material = material;
     }
 
-    pub fn albedo(&self) -> Color {
-        self.albedo
+    /// The triangle with its vertices moved by a transform and its normals by the inverse
+    /// transpose of the transform's matrix, keeping its texture coordinates and material.
+    /// The normal transform is passed in so that it's computed once for many triangles.
+    pub fn transformed(&self, transform: &Affine3A, normal_transform: &Mat3A) -> Self {
+        let [v0, v1, v2] = self.vertices().map(|v| transform.transform_point3a(v));
+        let material = self.material.clone();
+        let mut triangle = match self.vertex_normals {
+            Some(normals) => {
+                Self::with_normals(v0, v1, v2, normals.map(|n| *normal_transform * n), material)
+            }
+            None => Self::new(v0, v1, v2, material),
+        };
+        if let Some(tex_coords) = self.tex_coords {
+            triangle.set_tex_coords(tex_coords);
+        }
+        triangle
+    }
+
+    /// Radiance which a material, not necessarily the triangle's own, emits at its centroid.
+    pub fn emission_of(&self, material: &dyn Material) -> Color {
+        self.point_at(1.0 / 3.0, 1.0 / 3.0, material).emission
     }
 
-    pub fn emissive(&self) -> Color {
-        self.emissive
+    /// Point at barycentric coordinates `u` and `v`, the weights of `v1` and `v2`.
+    fn point_at(&self, u: f32, v: f32, material: &dyn Material) -> SurfacePoint {
+        let position = (1.0 - u - v) * self.v0 + u * self.v1 + v * self.v2;
+        let uv = self.interpolate_tex_coords(u, v);
+        SurfacePoint::new(position, self.normal, uv, material)
+    }
+
+    fn interpolate_tex_coords(&self, u: f32, v: f32) -> Vec2 {
+        match self.tex_coords {
+            Some([uv0, uv1, uv2]) => (1.0 - u - v) * uv0 + u * uv1 + v * uv2,
+            None => Vec2::ZERO,
+        }
+    }
+}
+
+impl AreaLight for Triangle {
+    fn area(&self) -> f32 {
+        0.5 * Vec3A::cross(self.v1 - self.v0, self.v2 - self.v0).length()
+    }
+
+    /// Radiance emitted by the triangle's material at its centroid.
+    /// Emission is assumed to be the same on both faces.
+    fn emission(&self) -> Color {
+        self.emission_of(self.material.as_ref())
+    }
+
+    fn sample_point(&self, u: Vec2) -> SurfacePoint {
+        // Uniformly distributed barycentric coordinates.
+        let su = u.x.sqrt();
+        let b1 = 1.0 - su;
+        let b2 = u.y * su;
+        self.point_at(b1, b2, self.material.as_ref())
     }
 }
 
 impl Hittable for Triangle {
     /// Calculate ray-triangle intersection using the Möller-Trumbore algorithm.
     /// Source: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload) -> bool {
+    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitPayload<'a>) -> bool {
+        rec.stats.triangle_tests += 1;
         let r_dir = r.direction();
         let r_orig = r.origin();
 
@@ -67,7 +175,7 @@ impl Hittable for Triangle {
         let f = 1.0 / a;
         let s = r_orig - self.v0;
         let u = f * s.dot(h);
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return false;
         }
 
@@ -89,12 +197,31 @@ impl Hittable for Triangle {
         rec.world_position = r_orig + t * r_dir;
         // rec.normal = self.normal;
         rec.set_face_normal(r, self.normal);
-        rec.albedo = self.albedo;
-        rec.emissive = self.emissive;
+        if let Some([n0, n1, n2]) = self.vertex_normals {
+            // Interpolate the vertex normals with the barycentric coordinates of the hit.
+            let normal = (1.0 - u - v) * n0 + u * n1 + v * n2;
+            rec.set_shading_normal(r, normal.normalize_or_zero());
+        }
+        rec.uv = self.interpolate_tex_coords(u, v);
+        rec.tangent = self.tangent;
+        rec.bitangent = self.bitangent;
+        rec.material = Some(self.material.as_ref());
+        rec.barycentrics = Some(vec2(u, v));
 
         true
     }
 
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(self.vertices())
+    }
+
+    fn emitters(&self) -> Vec<(usize, Arc<dyn AreaLight>)> {
+        if self.emission() == Color::ZERO {
+            return Vec::new();
+        }
+        vec![(0, Arc::new(self.clone()))]
+    }
+
     // Inside-outside intersection test.
     // fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
     //     // Check if the ray is parallel to the plane.
@@ -1,3 +1,4 @@
+use crate::onb::Onb;
 use glam::*;
 use rand::Rng;
 use std::f32::consts::PI;
@@ -6,7 +7,8 @@ const INV_PI: f32 = 1.0 / PI;
 
 /// Generates a random vector on the hemissphere about the z axis
 /// where z = (0.0, 0.0, 1.0).
-pub fn uniform_hemisphere_sample(rng: &mut impl Rng) -> Vec3A {
+#[allow(dead_code)]
+pub fn uniform_hemisphere_sample(rng: &mut (impl Rng + ?Sized)) -> Vec3A {
     // Draw two uniform random numbers in [0.0, 1.0)
     let x1: f32 = rng.gen_range(0.0..1.0);
     let x2: f32 = rng.gen_range(0.0..1.0);
@@ -20,7 +22,8 @@ pub fn uniform_hemisphere_sample(rng: &mut impl Rng) -> Vec3A {
 }
 
 /// Generates a random vector on the hemisphere in world space where n is the up direction.
-pub fn uniform_hemisphere_sample_world(rng: &mut impl Rng, n: Vec3A) -> Vec3A {
+#[allow(dead_code)]
+pub fn uniform_hemisphere_sample_world(rng: &mut (impl Rng + ?Sized), n: Vec3A) -> Vec3A {
     // Build an orthonormal basis from the surface normal.
     // Choose an arbitrary vector non-parallel to n.
     let a = if n.x.abs() > 0.9 {
@@ -41,7 +44,77 @@ pub fn uniform_hemisphere_sample_world(rng: &mut impl Rng, n: Vec3A) -> Vec3A {
     local_to_world * uniform_hemisphere_sample(rng)
 }
 
-pub fn random_in_unit_sphere(rng: &mut impl Rng) -> Vec3A {
+/// Maps a point in [0, 1)^2 to the unit disk, preserving relative areas.
+/// Uses Shirley's concentric mapping, which distorts the strata less than the polar one.
+pub fn concentric_disk_sample(u: Vec2) -> Vec2 {
+    // Map to [-1, 1]^2.
+    let offset = u * 2.0 - 1.0;
+    if offset.x == 0.0 && offset.y == 0.0 {
+        return Vec2::ZERO;
+    }
+
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, 0.25 * PI * (offset.y / offset.x))
+    } else {
+        (offset.y, 0.5 * PI - 0.25 * PI * (offset.x / offset.y))
+    };
+    r * vec2(theta.cos(), theta.sin())
+}
+
+/// Generates a cosine-distributed random vector on the hemisphere about the z axis.
+/// The density with respect to solid angle is `cos_theta / PI`.
+pub fn cosine_hemisphere_sample(rng: &mut (impl Rng + ?Sized)) -> Vec3A {
+    // Malley's method: project uniform samples on the disk up onto the hemisphere.
+    let d = concentric_disk_sample(vec2(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)));
+    let z = (1.0 - d.length_squared()).max(0.0).sqrt();
+    vec3a(d.x, d.y, z)
+}
+
+/// Generates a cosine-distributed random vector on the hemisphere in world space
+/// where n is the up direction.
+pub fn cosine_hemisphere_sample_world(rng: &mut (impl Rng + ?Sized), n: Vec3A) -> Vec3A {
+    Onb::from_w(n).local(cosine_hemisphere_sample(rng))
+}
+
+/// Solid angle density of `cosine_hemisphere_sample` given the cosine to the up direction.
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    if cos_theta <= 0.0 {
+        return 0.0;
+    }
+    cos_theta * INV_PI
+}
+
+/// Solid angle density of `uniform_hemisphere_sample`.
+#[allow(dead_code)]
+pub fn uniform_hemisphere_pdf() -> f32 {
+    0.5 * INV_PI
+}
+
+/// Relative luminance of a linear sRGB color.
+pub fn luminance(c: Vec3A) -> f32 {
+    c.dot(vec3a(0.2126, 0.7152, 0.0722))
+}
+
+/// Decode an sRGB encoded value in [0, 1] to linear.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear value in [0, 1] with the sRGB transfer function, for display.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[allow(dead_code)]
+pub fn random_in_unit_sphere(rng: &mut (impl Rng + ?Sized)) -> Vec3A {
     loop {
         let x: f32 = rng.gen_range(-1.0..1.0);
         let y: f32 = rng.gen_range(-1.0..1.0);
@@ -85,4 +158,85 @@ mod test {
             assert!(w.dot(n) >= 0.0); 
         }
     }
+
+    /// Bin samples by (cos_theta, phi) and compare the counts against the probabilities
+    /// predicted by the pdf with a chi-square test.
+    /// `cdf` gives the probability that cos_theta is below the given value.
+    fn chi_square(
+        sample: impl Fn(&mut rand_xoshiro::Xoshiro256PlusPlus) -> Vec3A,
+        cdf: impl Fn(f32) -> f32,
+    ) -> f32 {
+        const THETA_BINS: usize = 10;
+        const PHI_BINS: usize = 20;
+        const SAMPLES: usize = 1_000_000;
+
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(4);
+        let mut counts = [[0usize; PHI_BINS]; THETA_BINS];
+        for _ in 0..SAMPLES {
+            let w = sample(&mut rng);
+            let phi = w.y.atan2(w.x) + PI;
+            let i = ((w.z * THETA_BINS as f32) as usize).min(THETA_BINS - 1);
+            let j = ((phi / (2.0 * PI) * PHI_BINS as f32) as usize).min(PHI_BINS - 1);
+            counts[i][j] += 1;
+        }
+
+        let mut chi2 = 0.0;
+        for (i, row) in counts.iter().enumerate() {
+            let lo = i as f32 / THETA_BINS as f32;
+            let hi = (i + 1) as f32 / THETA_BINS as f32;
+            // Both distributions are isotropic in phi.
+            let expected = (cdf(hi) - cdf(lo)) / PHI_BINS as f32 * SAMPLES as f32;
+            for &count in row {
+                let diff = count as f32 - expected;
+                chi2 += diff * diff / expected;
+            }
+        }
+        chi2
+    }
+
+    // With 199 degrees of freedom, the 99.9th percentile of the chi-square distribution
+    // is roughly 267.
+    const CHI_SQUARE_THRESHOLD: f32 = 267.0;
+
+    #[test]
+    fn cosine_hemisphere_sample_distribution() {
+        // p(cos_theta) = 2 cos_theta, so the cdf is cos_theta^2.
+        let chi2 = chi_square(cosine_hemisphere_sample, |c| c * c);
+        assert!(chi2 < CHI_SQUARE_THRESHOLD, "chi2 = {}", chi2);
+
+        // The uniform cdf must be rejected, otherwise the test has no power.
+        let chi2 = chi_square(cosine_hemisphere_sample, |c| c);
+        assert!(chi2 > CHI_SQUARE_THRESHOLD, "chi2 = {}", chi2);
+    }
+
+    #[test]
+    fn uniform_hemisphere_sample_distribution() {
+        let chi2 = chi_square(uniform_hemisphere_sample, |c| c);
+        assert!(chi2 < CHI_SQUARE_THRESHOLD, "chi2 = {}", chi2);
+    }
+
+    #[test]
+    fn cosine_hemisphere_pdf_integrates_to_one() {
+        // Monte Carlo estimate of the integral over the hemisphere using uniform samples.
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(7);
+        let n = 1_000_000;
+        let sum: f32 = (0..n)
+            .map(|_| {
+                let w = uniform_hemisphere_sample(&mut rng);
+                cosine_hemisphere_pdf(w.z) / uniform_hemisphere_pdf()
+            })
+            .sum();
+        assert!((sum / n as f32 - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn cosine_hemisphere_sample_world_test() {
+        let mut rng = rand_xoshiro::Xoshiro256PlusPlus::seed_from_u64(1);
+        let n = vec3a(0.3, -0.8, 0.5).normalize();
+        for _ in 0..100_000 {
+            let w = cosine_hemisphere_sample_world(&mut rng, n);
+            assert!(w.is_normalized());
+            assert!(w.dot(n) >= 0.0);
+        }
+    }
 }